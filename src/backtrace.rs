@@ -0,0 +1,149 @@
+// {{{ Backtrace
+//! Backtrace value, needs Rust 1.65
+use slog::{Key, Record};
+
+/// Maximum number of frames emitted by `Backtrace`
+pub const BACKTRACE_MAX_FRAMES: usize = 64;
+
+/// Captured backtrace emitted as an array of frame descriptions
+///
+/// Emitted like `"stack": ["app::main at ./src/main.rs:3:5", ...]`,
+/// capped at `BACKTRACE_MAX_FRAMES` frames. A backtrace that was not
+/// captured (see `std::backtrace::Backtrace::capture`) is an empty array.
+///
+/// ```
+/// use slog::{error, o, Drain};
+/// use slog_json::{Backtrace, Json, SharedBuffer, BACKTRACE_MAX_FRAMES};
+///
+/// let buf = SharedBuffer::new();
+/// let drain = Json::new(buf.clone()).build();
+/// let log = slog::Logger::root(std::sync::Mutex::new(drain).fuse(), o!());
+/// let bt = std::backtrace::Backtrace::force_capture();
+/// error!(log, "failed"; "stack" => Backtrace(bt));
+///
+/// let line: serde_json::Value =
+///     serde_json::from_str(&buf.to_string_lossy()).unwrap();
+/// let stack = line["stack"].as_array().unwrap();
+/// assert!(!stack.is_empty() && stack.len() <= BACKTRACE_MAX_FRAMES);
+/// assert!(stack.iter().all(|frame| frame.is_string()));
+///
+/// let bt = std::backtrace::Backtrace::disabled();
+/// error!(log, "failed"; "stack" => Backtrace(bt));
+/// let line = buf.to_string_lossy();
+/// assert!(line.lines().last().unwrap().contains(r#""stack":[]"#));
+/// ```
+///
+/// Requires the `backtrace` feature, and Rust 1.65 or newer.
+pub struct Backtrace(pub std::backtrace::Backtrace);
+
+impl Backtrace {
+    /// Frame descriptions, from the `Display` output of the backtrace
+    fn frames(&self) -> Vec<String> {
+        let mut frames: Vec<String> = vec![];
+        for line in self.0.to_string().lines() {
+            let line = line.trim();
+            if let Some(location) = line.strip_prefix("at ") {
+                if let Some(frame) = frames.last_mut() {
+                    frame.push_str(" at ");
+                    frame.push_str(location);
+                }
+                continue;
+            }
+            let symbol = line.split_once(": ").and_then(|(n, symbol)| {
+                if n.chars().all(|c| c.is_ascii_digit()) {
+                    Some(symbol)
+                } else {
+                    None
+                }
+            });
+            if let Some(symbol) = symbol {
+                if frames.len() == BACKTRACE_MAX_FRAMES {
+                    break;
+                }
+                frames.push(symbol.to_owned());
+            }
+        }
+        frames
+    }
+}
+
+impl serde::Serialize for Backtrace {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.frames().serialize(serializer)
+    }
+}
+
+impl slog::Value for Backtrace {
+    fn serialize(
+        &self,
+        _record: &Record,
+        key: Key,
+        serializer: &mut dyn slog::Serializer,
+    ) -> slog::Result {
+        serializer.emit_serde(key, self)
+    }
+}
+
+impl slog::SerdeValue for Backtrace {
+    fn serialize_fallback(
+        &self,
+        key: Key,
+        serializer: &mut dyn slog::Serializer,
+    ) -> slog::Result {
+        Frames(self.frames()).serialize_fallback(key, serializer)
+    }
+
+    fn as_serde(&self) -> &dyn erased_serde::Serialize {
+        self
+    }
+
+    fn to_sendable(&self) -> Box<dyn slog::SerdeValue + Send + 'static> {
+        Box::new(Frames(self.frames()))
+    }
+}
+
+/// Frames of a `Backtrace`, resolved so they can be sent across threads
+struct Frames(Vec<String>);
+
+impl serde::Serialize for Frames {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.0.serialize(serializer)
+    }
+}
+
+impl slog::Value for Frames {
+    fn serialize(
+        &self,
+        _record: &Record,
+        key: Key,
+        serializer: &mut dyn slog::Serializer,
+    ) -> slog::Result {
+        serializer.emit_serde(key, self)
+    }
+}
+
+impl slog::SerdeValue for Frames {
+    fn serialize_fallback(
+        &self,
+        key: Key,
+        serializer: &mut dyn slog::Serializer,
+    ) -> slog::Result {
+        serializer.emit_str(key, &self.0.join("\n"))
+    }
+
+    fn as_serde(&self) -> &dyn erased_serde::Serialize {
+        self
+    }
+
+    fn to_sendable(&self) -> Box<dyn slog::SerdeValue + Send + 'static> {
+        Box::new(Frames(self.0.clone()))
+    }
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
@@ -0,0 +1,205 @@
+// {{{ Canonical JSON
+//! RFC 8785 canonical JSON, see `JsonBuilder::canonical`
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use serde_json::ser::Formatter;
+use serde_json::Value;
+use std::io;
+
+/// Largest integer all smaller ones of which are exact as `f64`s
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// Write `kvs` as a canonical JSON object
+///
+/// A key logged more than once is written once, with the value logged
+/// last, as canonical JSON has no duplicate keys.
+pub(crate) fn write_object<W>(
+    io: &mut W,
+    kvs: &[(String, Value)],
+) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let mut kvs: Vec<_> = kvs.iter().rev().collect();
+    // Stable, so the value logged last stays first and is kept by `dedup`
+    kvs.sort_by(|(a, _), (b, _)| utf16_cmp(a, b));
+    kvs.dedup_by(|(a, _), (b, _)| a == b);
+    let mut serializer =
+        serde_json::Serializer::with_formatter(io, CanonicalFormatter);
+    let mut map = serializer.serialize_map(Some(kvs.len()))?;
+    for (k, v) in kvs {
+        map.serialize_entry(k, &Sorted(v))?;
+    }
+    SerializeMap::end(map)?;
+    Ok(())
+}
+
+/// Order of keys in canonical JSON: by their UTF-16 code units
+fn utf16_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    a.encode_utf16().cmp(b.encode_utf16())
+}
+
+/// Value serialized with the keys of its objects sorted
+struct Sorted<'a>(&'a Value);
+
+impl Serialize for Sorted<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match *self.0 {
+            Value::Object(ref object) => {
+                let mut entries: Vec<_> = object.iter().collect();
+                entries.sort_by(|(a, _), (b, _)| utf16_cmp(a, b));
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (k, v) in entries {
+                    map.serialize_entry(k, &Sorted(v))?;
+                }
+                map.end()
+            }
+            Value::Array(ref array) => {
+                let mut seq = serializer.serialize_seq(Some(array.len()))?;
+                for v in array {
+                    seq.serialize_element(&Sorted(v))?;
+                }
+                seq.end()
+            }
+            ref value => value.serialize(serializer),
+        }
+    }
+}
+
+/// Compact formatter writing numbers as ECMAScript does, like RFC 8785
+///
+/// Numbers are IEEE 754 doubles there: integers beyond 2^53 are written as
+/// the nearest double, and so may change.
+struct CanonicalFormatter;
+
+impl CanonicalFormatter {
+    fn write_int<W>(
+        &mut self,
+        writer: &mut W,
+        magnitude: u128,
+        negative: bool,
+    ) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        let sign = if negative && magnitude != 0 { "-" } else { "" };
+        if magnitude <= u128::from(MAX_SAFE_INTEGER) {
+            write!(writer, "{}{}", sign, magnitude)
+        } else {
+            let value = magnitude as f64;
+            self.write_f64(writer, if negative { -value } else { value })
+        }
+    }
+}
+
+impl Formatter for CanonicalFormatter {
+    fn write_i8<W>(&mut self, writer: &mut W, value: i8) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.write_i64(writer, value.into())
+    }
+    fn write_i16<W>(&mut self, writer: &mut W, value: i16) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.write_i64(writer, value.into())
+    }
+    fn write_i32<W>(&mut self, writer: &mut W, value: i32) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.write_i64(writer, value.into())
+    }
+    fn write_i64<W>(&mut self, writer: &mut W, value: i64) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.write_int(writer, u128::from(value.unsigned_abs()), value < 0)
+    }
+    fn write_i128<W>(&mut self, writer: &mut W, value: i128) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.write_int(writer, value.unsigned_abs(), value < 0)
+    }
+    fn write_u8<W>(&mut self, writer: &mut W, value: u8) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.write_u64(writer, value.into())
+    }
+    fn write_u16<W>(&mut self, writer: &mut W, value: u16) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.write_u64(writer, value.into())
+    }
+    fn write_u32<W>(&mut self, writer: &mut W, value: u32) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.write_u64(writer, value.into())
+    }
+    fn write_u64<W>(&mut self, writer: &mut W, value: u64) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.write_int(writer, value.into(), false)
+    }
+    fn write_u128<W>(&mut self, writer: &mut W, value: u128) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.write_int(writer, value, false)
+    }
+    fn write_f32<W>(&mut self, writer: &mut W, value: f32) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.write_f64(writer, value.into())
+    }
+    fn write_f64<W>(&mut self, writer: &mut W, value: f64) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        writer.write_all(ecmascript_number(value).as_bytes())
+    }
+}
+
+/// `value` as formatted by ECMAScript's `Number.prototype.toString`
+///
+/// `value` is finite, as `serde_json` writes other numbers as `null`.
+fn ecmascript_number(value: f64) -> String {
+    if value == 0.0 {
+        return "0".into();
+    }
+    let sign = if value < 0.0 { "-" } else { "" };
+    // Shortest digits that round-trip, as `d.ddde<exp>`
+    let exp_form = format!("{:e}", value.abs());
+    let (mantissa, exp) = exp_form.split_at(exp_form.find('e').unwrap_or(0));
+    let digits: String = mantissa.chars().filter(|&c| c != '.').collect();
+    let exp: i32 = exp[1..].parse().unwrap_or(0);
+
+    // The value is 0.<digits> times 10^n
+    let k = digits.len() as i32;
+    let n = exp + 1;
+    let body = if k <= n && n <= 21 {
+        format!("{}{}", digits, "0".repeat((n - k) as usize))
+    } else if 0 < n && n <= 21 {
+        let (int, frac) = digits.split_at(n as usize);
+        format!("{}.{}", int, frac)
+    } else if -6 < n && n <= 0 {
+        format!("0.{}{}", "0".repeat(-n as usize), digits)
+    } else {
+        let (first, rest) = digits.split_at(1);
+        let dot = if rest.is_empty() { "" } else { "." };
+        let sign = if n - 1 < 0 { "-" } else { "+" };
+        format!("{}{}{}e{}{}", first, dot, rest, sign, (n - 1).abs())
+    };
+    format!("{}{}", sign, body)
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
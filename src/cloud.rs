@@ -0,0 +1,280 @@
+// {{{ Cloud metadata
+//! Placement of the instance, from the metadata service of its provider
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+/// Address of the metadata service, the same for all supported providers
+const METADATA_ADDR: ([u8; 4], u16) = ([169, 254, 169, 254], 80);
+
+/// Time allowed for all the requests to the metadata service
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Largest response read from the metadata service
+const MAX_RESPONSE: u64 = 64 * 1024;
+
+/// Cloud provider of the instance, see `JsonBuilder::add_cloud_metadata`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CloudProvider {
+    /// Amazon EC2, through the instance metadata service (IMDSv2)
+    Aws,
+    /// Google Compute Engine
+    Gcp,
+    /// Azure Instance Metadata Service
+    Azure,
+}
+
+impl CloudProvider {
+    /// Region and availability zone of the instance, each `None` if it
+    /// cannot be found
+    pub(crate) fn placement(self) -> (Option<String>, Option<String>) {
+        self.placement_from(SocketAddr::from(METADATA_ADDR))
+    }
+
+    /// `placement`, from the metadata service at `addr`
+    fn placement_from(
+        self,
+        addr: SocketAddr,
+    ) -> (Option<String>, Option<String>) {
+        let client = Client {
+            addr,
+            deadline: Instant::now() + TIMEOUT,
+        };
+        match self {
+            CloudProvider::Aws => {
+                let token = client
+                    .request(
+                        "PUT",
+                        "/latest/api/token",
+                        &[("X-aws-ec2-metadata-token-ttl-seconds", "60")],
+                    )
+                    .ok();
+                let headers: Vec<_> = token
+                    .iter()
+                    .map(|token| ("X-aws-ec2-metadata-token", token.as_str()))
+                    .collect();
+                let get = |path| client.request("GET", path, &headers).ok();
+                (
+                    get("/latest/meta-data/placement/region"),
+                    get("/latest/meta-data/placement/availability-zone"),
+                )
+            }
+            CloudProvider::Gcp => {
+                // Like `projects/123456789/zones/us-central1-a`
+                let zone = client
+                    .request(
+                        "GET",
+                        "/computeMetadata/v1/instance/zone",
+                        &[("Metadata-Flavor", "Google")],
+                    )
+                    .ok()
+                    .and_then(|zone| Some(zone.rsplit('/').next()?.to_owned()));
+                // Zones are named after their region
+                let region = zone
+                    .as_ref()
+                    .and_then(|zone| Some(zone[..zone.rfind('-')?].to_owned()));
+                (region, zone)
+            }
+            CloudProvider::Azure => {
+                let get = |path| {
+                    client
+                        .request("GET", path, &[("Metadata", "true")])
+                        .ok()
+                        // Instances outside of availability zones have none
+                        .filter(|value| !value.is_empty())
+                };
+                (
+                    get("/metadata/instance/compute/location?api-version=2021-02-01&format=text"),
+                    get("/metadata/instance/compute/zone?api-version=2021-02-01&format=text"),
+                )
+            }
+        }
+    }
+}
+
+/// Minimal HTTP/1.0 client of the metadata service
+struct Client {
+    addr: SocketAddr,
+    /// Time after which no more requests are made
+    deadline: Instant,
+}
+
+impl Client {
+    /// Body of the successful response to a request, trimmed
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+    ) -> io::Result<String> {
+        let timeout = self
+            .deadline
+            .checked_duration_since(Instant::now())
+            .filter(|timeout| *timeout > Duration::ZERO)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "metadata service timed out",
+                )
+            })?;
+        let mut stream = TcpStream::connect_timeout(&self.addr, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+
+        let mut req = format!(
+            "{} {} HTTP/1.0\r\nHost: {}\r\n",
+            method,
+            path,
+            self.addr.ip()
+        );
+        for (name, value) in headers {
+            let _ = write!(req, "{}: {}\r\n", name, value);
+        }
+        req.push_str("Content-Length: 0\r\n\r\n");
+        stream.write_all(req.as_bytes())?;
+
+        let mut res = vec![];
+        stream.take(MAX_RESPONSE).read_to_end(&mut res)?;
+        let res = String::from_utf8(res)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        match res.split_once("\r\n\r\n") {
+            Some((head, body)) if head.split(' ').nth(1) == Some("200") => {
+                Ok(body.trim().to_owned())
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "metadata service request failed",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Metadata service answering `responses` by path, as status and body,
+    /// returning the requests it got once it is done
+    fn serve(
+        responses: &'static [(&'static str, u16, &'static str)],
+        requests: usize,
+    ) -> (SocketAddr, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut got = vec![];
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut head = String::new();
+                let mut reader = BufReader::new(&mut stream);
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" || line.is_empty() {
+                        break;
+                    }
+                    head.push_str(&line);
+                }
+                let path = head.split(' ').nth(1).unwrap_or_default();
+                let (status, body) = responses
+                    .iter()
+                    .find(|(p, _, _)| *p == path)
+                    .map_or((404, ""), |&(_, status, body)| (status, body));
+                write!(
+                    stream,
+                    "HTTP/1.0 {} X\r\nContent-Length: {}\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+                got.push(head);
+            }
+            got
+        });
+        (addr, server)
+    }
+
+    #[test]
+    fn aws_placement_with_token() {
+        let (addr, server) = serve(
+            &[
+                ("/latest/api/token", 200, "secret\n"),
+                ("/latest/meta-data/placement/region", 200, "eu-west-1"),
+                (
+                    "/latest/meta-data/placement/availability-zone",
+                    200,
+                    "eu-west-1b",
+                ),
+            ],
+            3,
+        );
+        assert_eq!(
+            CloudProvider::Aws.placement_from(addr),
+            (Some("eu-west-1".into()), Some("eu-west-1b".into()))
+        );
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("PUT /latest/api/token "));
+        assert!(requests[1].contains("X-aws-ec2-metadata-token: secret\r\n"));
+        assert!(requests[2].contains("X-aws-ec2-metadata-token: secret\r\n"));
+    }
+
+    #[test]
+    fn gcp_region_from_zone() {
+        let (addr, server) = serve(
+            &[(
+                "/computeMetadata/v1/instance/zone",
+                200,
+                "projects/123456789/zones/us-central1-a",
+            )],
+            1,
+        );
+        assert_eq!(
+            CloudProvider::Gcp.placement_from(addr),
+            (Some("us-central1".into()), Some("us-central1-a".into()))
+        );
+        let requests = server.join().unwrap();
+        assert!(requests[0].contains("Metadata-Flavor: Google\r\n"));
+    }
+
+    #[test]
+    fn azure_without_zone() {
+        let (addr, server) = serve(
+            &[
+                (
+                    "/metadata/instance/compute/location?api-version=2021-02-01&format=text",
+                    200,
+                    "westeurope",
+                ),
+                (
+                    "/metadata/instance/compute/zone?api-version=2021-02-01&format=text",
+                    200,
+                    "",
+                ),
+            ],
+            2,
+        );
+        assert_eq!(
+            CloudProvider::Azure.placement_from(addr),
+            (Some("westeurope".into()), None)
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn failed_requests_are_none() {
+        let (addr, server) = serve(&[], 2);
+        assert_eq!(CloudProvider::Azure.placement_from(addr), (None, None));
+        server.join().unwrap();
+
+        // Nothing listening any more
+        assert_eq!(CloudProvider::Aws.placement_from(addr), (None, None));
+    }
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
@@ -0,0 +1,207 @@
+// {{{ KvCollector
+//! Collecting key-values before emitting them
+use crate::{SerdeOptions, Traceparent, FIELD_ERROR};
+use slog::Key;
+use std::fmt::{self, Write};
+
+/// `slog::Serializer` collecting key-values as `serde_json::Value`s
+///
+/// Used where the key-values of a record have to be looked at as a whole
+/// before any is emitted. Keys are kept as given; values are converted
+/// like `SerdeSerializer` does.
+pub(crate) struct KvCollector<'a> {
+    opts: &'a SerdeOptions,
+    kvs: Vec<(String, serde_json::Value)>,
+}
+
+macro_rules! collect(
+    ($s:expr, $key:expr, $val:expr) => ({
+        let key: &str = $key.as_ref();
+        $s.push(key, $val)
+    });
+);
+
+macro_rules! collect_num(
+    ($s:expr, $key:expr, $val:expr) => ({
+        if $s.opts.numeric($key.as_ref()) {
+            collect!($s, $key, &$val)
+        } else {
+            collect!($s, $key, &$val.to_string())
+        }
+    });
+);
+
+impl<'a> KvCollector<'a> {
+    pub(crate) fn new(opts: &'a SerdeOptions) -> Self {
+        KvCollector { opts, kvs: vec![] }
+    }
+
+    fn push<V>(&mut self, key: &str, val: &V) -> slog::Result
+    where
+        V: serde::Serialize + ?Sized,
+    {
+        if !self.opts.emitted(key) {
+            return Ok(());
+        }
+        let val = match serde_json::to_value(val) {
+            Ok(val) => val,
+            Err(_) if self.opts.best_effort => FIELD_ERROR.into(),
+            Err(e) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!("serde serialization error: {}", e),
+                )
+                .into())
+            }
+        };
+        self.kvs.push((key.to_owned(), val));
+        Ok(())
+    }
+
+    fn collect_traceparent(&mut self, tp: &Traceparent<'_>) -> slog::Result {
+        self.push("trace_id", tp.trace_id)?;
+        self.push("span_id", tp.span_id)?;
+        self.push("trace_flags", tp.trace_flags)
+    }
+
+    /// Collected key-values, in the order they were serialized
+    pub(crate) fn into_kvs(self) -> Vec<(String, serde_json::Value)> {
+        self.kvs
+    }
+}
+
+impl<'a> slog::Serializer for KvCollector<'a> {
+    fn emit_bool(&mut self, key: Key, val: bool) -> slog::Result {
+        let as_int = self.opts.bool_as_int(key.as_ref());
+        match (as_int, self.opts.all_strings) {
+            (true, false) => collect!(self, key, &u8::from(val)),
+            (true, true) => collect!(self, key, &u8::from(val).to_string()),
+            (false, false) => collect!(self, key, &val),
+            (false, true) => collect!(self, key, &val.to_string()),
+        }
+    }
+    fn emit_unit(&mut self, key: Key) -> slog::Result {
+        collect!(self, key, &())
+    }
+    fn emit_char(&mut self, key: Key, val: char) -> slog::Result {
+        collect!(self, key, &val)
+    }
+    fn emit_none(&mut self, key: Key) -> slog::Result {
+        let val: Option<()> = None;
+        collect!(self, key, &val)
+    }
+    fn emit_u8(&mut self, key: Key, val: u8) -> slog::Result {
+        collect_num!(self, key, val)
+    }
+    fn emit_i8(&mut self, key: Key, val: i8) -> slog::Result {
+        collect_num!(self, key, val)
+    }
+    fn emit_u16(&mut self, key: Key, val: u16) -> slog::Result {
+        collect_num!(self, key, val)
+    }
+    fn emit_i16(&mut self, key: Key, val: i16) -> slog::Result {
+        collect_num!(self, key, val)
+    }
+    fn emit_usize(&mut self, key: Key, val: usize) -> slog::Result {
+        collect_num!(self, key, val)
+    }
+    fn emit_isize(&mut self, key: Key, val: isize) -> slog::Result {
+        collect_num!(self, key, val)
+    }
+    fn emit_u32(&mut self, key: Key, val: u32) -> slog::Result {
+        collect_num!(self, key, val)
+    }
+    fn emit_i32(&mut self, key: Key, val: i32) -> slog::Result {
+        collect_num!(self, key, val)
+    }
+    fn emit_f32(&mut self, key: Key, val: f32) -> slog::Result {
+        collect_num!(self, key, val)
+    }
+    fn emit_u64(&mut self, key: Key, val: u64) -> slog::Result {
+        collect_num!(self, key, val)
+    }
+    fn emit_i64(&mut self, key: Key, val: i64) -> slog::Result {
+        collect_num!(self, key, val)
+    }
+    fn emit_f64(&mut self, key: Key, val: f64) -> slog::Result {
+        collect_num!(self, key, val)
+    }
+    fn emit_u128(&mut self, key: Key, val: u128) -> slog::Result {
+        collect_num!(self, key, val)
+    }
+    fn emit_i128(&mut self, key: Key, val: i128) -> slog::Result {
+        collect_num!(self, key, val)
+    }
+    fn emit_str(&mut self, key: Key, val: &str) -> slog::Result {
+        let val = self.opts.strip_controls(val);
+        if let Some(tp) = self.opts.traceparent(key.as_ref(), &val) {
+            return self.collect_traceparent(&tp);
+        }
+        if val.is_empty() && self.opts.empty_string_as_null {
+            return self.emit_none(key);
+        }
+        if let Some(n) = self.opts.number_string(key.as_ref(), &val) {
+            return collect!(self, key, &n);
+        }
+        collect!(self, key, &*val)
+    }
+    fn emit_arguments(
+        &mut self,
+        key: Key,
+        val: &fmt::Arguments,
+    ) -> slog::Result {
+        let mut buf = String::new();
+        buf.write_fmt(*val).unwrap();
+        self.emit_str(key, &buf)
+    }
+
+    #[cfg(feature = "nested-values")]
+    fn emit_serde(
+        &mut self,
+        key: Key,
+        value: &dyn slog::SerdeValue,
+    ) -> slog::Result {
+        match self.opts.transform_nested(value)? {
+            Some(value) => collect!(self, key, &value),
+            None => collect!(self, key, value.as_serde()),
+        }
+    }
+}
+
+/// Members of a serialized JSON object, in order and with duplicates
+///
+/// `serde_json::Map` would sort them, unless `serde_json/preserve_order`
+/// is enabled.
+pub(crate) struct KvPairs(pub(crate) Vec<(String, serde_json::Value)>);
+
+impl<'de> serde::Deserialize<'de> for KvPairs {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = KvPairs;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a JSON object")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<KvPairs, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                let mut kvs = Vec::with_capacity(map.size_hint().unwrap_or(16));
+                while let Some(kv) = map.next_entry()? {
+                    kvs.push(kv);
+                }
+                Ok(KvPairs(kvs))
+            }
+        }
+
+        deserializer.deserialize_map(Visitor)
+    }
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
@@ -0,0 +1,444 @@
+// {{{ Compression
+//! Compressed output, see `JsonBuilder::with_compression`
+use std::io;
+
+/// Codec of `JsonBuilder::with_compression`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Compression {
+    /// Write the output as is
+    None,
+    /// gzip (RFC 1952), as read by `gunzip` or `zcat`
+    #[cfg(feature = "gzip")]
+    Gzip,
+    /// Zstandard (RFC 8878), as read by `zstd -d` or `zstdcat`
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+/// `io::Write` compressing everything written to it into another one
+///
+/// Created by `JsonBuilder::with_compression`. What was written is sent
+/// compressed on `flush`, so that it can be decompressed up to there:
+/// with `JsonBuilder::set_flush(true)`, the output is readable record by
+/// record while being written. The compressed stream is ended by `finish`,
+/// or else when the writer is dropped.
+pub struct CompressedWriter<W: io::Write> {
+    /// `None` once finished
+    io: Option<W>,
+    codec: Codec,
+}
+
+enum Codec {
+    None,
+    #[cfg(feature = "gzip")]
+    Gzip(Box<gzip::Encoder>),
+    /// Encoder writing into a buffer, emptied into the writer
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl<W> CompressedWriter<W>
+where
+    W: io::Write,
+{
+    pub(crate) fn new(io: W, codec: Compression) -> Self {
+        let codec = match codec {
+            Compression::None => Codec::None,
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Codec::Gzip(Box::new(gzip::Encoder::new())),
+            #[cfg(feature = "zstd")]
+            // Only fails to allocate its context
+            Compression::Zstd => Codec::Zstd(
+                zstd::stream::write::Encoder::new(vec![], 0)
+                    .expect("zstd encoder"),
+            ),
+        };
+        CompressedWriter {
+            io: Some(io),
+            codec,
+        }
+    }
+
+    /// End the compressed stream, returning the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.end()?;
+        Ok(self.io.take().expect("writer finished twice"))
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        let io = match self.io.as_mut() {
+            Some(io) => io,
+            None => return Ok(()),
+        };
+        match self.codec {
+            Codec::None => {}
+            #[cfg(feature = "gzip")]
+            Codec::Gzip(ref mut encoder) => {
+                io.write_all(&encoder.finish())?;
+            }
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(ref mut encoder) => {
+                encoder.do_finish()?;
+                io.write_all(&std::mem::take(encoder.get_mut()))?;
+            }
+        }
+        io.flush()
+    }
+
+    fn io(&mut self) -> &mut W {
+        self.io.as_mut().expect("writer used once finished")
+    }
+}
+
+impl<W> io::Write for CompressedWriter<W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.codec {
+            Codec::None => self.io().write(buf),
+            #[cfg(feature = "gzip")]
+            Codec::Gzip(ref mut encoder) => {
+                if let Some(out) = encoder.write(buf) {
+                    self.io
+                        .as_mut()
+                        .expect("writer used once finished")
+                        .write_all(&out)?;
+                }
+                Ok(buf.len())
+            }
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(ref mut encoder) => {
+                let n = encoder.write(buf)?;
+                let out = std::mem::take(encoder.get_mut());
+                self.io
+                    .as_mut()
+                    .expect("writer used once finished")
+                    .write_all(&out)?;
+                Ok(n)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.codec {
+            Codec::None => {}
+            #[cfg(feature = "gzip")]
+            Codec::Gzip(ref mut encoder) => {
+                let out = encoder.flush();
+                self.io
+                    .as_mut()
+                    .expect("writer used once finished")
+                    .write_all(&out)?;
+            }
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(ref mut encoder) => {
+                encoder.flush()?;
+                let out = std::mem::take(encoder.get_mut());
+                self.io
+                    .as_mut()
+                    .expect("writer used once finished")
+                    .write_all(&out)?;
+            }
+        }
+        self.io().flush()
+    }
+}
+
+impl<W> Drop for CompressedWriter<W>
+where
+    W: io::Write,
+{
+    fn drop(&mut self) {
+        let _ = self.end();
+    }
+}
+
+#[cfg(feature = "gzip")]
+mod gzip {
+    //! gzip encoder, deflating with fixed Huffman codes
+
+    /// Input buffered before being compressed as a block, at most
+    const BLOCK_SIZE: usize = 64 * 1024;
+    /// Distance back matches can be found at
+    const WINDOW_SIZE: usize = 32 * 1024;
+    const MIN_MATCH: usize = 3;
+    const MAX_MATCH: usize = 258;
+    /// Candidates tried for every match
+    const MAX_CHAIN: usize = 32;
+    const HASH_BITS: u32 = 15;
+
+    const LENGTH_BASE: [u16; 29] = [
+        3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51,
+        59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+    ];
+    const LENGTH_EXTRA: [u8; 29] = [
+        0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4,
+        5, 5, 5, 5, 0,
+    ];
+    const DIST_BASE: [u16; 30] = [
+        1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385,
+        513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385,
+        24577,
+    ];
+    const DIST_EXTRA: [u8; 30] = [
+        0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10,
+        10, 11, 11, 12, 12, 13, 13,
+    ];
+
+    const CRC_TABLE: [u32; 256] = crc_table();
+
+    const fn crc_table() -> [u32; 256] {
+        let mut table = [0; 256];
+        let mut n = 0;
+        while n < 256 {
+            let mut c = n as u32;
+            let mut k = 0;
+            while k < 8 {
+                c = if c & 1 != 0 {
+                    0xedb8_8320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+                k += 1;
+            }
+            table[n] = c;
+            n += 1;
+        }
+        table
+    }
+
+    /// Encoder of a gzip member, producing its bytes piecewise
+    pub(super) struct Encoder {
+        /// Input not compressed yet
+        pending: Vec<u8>,
+        /// Last input compressed, where matches can be found
+        history: Vec<u8>,
+        bits: BitWriter,
+        crc: u32,
+        /// Size of the input, modulo 2^32
+        size: u32,
+    }
+
+    impl Encoder {
+        pub(super) fn new() -> Self {
+            let mut bits = BitWriter::default();
+            // No file name nor modification time, unknown OS
+            bits.out
+                .extend_from_slice(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff]);
+            Encoder {
+                pending: vec![],
+                history: vec![],
+                bits,
+                crc: !0,
+                size: 0,
+            }
+        }
+
+        /// Take input, returning output once there is enough input for a
+        /// block
+        pub(super) fn write(&mut self, buf: &[u8]) -> Option<Vec<u8>> {
+            self.crc = buf.iter().fold(self.crc, |crc, &b| {
+                CRC_TABLE[((crc ^ u32::from(b)) & 0xff) as usize] ^ (crc >> 8)
+            });
+            self.size = self.size.wrapping_add(buf.len() as u32);
+            self.pending.extend_from_slice(buf);
+            if self.pending.len() < BLOCK_SIZE {
+                return None;
+            }
+            self.compress_block(false);
+            Some(std::mem::take(&mut self.bits.out))
+        }
+
+        /// Output of all the input so far, ending on a byte boundary
+        pub(super) fn flush(&mut self) -> Vec<u8> {
+            if !self.pending.is_empty() {
+                self.compress_block(false);
+            }
+            if self.bits.nbits > 0 {
+                // Empty stored block, as a marker to align to
+                self.bits.put(0, 3);
+                self.bits.align();
+                self.bits.out.extend_from_slice(&[0, 0, 0xff, 0xff]);
+            }
+            std::mem::take(&mut self.bits.out)
+        }
+
+        /// Rest of the output, ending the member
+        pub(super) fn finish(&mut self) -> Vec<u8> {
+            self.compress_block(true);
+            self.bits.align();
+            let crc = !self.crc;
+            self.bits.out.extend_from_slice(&crc.to_le_bytes());
+            self.bits.out.extend_from_slice(&self.size.to_le_bytes());
+            std::mem::take(&mut self.bits.out)
+        }
+
+        /// Compress the pending input as a block with fixed Huffman codes
+        fn compress_block(&mut self, last: bool) {
+            let bits = &mut self.bits;
+            bits.put(u32::from(last), 1);
+            bits.put(1, 2);
+
+            let start = self.history.len();
+            let mut data = std::mem::take(&mut self.history);
+            data.append(&mut self.pending);
+            let mut matcher = Matcher::new(data.len());
+            for pos in 0..start {
+                matcher.insert(&data, pos);
+            }
+
+            let mut pos = start;
+            while pos < data.len() {
+                match matcher.find(&data, pos) {
+                    Some((len, dist)) => {
+                        put_match(bits, len, dist);
+                        for p in pos..pos + len {
+                            matcher.insert(&data, p);
+                        }
+                        pos += len;
+                    }
+                    None => {
+                        put_symbol(bits, u16::from(data[pos]));
+                        matcher.insert(&data, pos);
+                        pos += 1;
+                    }
+                }
+            }
+            put_symbol(bits, 256);
+
+            let stale = data.len().saturating_sub(WINDOW_SIZE);
+            data.drain(..stale);
+            self.history = data;
+        }
+    }
+
+    /// Chains of the earlier positions of every 3 bytes hash
+    struct Matcher {
+        head: Vec<usize>,
+        prev: Vec<usize>,
+    }
+
+    impl Matcher {
+        fn new(len: usize) -> Self {
+            Matcher {
+                head: vec![usize::MAX; 1 << HASH_BITS],
+                prev: vec![usize::MAX; len],
+            }
+        }
+
+        fn hash(data: &[u8], pos: usize) -> usize {
+            let h = (u32::from(data[pos]) << 10)
+                ^ (u32::from(data[pos + 1]) << 5)
+                ^ u32::from(data[pos + 2]);
+            (h & ((1 << HASH_BITS) - 1)) as usize
+        }
+
+        fn insert(&mut self, data: &[u8], pos: usize) {
+            if pos + MIN_MATCH > data.len() {
+                return;
+            }
+            let h = Self::hash(data, pos);
+            self.prev[pos] = self.head[h];
+            self.head[h] = pos;
+        }
+
+        /// Length and distance of the longest earlier match at `pos`
+        fn find(&self, data: &[u8], pos: usize) -> Option<(usize, usize)> {
+            if pos + MIN_MATCH > data.len() {
+                return None;
+            }
+            let max_len = std::cmp::min(MAX_MATCH, data.len() - pos);
+            let mut best = (0, 0);
+            let mut candidate = self.head[Self::hash(data, pos)];
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || pos - candidate > WINDOW_SIZE {
+                    break;
+                }
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[pos..pos + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best.0 {
+                    best = (len, pos - candidate);
+                    if len == max_len {
+                        break;
+                    }
+                }
+                candidate = self.prev[candidate];
+            }
+            if best.0 >= MIN_MATCH {
+                Some(best)
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Write a literal, end of block or length symbol
+    fn put_symbol(bits: &mut BitWriter, sym: u16) {
+        let (code, len) = match sym {
+            0..=143 => (0x30 + sym, 8),
+            144..=255 => (0x190 + sym - 144, 9),
+            256..=279 => (sym - 256, 7),
+            _ => (0xc0 + sym - 280, 8),
+        };
+        bits.put_code(code, len);
+    }
+
+    fn put_match(bits: &mut BitWriter, len: usize, dist: usize) {
+        let i = LENGTH_BASE.iter().rposition(|&b| usize::from(b) <= len);
+        let i = i.expect("match too short");
+        put_symbol(bits, 257 + i as u16);
+        bits.put(
+            (len - usize::from(LENGTH_BASE[i])) as u32,
+            LENGTH_EXTRA[i].into(),
+        );
+
+        let i = DIST_BASE.iter().rposition(|&b| usize::from(b) <= dist);
+        let i = i.expect("distance of 0");
+        bits.put_code(i as u16, 5);
+        bits.put(
+            (dist - usize::from(DIST_BASE[i])) as u32,
+            DIST_EXTRA[i].into(),
+        );
+    }
+
+    /// Bits packed least significant first
+    #[derive(Default)]
+    struct BitWriter {
+        out: Vec<u8>,
+        bits: u64,
+        nbits: u32,
+    }
+
+    impl BitWriter {
+        fn put(&mut self, value: u32, n: u32) {
+            self.bits |= u64::from(value) << self.nbits;
+            self.nbits += n;
+            while self.nbits >= 8 {
+                self.out.push(self.bits as u8);
+                self.bits >>= 8;
+                self.nbits -= 8;
+            }
+        }
+
+        /// Put a Huffman code, which is packed most significant bit first
+        fn put_code(&mut self, code: u16, len: u32) {
+            let reversed = code.reverse_bits() >> (16 - len);
+            self.put(u32::from(reversed), len);
+        }
+
+        fn align(&mut self) {
+            if self.nbits > 0 {
+                self.out.push(self.bits as u8);
+                self.bits = 0;
+                self.nbits = 0;
+            }
+        }
+    }
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
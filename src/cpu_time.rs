@@ -0,0 +1,35 @@
+// {{{ CPU time
+//! CPU time of the current thread, from `clock_gettime`
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos"
+))]
+use libc::{clock_gettime, timespec, CLOCK_THREAD_CPUTIME_ID};
+
+/// CPU time consumed by the current thread so far, in milliseconds
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+pub(crate) fn thread_cpu_ms() -> Option<u64> {
+    let mut ts = timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // Safety: `ts` is a valid `struct timespec` to write to
+    let res = unsafe { clock_gettime(CLOCK_THREAD_CPUTIME_ID, &mut ts) };
+    if res != 0 {
+        return None;
+    }
+    Some(ts.tv_sec as u64 * 1000 + ts.tv_nsec as u64 / 1_000_000)
+}
+
+/// CPU time consumed by the current thread so far, in milliseconds
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos"
+)))]
+pub(crate) fn thread_cpu_ms() -> Option<u64> {
+    None
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
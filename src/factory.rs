@@ -0,0 +1,105 @@
+// {{{ Serializer factory
+//! Output in any serde format, see `JsonBuilder::build_with_factory`
+use crate::{Json, KvMap};
+use serde_json::Value;
+use slog::{OwnedKVList, Record};
+use std::io;
+
+/// Serde format records are written in by a `SerdeDrain`
+///
+/// Each record is handed over as a map of its key-values, to be written to
+/// `io` by a serializer of the format, eg.:
+///
+/// ```
+/// use serde_json::ser::PrettyFormatter;
+/// use slog_json::SerializerFactory;
+/// use std::io;
+///
+/// /// JSON indented with tabs
+/// struct TabbedJson;
+///
+/// impl SerializerFactory for TabbedJson {
+///     fn serialize(
+///         &self,
+///         io: &mut dyn io::Write,
+///         record: &dyn erased_serde::Serialize,
+///     ) -> io::Result<()> {
+///         let formatter = PrettyFormatter::with_indent(b"\t");
+///         let mut ser = serde_json::Serializer::with_formatter(io, formatter);
+///         erased_serde::serialize(record, &mut ser)?;
+///         Ok(())
+///     }
+/// }
+/// ```
+///
+/// Most serializers are used through a mutable reference, so they are
+/// created and used within `serialize` rather than returned.
+pub trait SerializerFactory {
+    /// Write `record` to `io`
+    fn serialize(
+        &self,
+        io: &mut dyn io::Write,
+        record: &dyn erased_serde::Serialize,
+    ) -> io::Result<()>;
+}
+
+/// Compact JSON, written by `serde_json`
+///
+/// As written by `Json`, which does not go through a `SerializerFactory`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonFactory;
+
+impl SerializerFactory for JsonFactory {
+    fn serialize(
+        &self,
+        io: &mut dyn io::Write,
+        record: &dyn erased_serde::Serialize,
+    ) -> io::Result<()> {
+        let mut ser = serde_json::Serializer::new(io);
+        erased_serde::serialize(record, &mut ser)?;
+        Ok(())
+    }
+}
+
+/// `Drain` writing records in the format of a `SerializerFactory`
+///
+/// Create with `JsonBuilder::build_with_factory`: records have the same
+/// key-values as with `Json`, but for `event_id` and `record_bytes`, which
+/// describe JSON output.
+pub struct SerdeDrain<W: io::Write>(pub(crate) Json<W>);
+
+impl<W> SerdeDrain<W>
+where
+    W: io::Write,
+{
+    /// Number of records that failed to be serialized or written
+    pub fn dropped_count(&self) -> u64 {
+        self.0.dropped_count()
+    }
+}
+
+impl<W> slog::Drain for SerdeDrain<W>
+where
+    W: io::Write,
+{
+    type Ok = ();
+    type Err = io::Error;
+    fn log(
+        &self,
+        rinfo: &Record,
+        logger_values: &OwnedKVList,
+    ) -> io::Result<()> {
+        self.0.log(rinfo, logger_values)
+    }
+}
+
+/// Write `kvs` with `factory`, without a newline
+pub(crate) fn write_record(
+    factory: &dyn SerializerFactory,
+    out: &mut Vec<u8>,
+    kvs: &[(String, Value)],
+) -> io::Result<()> {
+    factory.serialize(out, &KvMap(kvs))
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
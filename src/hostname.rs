@@ -0,0 +1,103 @@
+// {{{ Hostname
+//! Name of the host, see `JsonBuilder::add_hostname`
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Name of the host, from `gethostname`
+#[cfg(unix)]
+pub(crate) fn hostname() -> Option<String> {
+    use std::os::raw::{c_char, c_int};
+
+    extern "C" {
+        fn gethostname(name: *mut c_char, len: usize) -> c_int;
+    }
+
+    let mut buf = [0u8; 256];
+    // Safety: `buf` is valid for writes of its whole length
+    let res =
+        unsafe { gethostname(buf.as_mut_ptr() as *mut c_char, buf.len()) };
+    if res != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8(buf[..len].to_vec()).ok()
+}
+
+/// Name of the host, from the `COMPUTERNAME` environment variable
+#[cfg(not(unix))]
+pub(crate) fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+/// Hostname of `JsonBuilder::add_hostname`, resolved again once stale
+pub(crate) struct CachedHostname {
+    resolve: fn() -> Option<String>,
+    /// Time after which the hostname is resolved again, if ever
+    refresh_interval: Option<Duration>,
+    cached: Mutex<(Option<String>, Instant)>,
+}
+
+impl CachedHostname {
+    pub(crate) fn new(
+        resolve: fn() -> Option<String>,
+        refresh_interval: Option<Duration>,
+    ) -> Self {
+        CachedHostname {
+            resolve,
+            refresh_interval,
+            cached: Mutex::new((resolve(), Instant::now())),
+        }
+    }
+
+    pub(crate) fn get(&self) -> Option<String> {
+        let mut cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+        let (ref mut name, ref mut resolved_at) = *cached;
+        if let Some(interval) = self.refresh_interval {
+            if resolved_at.elapsed() >= interval {
+                *name = (self.resolve)();
+                *resolved_at = Instant::now();
+            }
+        }
+        name.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn refreshed_once_stale() {
+        static RESOLVED: AtomicUsize = AtomicUsize::new(0);
+        fn resolve() -> Option<String> {
+            let n = RESOLVED.fetch_add(1, Ordering::SeqCst);
+            Some(format!("host-{}", n))
+        }
+
+        let cached =
+            CachedHostname::new(resolve, Some(Duration::from_millis(20)));
+        assert_eq!(cached.get().as_deref(), Some("host-0"));
+        assert_eq!(cached.get().as_deref(), Some("host-0"));
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(cached.get().as_deref(), Some("host-1"));
+        assert_eq!(cached.get().as_deref(), Some("host-1"));
+    }
+
+    #[test]
+    fn never_refreshed_without_interval() {
+        static RESOLVED: AtomicUsize = AtomicUsize::new(0);
+        fn resolve() -> Option<String> {
+            let n = RESOLVED.fetch_add(1, Ordering::SeqCst);
+            Some(format!("host-{}", n))
+        }
+
+        let cached = CachedHostname::new(resolve, None);
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(cached.get().as_deref(), Some("host-0"));
+        assert_eq!(RESOLVED.load(Ordering::SeqCst), 1);
+    }
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
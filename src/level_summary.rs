@@ -0,0 +1,70 @@
+// {{{ Level summary
+//! Periodic counts of records by level, see `JsonBuilder::with_level_summary`
+use slog::Level;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Records logged since the last summary, by level
+pub(crate) struct LevelSummary {
+    interval: Duration,
+    counts: [AtomicU64; 6],
+    /// Time of the last summary, or of the first record before any
+    summarized_at: Mutex<Option<SystemTime>>,
+}
+
+impl LevelSummary {
+    pub(crate) fn new(interval: Duration) -> Self {
+        LevelSummary {
+            interval,
+            counts: Default::default(),
+            summarized_at: Mutex::new(None),
+        }
+    }
+
+    /// Count a record at `level`
+    pub(crate) fn count(&self, level: Level) {
+        self.counts[level.as_usize() - 1].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Summary of the records counted since the last one, if `interval`
+    /// elapsed since then
+    ///
+    /// The first call only starts the first interval, at `now`.
+    ///
+    /// Counters are reset, and the summary is an object of the count of
+    /// every level, keyed by `slog::Level::as_short_str`.
+    pub(crate) fn take_due(
+        &self,
+        now: SystemTime,
+    ) -> Option<serde_json::Map<String, serde_json::Value>> {
+        let mut summarized_at = match self.summarized_at.lock() {
+            Ok(summarized_at) => summarized_at,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let elapsed = match *summarized_at {
+            Some(at) => now.duration_since(at).unwrap_or_default(),
+            None => {
+                *summarized_at = Some(now);
+                return None;
+            }
+        };
+        if elapsed < self.interval {
+            return None;
+        }
+        *summarized_at = Some(now);
+        let summary = self
+            .counts
+            .iter()
+            .enumerate()
+            .filter_map(|(i, count)| {
+                let level = Level::from_usize(i + 1)?;
+                let count = count.swap(0, Ordering::Relaxed);
+                Some((level.as_short_str().to_owned(), count.into()))
+            })
+            .collect();
+        Some(summary)
+    }
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
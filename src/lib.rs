@@ -23,16 +23,156 @@
 extern crate slog;
 
 use serde::ser::SerializeMap;
-use serde::serde_if_integer128;
 use slog::Key;
 use slog::Record;
-use slog::{FnValue, PushFnValue};
 use slog::{OwnedKVList, SendSyncRefUnwindSafeKV, KV};
 use std::{fmt, io, result};
 
-use std::cell::RefCell;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
+mod canonical;
+mod collect;
+mod compress;
+mod hostname;
+mod level_summary;
+mod logfmt;
+pub mod mdc;
+mod rate_limit;
+mod reconnect;
+mod strftime;
+use collect::{KvCollector, KvPairs};
+pub use compress::{CompressedWriter, Compression};
+use hostname::{hostname, CachedHostname};
+use level_summary::LevelSummary;
+pub use logfmt::Logfmt;
+use rate_limit::RateLimiter;
+pub use reconnect::ReconnectingWriter;
+pub use strftime::TsFormat;
+#[cfg(feature = "nested-values")]
+mod truncate;
+#[cfg(feature = "nested-values")]
+mod values;
+#[cfg(feature = "nested-values")]
+pub use values::ResultValue;
+#[cfg(feature = "backtrace")]
+#[clippy::msrv = "1.65"]
+mod backtrace;
+#[cfg(feature = "cloud-metadata")]
+mod cloud;
+#[cfg(feature = "cpu-time")]
+mod cpu_time;
+#[cfg(feature = "serializer-factory")]
+mod factory;
+#[cfg(all(
+    feature = "mmap-ring",
+    any(target_os = "linux", target_os = "android", target_os = "macos")
+))]
+mod mmap_ring;
+#[cfg(feature = "jsonschema")]
+mod schema;
+#[cfg(feature = "tty")]
+mod tty;
+#[cfg(feature = "yaml")]
+mod yaml;
+#[cfg(feature = "backtrace")]
+pub use backtrace::{Backtrace, BACKTRACE_MAX_FRAMES};
+#[cfg(feature = "cloud-metadata")]
+pub use cloud::CloudProvider;
+#[cfg(feature = "serializer-factory")]
+pub use factory::{JsonFactory, SerdeDrain, SerializerFactory};
+#[cfg(all(
+    feature = "mmap-ring",
+    any(target_os = "linux", target_os = "android", target_os = "macos")
+))]
+pub use mmap_ring::MmapRingJson;
+#[cfg(feature = "jsonschema")]
+use schema::SchemaValidator;
+#[cfg(feature = "jsonschema")]
+pub use schema::ValidationPolicy;
+#[cfg(feature = "tty")]
+pub use tty::Tty;
+#[cfg(feature = "yaml")]
+pub use yaml::Yaml;
+
+#[doc(hidden)]
+pub extern crate slog as __slog;
+// }}}
+
+// {{{ Macros
+/// Key-value with the package name of the calling crate, under `app`
+///
+/// Expands to `o!("app" => env!("CARGO_PKG_NAME"))`, to be passed to
+/// `JsonBuilder::add_key_value`. See `JsonBuilder::add_app_name` to set the
+/// name explicitly.
+///
+/// ```
+/// use slog::{info, o, Drain};
+///
+/// let buf = slog_json::SharedBuffer::new();
+/// let drain = slog_json::Json::new(buf.clone())
+///     .add_key_value(slog_json::app_name_kv!())
+///     .build();
+/// let log = slog::Logger::root(std::sync::Mutex::new(drain).fuse(), o!());
+/// info!(log, "hello");
+/// assert_eq!(
+///     buf.to_string_lossy(),
+///     format!("{{\"app\":\"{}\"}}\n", env!("CARGO_PKG_NAME"))
+/// );
+/// ```
+#[macro_export]
+macro_rules! app_name_kv {
+    () => {
+        $crate::__slog::o!("app" => env!("CARGO_PKG_NAME"))
+    };
+}
+
+/// Key-value with a git commit hash set at compile time, under
+/// `build.git_sha`
+///
+/// Expands to an `o!` with the value of the environment variable `$var`
+/// when the calling crate was compiled, as in `option_env!`, to be passed
+/// to `JsonBuilder::add_key_value`. If the variable was not set, the key is
+/// left out of records.
+///
+/// ```
+/// let drain = slog_json::Json::new(std::io::stderr())
+///     .add_default_keys()
+///     .add_key_value(slog_json::git_sha_kv!("GIT_SHA"))
+///     .build();
+/// ```
+#[macro_export]
+macro_rules! git_sha_kv {
+    ($var:tt) => {
+        $crate::__slog::o!(
+            "build.git_sha" => $crate::__OmittedIfNone(option_env!($var))
+        )
+    };
+}
+
+/// Value emitting nothing when `None`, see `git_sha_kv`
+#[doc(hidden)]
+pub struct __OmittedIfNone(pub Option<&'static str>);
+
+impl slog::Value for __OmittedIfNone {
+    fn serialize(
+        &self,
+        _record: &Record,
+        key: Key,
+        serializer: &mut dyn slog::Serializer,
+    ) -> slog::Result {
+        match self.0 {
+            Some(val) => serializer.emit_str(key, val),
+            None => Ok(()),
+        }
+    }
+}
 // }}}
 
 // {{{ Serialize
@@ -44,21 +184,102 @@ thread_local! {
 ///
 /// Newtype to wrap serde Serializer, so that `Serialize` can be implemented
 /// for it
-struct SerdeSerializer<S: serde::Serializer> {
+struct SerdeSerializer<'a, S: serde::Serializer> {
     /// Current state of map serializing: `serde::Serializer::MapState`
     ser_map: S::SerializeMap,
+    /// Transformations applied to every key-value
+    opts: &'a SerdeOptions,
 }
 
-impl<S: serde::Serializer> SerdeSerializer<S> {
+macro_rules! impl_m(
+    (@key $s:expr, $key:expr, $val:expr) => (
+        impl_m!(@emit $s, $key, $s.opts.key($key.as_ref()), $val)
+    );
+    (@emit $s:expr, $key:expr, $k_s:expr, $val:expr) => ({
+        if !$s.opts.emitted($key.as_ref()) {
+            return Ok(());
+        }
+        let k_s = $k_s;
+        let res = if $s.opts.best_effort {
+            // Checked up front, so no half-written value ends up in `io`
+            match serde_json::to_value($val) {
+                Ok(val) => $s.ser_map.serialize_entry(&*k_s, &val),
+                Err(_) => $s.ser_map.serialize_entry(&*k_s, FIELD_ERROR),
+            }
+        } else {
+            $s.ser_map.serialize_entry(&*k_s, $val)
+        };
+        res.map_err(|e| io::Error::new(io::ErrorKind::Other, format!("serde serialization error: {}", e)))?;
+        Ok(())
+    });
+    ($s:expr, $key:expr, $val:expr) => (
+        impl_m!(@emit $s, $key, $s.opts.user_key($key.as_ref())?, $val)
+    );
+);
+
+/// `impl_m` for numbers, stringified unless allowed by `SerdeOptions`
+macro_rules! impl_num(
+    ($s:expr, $key:expr, $val:expr) => ({
+        if $s.opts.numeric($key.as_ref()) {
+            impl_m!($s, $key, &$val)
+        } else {
+            impl_m!($s, $key, &$val.to_string())
+        }
+    });
+);
+
+impl<'a, S: serde::Serializer> SerdeSerializer<'a, S> {
     /// Start serializing map of values
-    fn start(ser: S, len: Option<usize>) -> result::Result<Self, slog::Error> {
+    fn start(
+        ser: S,
+        len: Option<usize>,
+        opts: &'a SerdeOptions,
+    ) -> result::Result<Self, slog::Error> {
         let ser_map = ser.serialize_map(len).map_err(|e| {
             io::Error::new(
                 io::ErrorKind::Other,
                 format!("serde serialization error: {}", e),
             )
         })?;
-        Ok(SerdeSerializer { ser_map })
+        Ok(SerdeSerializer { ser_map, opts })
+    }
+
+    /// Serialize a single entry under a key that is not a `slog::Key`
+    fn emit_entry<V>(&mut self, key: &str, val: &V) -> slog::Result
+    where
+        V: serde::Serialize + ?Sized,
+    {
+        impl_m!(@key self, key, val)
+    }
+
+    /// Serialize a single entry under a key set by the user, but not as a
+    /// `slog::Key`
+    fn emit_user_entry<V>(&mut self, key: &str, val: &V) -> slog::Result
+    where
+        V: serde::Serialize + ?Sized,
+    {
+        impl_m!(self, key, val)
+    }
+
+    /// Emit the fields of a `traceparent`, see `JsonBuilder::parse_traceparent`
+    fn emit_traceparent(&mut self, tp: &Traceparent<'_>) -> slog::Result {
+        self.emit_entry("trace_id", tp.trace_id)?;
+        self.emit_entry("span_id", tp.span_id)?;
+        self.emit_entry("trace_flags", tp.trace_flags)
+    }
+
+    /// Serialize a single entry under a key that was already transformed
+    fn emit_raw_entry<V>(&mut self, key: &str, val: &V) -> slog::Result
+    where
+        V: serde::Serialize + ?Sized,
+    {
+        self.ser_map.serialize_entry(key, val).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                format!("serde serialization error: {}", e),
+            )
+        })?;
+        Ok(())
     }
 
     /// Finish serialization, and return the serializer
@@ -67,21 +288,18 @@ impl<S: serde::Serializer> SerdeSerializer<S> {
     }
 }
 
-macro_rules! impl_m(
-    ($s:expr, $key:expr, $val:expr) => ({
-        let k_s:  &str = $key.as_ref();
-        $s.ser_map.serialize_entry(k_s, $val)
-             .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("serde serialization error: {}", e)))?;
-        Ok(())
-    });
-);
-
-impl<S> slog::Serializer for SerdeSerializer<S>
+impl<'a, S> slog::Serializer for SerdeSerializer<'a, S>
 where
     S: serde::Serializer,
 {
     fn emit_bool(&mut self, key: Key, val: bool) -> slog::Result {
-        impl_m!(self, key, &val)
+        let as_int = self.opts.bool_as_int(key.as_ref());
+        match (as_int, self.opts.all_strings) {
+            (true, false) => impl_m!(self, key, &u8::from(val)),
+            (true, true) => impl_m!(self, key, &u8::from(val).to_string()),
+            (false, false) => impl_m!(self, key, &val),
+            (false, true) => impl_m!(self, key, &val.to_string()),
+        }
     }
 
     fn emit_unit(&mut self, key: Key) -> slog::Result {
@@ -97,51 +315,64 @@ where
         impl_m!(self, key, &val)
     }
     fn emit_u8(&mut self, key: Key, val: u8) -> slog::Result {
-        impl_m!(self, key, &val)
+        impl_num!(self, key, val)
     }
     fn emit_i8(&mut self, key: Key, val: i8) -> slog::Result {
-        impl_m!(self, key, &val)
+        impl_num!(self, key, val)
     }
     fn emit_u16(&mut self, key: Key, val: u16) -> slog::Result {
-        impl_m!(self, key, &val)
+        impl_num!(self, key, val)
     }
     fn emit_i16(&mut self, key: Key, val: i16) -> slog::Result {
-        impl_m!(self, key, &val)
+        impl_num!(self, key, val)
     }
     fn emit_usize(&mut self, key: Key, val: usize) -> slog::Result {
-        impl_m!(self, key, &val)
+        impl_num!(self, key, val)
     }
     fn emit_isize(&mut self, key: Key, val: isize) -> slog::Result {
-        impl_m!(self, key, &val)
+        impl_num!(self, key, val)
     }
     fn emit_u32(&mut self, key: Key, val: u32) -> slog::Result {
-        impl_m!(self, key, &val)
+        impl_num!(self, key, val)
     }
     fn emit_i32(&mut self, key: Key, val: i32) -> slog::Result {
-        impl_m!(self, key, &val)
+        impl_num!(self, key, val)
     }
     fn emit_f32(&mut self, key: Key, val: f32) -> slog::Result {
-        impl_m!(self, key, &val)
+        impl_num!(self, key, val)
     }
     fn emit_u64(&mut self, key: Key, val: u64) -> slog::Result {
-        impl_m!(self, key, &val)
+        impl_num!(self, key, val)
     }
     fn emit_i64(&mut self, key: Key, val: i64) -> slog::Result {
-        impl_m!(self, key, &val)
+        impl_num!(self, key, val)
     }
     fn emit_f64(&mut self, key: Key, val: f64) -> slog::Result {
-        impl_m!(self, key, &val)
+        impl_num!(self, key, val)
     }
-    serde_if_integer128! {
-        fn emit_u128(&mut self, key: Key, val: u128) -> slog::Result {
-            impl_m!(self, key, &val)
-        }
-        fn emit_i128(&mut self, key: Key, val: i128) -> slog::Result {
-            impl_m!(self, key, &val)
-        }
+    fn emit_u128(&mut self, key: Key, val: u128) -> slog::Result {
+        impl_num!(self, key, val)
+    }
+    fn emit_i128(&mut self, key: Key, val: i128) -> slog::Result {
+        impl_num!(self, key, val)
     }
     fn emit_str(&mut self, key: Key, val: &str) -> slog::Result {
-        impl_m!(self, key, &val)
+        let val = self.opts.strip_controls(val);
+        #[cfg(feature = "unicode-normalization")]
+        let val = match self.opts.unicode_form {
+            Some(form) => form.normalize(val),
+            None => val,
+        };
+        if let Some(tp) = self.opts.traceparent(key.as_ref(), &val) {
+            return self.emit_traceparent(&tp);
+        }
+        if val.is_empty() && self.opts.empty_string_as_null {
+            return self.emit_none(key);
+        }
+        if let Some(n) = self.opts.number_string(key.as_ref(), &val) {
+            return impl_m!(self, key, &n);
+        }
+        impl_m!(self, key, &*val)
     }
     fn emit_arguments(
         &mut self,
@@ -153,7 +384,7 @@ where
 
             buf.write_fmt(*val).unwrap();
 
-            let res = { || impl_m!(self, key, &*buf) }();
+            let res = self.emit_str(key, &buf);
             buf.clear();
             res
         })
@@ -165,184 +396,5160 @@ where
         key: Key,
         value: &dyn slog::SerdeValue,
     ) -> slog::Result {
-        impl_m!(self, key, value.as_serde())
+        match self.opts.transform_nested(value)? {
+            Some(value) => impl_m!(self, key, &value),
+            None => impl_m!(self, key, value.as_serde()),
+        }
     }
 }
-// }}}
 
-// {{{ Json
-/// Json `Drain`
+/// Value emitted for fields failing to serialize, in best-effort mode
+const FIELD_ERROR: &str = "<field error>";
+
+/// Transformations applied to every key-value, in `SerdeSerializer`
+#[derive(Default)]
+struct SerdeOptions {
+    /// Emit `FIELD_ERROR` for fields failing to serialize, and go on
+    best_effort: bool,
+    lowercase_keys: bool,
+    /// New names of keys, see `JsonBuilder::rename_keys`
+    renames: HashMap<String, String>,
+    /// Keys allowed to be emitted as numbers, if restricted
+    numeric_allowlist: Option<HashSet<String>>,
+    /// Keys whose booleans are emitted as `0` and `1`
+    bool_as_int: HashSet<String>,
+    /// Keys whose numeric strings are emitted as numbers
+    number_strings: HashSet<String>,
+    /// Key of W3C `traceparent` values to split into their fields
+    traceparent_key: Option<String>,
+    empty_string_as_null: bool,
+    /// Predicate keys have to match to be emitted
+    key_filter: Option<KeyFilter>,
+    /// Length in bytes above which keys are truncated
+    max_key_len: Option<usize>,
+    long_keys: LongKeyPolicy,
+    strip_control_chars: bool,
+    /// Keep tabs and newlines, when stripping control characters
+    keep_tabs_newlines: bool,
+    /// Emit all values but `null`s as strings
+    all_strings: bool,
+    #[cfg(feature = "unicode-normalization")]
+    unicode_form: Option<UnicodeForm>,
+    /// Keys of the drain user keys are renamed from, prefixed with `user_`
+    reserved_keys: Vec<String>,
+    /// Number of elements above which arrays of nested values are cut
+    #[cfg(feature = "nested-values")]
+    max_array_len: Option<usize>,
+}
+
+/// What to do with keys longer than `JsonBuilder::max_key_length`, see
+/// `JsonBuilder::long_key_policy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LongKeyPolicy {
+    /// Truncate the key, keeping a hash of it, the default
+    Truncate,
+    /// Replace the key with `k_` and 8 hex digits hashing it
+    HashReplace,
+    /// Fail to serialize the key-value
+    Error,
+}
+
+impl Default for LongKeyPolicy {
+    fn default() -> Self {
+        LongKeyPolicy::Truncate
+    }
+}
+
+/// Unicode normalization form of `JsonBuilder::normalize_unicode`
+#[cfg(feature = "unicode-normalization")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UnicodeForm {
+    /// Canonical composition, eg. `é` as a single code point
+    Nfc,
+    /// Canonical decomposition, eg. `é` as `e` and a combining accent
+    Nfd,
+    /// Compatibility composition, eg. `ﬁ` as `fi`
+    Nfkc,
+    /// Compatibility decomposition
+    Nfkd,
+}
+
+#[cfg(feature = "unicode-normalization")]
+impl UnicodeForm {
+    fn normalize<'v>(self, val: Cow<'v, str>) -> Cow<'v, str> {
+        use unicode_normalization::{IsNormalized, UnicodeNormalization};
+
+        let quick = match self {
+            UnicodeForm::Nfc => {
+                unicode_normalization::is_nfc_quick(val.chars())
+            }
+            UnicodeForm::Nfd => {
+                unicode_normalization::is_nfd_quick(val.chars())
+            }
+            UnicodeForm::Nfkc => {
+                unicode_normalization::is_nfkc_quick(val.chars())
+            }
+            UnicodeForm::Nfkd => {
+                unicode_normalization::is_nfkd_quick(val.chars())
+            }
+        };
+        if quick == IsNormalized::Yes {
+            return val;
+        }
+        Cow::Owned(match self {
+            UnicodeForm::Nfc => val.nfc().collect(),
+            UnicodeForm::Nfd => val.nfd().collect(),
+            UnicodeForm::Nfkc => val.nfkc().collect(),
+            UnicodeForm::Nfkd => val.nfkd().collect(),
+        })
+    }
+}
+
+/// Truncate `key` to at most `max` bytes, see `JsonBuilder::max_key_length`
 ///
-/// Each record will be printed as a Json map
-/// to a given `io`
-pub struct Json<W: io::Write> {
-    newlines: bool,
-    flush: bool,
-    values: Vec<OwnedKVList>,
-    io: RefCell<W>,
-    pretty: bool,
+/// When there is room for it, the truncated key ends with `~` and 8 hex
+/// digits of the hash of the whole key, so that keys sharing a long prefix
+/// stay apart.
+fn truncate_key(key: &str, max: usize) -> String {
+    const SUFFIX_LEN: usize = 9;
+    let floor = |mut end: usize| {
+        while !key.is_char_boundary(end) {
+            end -= 1;
+        }
+        end
+    };
+    if max < SUFFIX_LEN {
+        return key[..floor(max)].to_owned();
+    }
+    let algo = HashAlgo::Fnv1a64;
+    let hash = algo.update(algo.initial(), key.as_bytes());
+    format!("{}~{:08x}", &key[..floor(max - SUFFIX_LEN)], hash as u32)
 }
 
-impl<W> Json<W>
-where
-    W: io::Write,
-{
-    /// New `Json` `Drain` with default key-value pairs added
-    pub fn default(io: W) -> Json<W> {
-        JsonBuilder::new(io).add_default_keys().build()
+/// Closure filtering emitted keys, see `JsonBuilder::with_key_filter`
+type KeyFilter = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+impl SerdeOptions {
+    fn emitted(&self, key: &str) -> bool {
+        self.key_filter.as_ref().map_or(true, |f| f(key))
     }
 
-    /// Build custom `Json` `Drain`
-    #[cfg_attr(feature = "cargo-clippy", allow(clippy::new_ret_no_self))]
-    pub fn new(io: W) -> JsonBuilder<W> {
-        JsonBuilder::new(io)
+    fn key<'k>(&self, key: &'k str) -> Cow<'k, str> {
+        self.rename(self.lowercase(key))
     }
 
-    fn log_impl<F>(
-        &self,
-        serializer: &mut serde_json::ser::Serializer<&mut W, F>,
-        rinfo: &Record,
-        logger_values: &OwnedKVList,
-    ) -> io::Result<()>
-    where
-        F: serde_json::ser::Formatter,
-    {
-        let mut serializer = SerdeSerializer::start(&mut *serializer, None)?;
+    /// `key`, for keys set by the user rather than by the drain
+    ///
+    /// Fails for keys that are too long, with `LongKeyPolicy::Error`.
+    fn user_key<'k>(&self, key: &'k str) -> io::Result<Cow<'k, str>> {
+        let key = self.lowercase(key);
+        let key = if self.reserved_keys.iter().any(|reserved| *reserved == key)
+        {
+            Cow::Owned(format!("user_{}", key))
+        } else {
+            key
+        };
+        let key = self.rename(key);
+        let max = match self.max_key_len {
+            Some(max) if key.len() > max => max,
+            _ => return Ok(key),
+        };
+        match self.long_keys {
+            LongKeyPolicy::Truncate => Ok(Cow::Owned(truncate_key(&key, max))),
+            LongKeyPolicy::HashReplace => {
+                let algo = HashAlgo::Fnv1a64;
+                let hash = algo.update(algo.initial(), key.as_bytes());
+                Ok(Cow::Owned(format!("k_{:08x}", hash as u32)))
+            }
+            LongKeyPolicy::Error => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("key of {} bytes, over {}: {}", key.len(), max, key),
+            )),
+        }
+    }
 
-        for kv in &self.values {
-            kv.serialize(rinfo, &mut serializer)?;
+    fn lowercase<'k>(&self, key: &'k str) -> Cow<'k, str> {
+        if self.lowercase_keys && key.chars().any(char::is_uppercase) {
+            Cow::Owned(key.to_lowercase())
+        } else {
+            Cow::Borrowed(key)
         }
+    }
 
-        logger_values.serialize(rinfo, &mut serializer)?;
+    fn rename<'k>(&self, key: Cow<'k, str>) -> Cow<'k, str> {
+        if self.renames.is_empty() {
+            return key;
+        }
+        match self.renames.get(&*key) {
+            Some(renamed) => Cow::Owned(renamed.clone()),
+            None => key,
+        }
+    }
 
-        rinfo.kv().serialize(rinfo, &mut serializer)?;
+    /// `val` without its control characters, see
+    /// `JsonBuilder::strip_control_chars`
+    fn strip_controls<'v>(&self, val: &'v str) -> Cow<'v, str> {
+        let stripped = |c: char| {
+            c.is_control() && !(self.keep_tabs_newlines && "\t\n".contains(c))
+        };
+        if self.strip_control_chars && val.chars().any(stripped) {
+            Cow::Owned(val.chars().filter(|&c| !stripped(c)).collect())
+        } else {
+            Cow::Borrowed(val)
+        }
+    }
 
-        let res = serializer.end();
+    /// Error of serializing a list of key-values, unless best-effort
+    ///
+    /// A failing `slog::Value` stops the serialization of the rest of its
+    /// list, but the record itself still gets emitted.
+    fn soften(&self, res: slog::Result) -> slog::Result {
+        if self.best_effort {
+            Ok(())
+        } else {
+            res
+        }
+    }
 
-        res.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    fn traceparent<'v>(
+        &self,
+        key: &str,
+        val: &'v str,
+    ) -> Option<Traceparent<'v>> {
+        if self.traceparent_key.as_deref() == Some(key) {
+            Traceparent::parse(val)
+        } else {
+            None
+        }
+    }
 
-        Ok(())
+    fn bool_as_int(&self, key: &str) -> bool {
+        self.bool_as_int.contains(key)
     }
-}
 
-impl<W> slog::Drain for Json<W>
-where
-    W: io::Write,
-{
-    type Ok = ();
-    type Err = io::Error;
-    fn log(
+    fn numeric(&self, key: &str) -> bool {
+        !self.all_strings
+            && self
+                .numeric_allowlist
+                .as_ref()
+                .map_or(true, |allowed| allowed.contains(key))
+    }
+
+    /// Number a string logged under `key` is emitted as, see
+    /// `JsonBuilder::auto_number_strings`
+    fn number_string(
         &self,
-        rinfo: &Record,
-        logger_values: &OwnedKVList,
-    ) -> io::Result<()> {
-        let mut io = self.io.borrow_mut();
-        let io = if self.pretty {
-            let mut serializer = serde_json::Serializer::pretty(&mut *io);
-            self.log_impl(&mut serializer, rinfo, logger_values)?;
-            serializer.into_inner()
-        } else {
-            let mut serializer = serde_json::Serializer::new(&mut *io);
-            self.log_impl(&mut serializer, rinfo, logger_values)?;
-            serializer.into_inner()
+        key: &str,
+        val: &str,
+    ) -> Option<serde_json::Number> {
+        if !self.number_strings.contains(key) || !self.numeric(key) {
+            return None;
+        }
+        // JSON numbers only: no leading zeros, `+`, `inf` or whitespace
+        if val.trim() != val {
+            return None;
+        }
+        serde_json::from_str(val).ok()
+    }
+
+    /// Nested value as transformed by `JsonBuilder::all_string_values`
+    /// and `JsonBuilder::max_array_len`, if either is enabled
+    #[cfg(feature = "nested-values")]
+    fn transform_nested(
+        &self,
+        value: &dyn slog::SerdeValue,
+    ) -> slog::Result<Option<serde_json::Value>> {
+        use serde::Serialize;
+
+        let res = match self.max_array_len {
+            Some(max) => {
+                value.as_serde().serialize(truncate::Truncating { max })
+            }
+            None if self.all_strings => serde_json::to_value(value.as_serde()),
+            None => return Ok(None),
         };
-        if self.newlines {
-            io.write_all("\n".as_bytes())?;
+        let value = match res {
+            Ok(value) => value,
+            Err(_) if self.best_effort => return Ok(Some(FIELD_ERROR.into())),
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("serde serialization error: {}", e),
+                )
+                .into())
+            }
+        };
+        if self.all_strings {
+            Ok(Some(value.to_string().into()))
+        } else {
+            Ok(Some(value))
         }
-        if self.flush {
-            io.flush()?;
+    }
+}
+
+/// Fields of a W3C Trace Context `traceparent` header value
+struct Traceparent<'a> {
+    trace_id: &'a str,
+    span_id: &'a str,
+    trace_flags: &'a str,
+}
+
+impl<'a> Traceparent<'a> {
+    /// Parse `version-trace_id-parent_id-trace_flags`
+    fn parse(s: &'a str) -> Option<Self> {
+        let is_hex = |s: &str, len| {
+            s.len() == len
+                && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        };
+        let is_zero = |s: &str| s.bytes().all(|b| b == b'0');
+
+        let mut parts = s.split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let span_id = parts.next()?;
+        let trace_flags = parts.next()?;
+        // Later versions may append fields, version 00 may not
+        if !is_hex(version, 2)
+            || version == "ff"
+            || (version == "00" && parts.next().is_some())
+            || !is_hex(trace_id, 32)
+            || is_zero(trace_id)
+            || !is_hex(span_id, 16)
+            || is_zero(span_id)
+            || !is_hex(trace_flags, 2)
+        {
+            return None;
         }
-        Ok(())
+        Some(Traceparent {
+            trace_id,
+            span_id,
+            trace_flags,
+        })
     }
 }
 
+/// Key-values of a record, serialized as a map in their order
+#[cfg(any(feature = "yaml", feature = "serializer-factory"))]
+pub(crate) struct KvMap<'a>(pub(crate) &'a [(String, serde_json::Value)]);
+
+#[cfg(any(feature = "yaml", feature = "serializer-factory"))]
+impl serde::Serialize for KvMap<'_> {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (k, v) in self.0 {
+            map.serialize_entry(k, v)?;
+        }
+        map.end()
+    }
+}
 // }}}
 
-// {{{ JsonBuilder
-/// Json `Drain` builder
+// {{{ Flattening
+/// Format of the keys of flattened objects
 ///
-/// Create with `Json::new`.
-pub struct JsonBuilder<W: io::Write> {
-    newlines: bool,
-    flush: bool,
-    values: Vec<OwnedKVList>,
-    io: W,
-    pretty: bool,
+/// See `JsonBuilder::add_flattened_object`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlattenStyle {
+    /// `service.name`, `tags.0`
+    Dotted,
+    /// JSON Pointer (RFC 6901): `/service/name`, `/tags/0`
+    JsonPointer,
+    /// `service[name]`, `tags[0]`
+    Bracketed,
 }
 
-impl<W> JsonBuilder<W>
-where
-    W: io::Write,
-{
-    fn new(io: W) -> Self {
-        JsonBuilder {
-            newlines: true,
-            flush: false,
-            values: vec![],
-            io,
-            pretty: false,
-        }
+impl Default for FlattenStyle {
+    fn default() -> Self {
+        FlattenStyle::Dotted
     }
+}
 
-    /// Build `Json` `Drain`
-    ///
-    /// This consumes the builder.
-    pub fn build(self) -> Json<W> {
-        Json {
-            values: self.values,
-            newlines: self.newlines,
-            flush: self.flush,
-            io: RefCell::new(self.io),
-            pretty: self.pretty,
+impl FlattenStyle {
+    fn join(self, prefix: Option<&str>, key: &str) -> String {
+        match (self, prefix) {
+            (FlattenStyle::JsonPointer, prefix) => format!(
+                "{}/{}",
+                prefix.unwrap_or(""),
+                key.replace('~', "~0").replace('/', "~1")
+            ),
+            (_, None) => key.to_owned(),
+            (FlattenStyle::Dotted, Some(prefix)) => {
+                format!("{}.{}", prefix, key)
+            }
+            (FlattenStyle::Bracketed, Some(prefix)) => {
+                format!("{}[{}]", prefix, key)
+            }
         }
     }
+}
 
-    /// Set writing a newline after every log record
-    pub fn set_newlines(mut self, enabled: bool) -> Self {
-        self.newlines = enabled;
-        self
+/// Flatten nested objects and arrays of `value` into top-level keys
+///
+/// Object members and array indices are joined according to `style`.
+/// Empty objects and arrays are kept as-is under their key.
+fn flatten_value(
+    style: FlattenStyle,
+    prefix: Option<&str>,
+    value: serde_json::Value,
+    out: &mut Vec<(String, serde_json::Value)>,
+) {
+    match value {
+        serde_json::Value::Object(map) if !map.is_empty() => {
+            for (k, v) in map {
+                flatten_value(style, Some(&style.join(prefix, &k)), v, out);
+            }
+        }
+        serde_json::Value::Array(arr) if !arr.is_empty() => {
+            for (i, v) in arr.into_iter().enumerate() {
+                let key = style.join(prefix, &i.to_string());
+                flatten_value(style, Some(&key), v, out);
+            }
+        }
+        value => {
+            if let Some(prefix) = prefix {
+                out.push((prefix.to_owned(), value));
+            }
+        }
     }
+}
 
-    /// Enable flushing of the `io::Write` after every log record
-    pub fn set_flush(mut self, enabled: bool) -> Self {
-        self.flush = enabled;
-        self
-    }
+/// Nest the values of dotted keys into objects, see
+/// `JsonBuilder::unflatten_dotted_keys`
+///
+/// A nested object takes the place of the first key nested into it. Keys
+/// that have another key as prefix, like `http.status` along with `http`,
+/// or that have empty segments, are kept as they are.
+fn unflatten_dotted(
+    kvs: Vec<(String, serde_json::Value)>,
+) -> Vec<(String, serde_json::Value)> {
+    use serde_json::{Map, Value};
 
-    /// Set whether or not pretty formatted logging should be used
-    pub fn set_pretty(mut self, enabled: bool) -> Self {
-        self.pretty = enabled;
-        self
-    }
+    let keys: HashSet<String> = kvs.iter().map(|(k, _)| k.clone()).collect();
+    let nestable = |key: &str| {
+        key.contains('.')
+            && !key.split('.').any(str::is_empty)
+            && !key
+                .match_indices('.')
+                .any(|(i, _)| keys.contains(&key[..i]))
+    };
 
-    /// Add custom values to be printed with this formatter
-    pub fn add_key_value<T>(mut self, value: slog::OwnedKV<T>) -> Self
-    where
-        T: SendSyncRefUnwindSafeKV + 'static,
-    {
-        self.values.push(value.into());
-        self
+    let mut out: Vec<(String, Value)> = Vec::with_capacity(kvs.len());
+    // Position in `out` of the objects created here, by key
+    let mut nested: Vec<(String, usize)> = vec![];
+    for (key, value) in kvs {
+        if !nestable(&key) {
+            out.push((key, value));
+            continue;
+        }
+        let mut segments = key.split('.');
+        let top = segments.next().unwrap_or_default();
+        let pos = match nested.iter().find(|(k, _)| k == top) {
+            Some(&(_, pos)) => pos,
+            None => {
+                nested.push((top.to_owned(), out.len()));
+                out.push((top.to_owned(), Value::Object(Map::new())));
+                out.len() - 1
+            }
+        };
+        let mut segments: Vec<&str> = segments.collect();
+        let last = segments.pop().unwrap_or_default();
+        let mut obj = &mut out[pos].1;
+        for segment in segments {
+            obj = match obj {
+                Value::Object(map) => map
+                    .entry(segment)
+                    .or_insert_with(|| Value::Object(Map::new())),
+                _ => unreachable!("nested values are objects"),
+            };
+        }
+        if let Value::Object(map) = obj {
+            map.insert(last.to_owned(), value);
+        }
+    }
+    out
+}
+// }}}
+
+// {{{ Host
+/// Primary non-loopback IP address of the host
+///
+/// Taken from the local address of a UDP socket connected (which sends
+/// nothing) to a non-local address, ie. the one of the default route.
+fn primary_ip() -> Option<std::net::IpAddr> {
+    use std::net::UdpSocket;
+
+    let candidates =
+        [("0.0.0.0:0", "192.0.2.1:9"), ("[::]:0", "[2001:db8::1]:9")];
+    candidates.iter().find_map(|&(local, remote)| {
+        let socket = UdpSocket::bind(local).ok()?;
+        socket.connect(remote).ok()?;
+        let ip = socket.local_addr().ok()?.ip();
+        if ip.is_loopback() || ip.is_unspecified() {
+            None
+        } else {
+            Some(ip)
+        }
+    })
+}
+// }}}
+
+// {{{ TimestampFormat
+/// Format of the `ts` default key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TimestampFormat {
+    /// RFC 3339, with nanoseconds: `2022-02-20T12:34:56.789012345Z`
+    Rfc3339,
+    /// RFC 3339, truncated to whole seconds: `2022-02-20T12:34:56Z`
+    Rfc3339Seconds,
+    /// RFC 3339, truncated to milliseconds: `2022-02-20T12:34:56.789Z`
+    Rfc3339Millis,
+    /// ISO 8601 basic format, without separators, truncated to whole
+    /// seconds: `20220220T123456Z`
+    Iso8601Basic,
+}
+
+impl Default for TimestampFormat {
+    fn default() -> Self {
+        TimestampFormat::Rfc3339
+    }
+}
+
+impl TimestampFormat {
+    fn format(self, ts: time::OffsetDateTime) -> Option<String> {
+        use time::format_description::well_known::Rfc3339;
+        match self {
+            TimestampFormat::Rfc3339 => ts.format(&Rfc3339).ok(),
+            TimestampFormat::Rfc3339Seconds => {
+                let whole =
+                    ts - time::Duration::nanoseconds(ts.nanosecond().into());
+                whole.format(&Rfc3339).ok()
+            }
+            TimestampFormat::Rfc3339Millis => {
+                let whole = TimestampFormat::Rfc3339Seconds.format(ts)?;
+                // Always 3 digits, where `Rfc3339` drops trailing zeros
+                let secs = whole.strip_suffix('Z')?;
+                Some(format!("{}.{:03}Z", secs, ts.millisecond()))
+            }
+            TimestampFormat::Iso8601Basic => Some(format!(
+                "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+                ts.year(),
+                u8::from(ts.month()),
+                ts.day(),
+                ts.hour(),
+                ts.minute(),
+                ts.second(),
+            )),
+        }
+    }
+}
+
+/// Timestamp settings known to work with a given consumer, see
+/// `JsonBuilder::timestamp_preset`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TimestampPreset {
+    /// `@timestamp`, RFC 3339 with milliseconds, the precision of the
+    /// default `date` type
+    Elasticsearch,
+    /// `time`, seconds since the Unix epoch with millisecond decimals, as
+    /// read by the HTTP Event Collector
+    Splunk,
+    /// `timestamp`, RFC 3339 with milliseconds, the precision of the date
+    /// remapper
+    Datadog,
+    /// `ts`, RFC 3339 with nanoseconds, the precision of Loki
+    Loki,
+}
+
+/// Unit of the `ts` default key when emitted as a number, see
+/// `JsonBuilder::set_ts_epoch`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EpochPrecision {
+    /// Whole seconds
+    Seconds,
+    /// Whole milliseconds
+    Millis,
+    /// Nanoseconds
+    Nanos,
+}
+
+impl EpochPrecision {
+    /// Time from `origin` to `time` in this unit, negative if `time` is
+    /// earlier
+    fn since(self, origin: SystemTime, time: SystemTime) -> i64 {
+        let (since, sign) = match time.duration_since(origin) {
+            Ok(since) => (since, 1),
+            Err(e) => (e.duration(), -1),
+        };
+        let since = match self {
+            EpochPrecision::Seconds => i128::from(since.as_secs()),
+            EpochPrecision::Millis => since.as_millis() as i128,
+            EpochPrecision::Nanos => since.as_nanos() as i128,
+        };
+        sign * since as i64
+    }
+}
+
+/// Milliseconds since the Unix epoch, negative before it
+fn unix_ms(time: SystemTime) -> i64 {
+    ms_since(std::time::UNIX_EPOCH, time)
+}
+
+/// Milliseconds from `origin` to `time`, negative if `time` is earlier
+fn ms_since(origin: SystemTime, time: SystemTime) -> i64 {
+    match time.duration_since(origin) {
+        Ok(since) => since.as_millis() as i64,
+        Err(e) => -(e.duration().as_millis() as i64),
+    }
+}
+
+/// Closure formatting the `ts` default key, see `JsonBuilder::with_timestamp_fn`
+type TimestampFn = Arc<dyn Fn(SystemTime) -> String + Send + Sync>;
+
+/// Closure reading the current time, see `JsonBuilder::with_clock`
+type ClockFn = Arc<dyn Fn() -> SystemTime + Send + Sync>;
+
+/// Timestamps formatted once per granule, see `JsonBuilder::cache_timestamps`
+///
+/// The timestamp formatted last is kept per thread, in `TL_TS`, so that
+/// threads logging at once neither wait on each other nor copy it.
+struct TimestampCache {
+    /// Tells the caches of different drains apart in `TL_TS`
+    id: u64,
+    /// Nanoseconds, at least one
+    granularity: u128,
+}
+
+/// Source of `TimestampCache::id`
+static NEXT_TIMESTAMP_CACHE: AtomicU64 = AtomicU64::new(0);
+
+/// Timestamp formatted last on a thread, see `TimestampCache`
+struct CachedTimestamp {
+    cache: u64,
+    /// Granule since the Unix epoch
+    granule: u128,
+    ts: Option<String>,
+}
+
+thread_local! {
+    static TL_TS: RefCell<Option<CachedTimestamp>> = RefCell::new(None)
+}
+
+impl TimestampCache {
+    fn new(granularity: Duration) -> Self {
+        TimestampCache {
+            id: NEXT_TIMESTAMP_CACHE.fetch_add(1, Ordering::Relaxed),
+            granularity: std::cmp::max(granularity.as_nanos(), 1),
+        }
+    }
+
+    /// Call `f` with the timestamp of `time` truncated to the granularity,
+    /// formatted with `format` unless it already was on this thread
+    fn with<F, G, R>(&self, time: SystemTime, format: F, f: G) -> R
+    where
+        F: Fn(SystemTime) -> Option<String>,
+        G: FnOnce(Option<&str>) -> R,
+    {
+        let since = match time.duration_since(std::time::UNIX_EPOCH) {
+            Ok(since) => since.as_nanos(),
+            Err(_) => return f(format(time).as_deref()),
+        };
+        let granule = since / self.granularity;
+        TL_TS.with(|last| {
+            let mut last = match last.try_borrow_mut() {
+                Ok(last) => last,
+                // `format` itself logging
+                Err(_) => return f(format(self.start(granule)).as_deref()),
+            };
+            let fresh = matches!(
+                *last,
+                Some(ref c) if c.cache == self.id && c.granule == granule
+            );
+            if !fresh {
+                *last = Some(CachedTimestamp {
+                    cache: self.id,
+                    granule,
+                    ts: format(self.start(granule)),
+                });
+            }
+            f(last.as_ref().and_then(|c| c.ts.as_deref()))
+        })
+    }
+
+    /// Start of `granule`
+    fn start(&self, granule: u128) -> SystemTime {
+        let start = granule * self.granularity;
+        std::time::UNIX_EPOCH
+            + Duration::new(
+                (start / 1_000_000_000) as u64,
+                (start % 1_000_000_000) as u32,
+            )
+    }
+}
+
+/// Clock of `JsonBuilder::add_monotonic_ts`
+struct MonotonicClock {
+    key: String,
+    base: Instant,
+    /// Last value read, to keep values strictly increasing
+    last: AtomicU64,
+}
+
+impl MonotonicClock {
+    fn new(key: &str) -> Self {
+        MonotonicClock {
+            key: key.to_owned(),
+            base: Instant::now(),
+            last: AtomicU64::new(0),
+        }
+    }
+
+    /// Nanoseconds since `base`, greater than any value read before
+    fn read(&self) -> u64 {
+        let now = self.base.elapsed().as_nanos() as u64;
+        let last = self
+            .last
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+                Some(std::cmp::max(now, last + 1))
+            })
+            .unwrap_or_default();
+        std::cmp::max(now, last + 1)
+    }
+}
+// }}}
+
+// {{{ Formatter
+/// `serde_json` formatter with the output options of `JsonBuilder`
+///
+/// Wraps the compact or pretty formatter to:
+/// * escape `/` in strings as `\/`, see
+///   `JsonBuilder::escape_forward_slashes`
+/// * leave identifier keys unquoted, see `JsonBuilder::json5_unquoted_keys`
+struct OutputFormatter<F> {
+    inner: F,
+    escape_slashes: bool,
+    json5_keys: bool,
+    /// Whether an object key is being written
+    in_key: bool,
+    /// Key being written, held back until it is known whether it needs
+    /// quotes, and whether it was escaped
+    key: Option<(Vec<u8>, bool)>,
+}
+
+impl<F> OutputFormatter<F>
+where
+    F: serde_json::ser::Formatter,
+{
+    fn new(inner: F, config: &JsonConfig) -> Self {
+        OutputFormatter {
+            inner,
+            escape_slashes: config.output.escape_slashes,
+            json5_keys: config.output.json5_keys,
+            in_key: false,
+            key: None,
+        }
+    }
+
+    fn write_fragment<W>(
+        &mut self,
+        writer: &mut W,
+        fragment: &str,
+    ) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        if !self.escape_slashes {
+            return self.inner.write_string_fragment(writer, fragment);
+        }
+        let mut parts = fragment.split('/');
+        if let Some(first) = parts.next() {
+            self.inner.write_string_fragment(writer, first)?;
+        }
+        for part in parts {
+            writer.write_all(b"\\/")?;
+            self.inner.write_string_fragment(writer, part)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `key` is an ECMAScript identifier name, left unquoted in JSON5
+fn is_identifier(key: &[u8]) -> bool {
+    let is_start = |b: u8| b.is_ascii_alphabetic() || b == b'_' || b == b'$';
+    match key.split_first() {
+        Some((&first, rest)) => {
+            is_start(first)
+                && rest.iter().all(|&b| is_start(b) || b.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+impl<F> serde_json::ser::Formatter for OutputFormatter<F>
+where
+    F: serde_json::ser::Formatter,
+{
+    fn begin_string<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        if self.in_key && self.json5_keys {
+            self.key = Some((vec![], false));
+            Ok(())
+        } else {
+            self.inner.begin_string(writer)
+        }
+    }
+
+    fn end_string<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        match self.key.take() {
+            Some((key, false)) if is_identifier(&key) => writer.write_all(&key),
+            Some((key, _)) => {
+                self.inner.begin_string(writer)?;
+                writer.write_all(&key)?;
+                self.inner.end_string(writer)
+            }
+            None => self.inner.end_string(writer),
+        }
+    }
+
+    fn write_string_fragment<W>(
+        &mut self,
+        writer: &mut W,
+        fragment: &str,
+    ) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        if let Some((mut key, escaped)) = self.key.take() {
+            let slash = self.escape_slashes && fragment.contains('/');
+            let res = self.write_fragment(&mut key, fragment);
+            self.key = Some((key, escaped || slash));
+            return res;
+        }
+        self.write_fragment(writer, fragment)
+    }
+
+    fn write_char_escape<W>(
+        &mut self,
+        writer: &mut W,
+        char_escape: serde_json::ser::CharEscape,
+    ) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        if let Some((mut key, _)) = self.key.take() {
+            let res = self.inner.write_char_escape(&mut key, char_escape);
+            self.key = Some((key, true));
+            return res;
+        }
+        self.inner.write_char_escape(writer, char_escape)
+    }
+
+    fn begin_array<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.inner.begin_array(writer)
+    }
+
+    fn end_array<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.inner.end_array(writer)
+    }
+
+    fn begin_array_value<W>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.inner.begin_array_value(writer, first)
+    }
+
+    fn end_array_value<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.inner.end_array_value(writer)
+    }
+
+    fn begin_object<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.inner.begin_object(writer)
+    }
+
+    fn end_object<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.inner.end_object(writer)
+    }
+
+    fn begin_object_key<W>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.in_key = true;
+        self.inner.begin_object_key(writer, first)
+    }
+
+    fn begin_object_value<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.in_key = false;
+        self.inner.begin_object_value(writer)
+    }
+
+    fn end_object_value<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        self.inner.end_object_value(writer)
+    }
+}
+// }}}
+
+// {{{ JsonConfig
+/// Key marking the start of a span, see `JsonBuilder::set_spans`
+pub const SPAN_KEY: &str = "span";
+
+/// Logger key of the subsystem, see `JsonBuilder::lift_subsystem`
+pub const SUBSYSTEM_KEY: &str = "_subsystem";
+
+/// Closure deriving the `kind` key, see `JsonBuilder::with_dynamic_kind`
+type KindFn = Arc<dyn Fn(&Record) -> Option<String> + Send + Sync>;
+
+/// Closure picking the writer of records, see
+/// `JsonBuilder::with_writer_selector`
+type WriterSelector =
+    Arc<dyn Fn(&Record, &OwnedKVList) -> io::Result<usize> + Send + Sync>;
+
+/// Predicate on records, see `JsonBuilder::with_record_filter`
+type RecordFilter = Arc<dyn Fn(&Record, &OwnedKVList) -> bool + Send + Sync>;
+
+/// Closure reading the current trace and span ids, see
+/// `JsonBuilder::with_otel_context_fn`
+type OtelContextFn = Arc<dyn Fn() -> Option<(String, String)> + Send + Sync>;
+
+/// Closure deriving labels, see `JsonBuilder::with_dynamic_labels`
+type LabelsFn = Arc<
+    dyn Fn(&Record) -> serde_json::Map<String, serde_json::Value> + Send + Sync,
+>;
+
+/// Names of the default keys, and format of their values
+#[derive(Clone)]
+struct DefaultKeys {
+    msg: Cow<'static, str>,
+    level: Cow<'static, str>,
+    ts: Cow<'static, str>,
+    level_format: LevelFormat,
+    /// Emit `ts` as seconds since the Unix epoch, with millisecond decimals
+    epoch_ts: bool,
+    /// Emit `ts` as a whole number since the Unix epoch, see
+    /// `JsonBuilder::set_ts_epoch`
+    epoch_precision: Option<EpochPrecision>,
+}
+
+impl Default for DefaultKeys {
+    fn default() -> Self {
+        DefaultKeys {
+            msg: "msg".into(),
+            level: "level".into(),
+            ts: "ts".into(),
+            level_format: LevelFormat::Short,
+            epoch_ts: false,
+            epoch_precision: None,
+        }
+    }
+}
+
+/// Format of the `level` default key
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LevelFormat {
+    /// `slog::Level::as_short_str`, like `INFO` or `WARN`
+    Short,
+    /// Lowercase word, like `info` or `warning`
+    Word,
+    /// Lowercase word of `tracing`, like `info` or `warn`
+    Tracing,
+    /// Syslog severity, from 2 (critical) to 7 (debug)
+    Syslog,
+    /// Bunyan level, from 60 (fatal) to 10 (trace)
+    Bunyan,
+}
+
+impl LevelFormat {
+    fn emit<S>(
+        self,
+        key: &str,
+        level: slog::Level,
+        serializer: &mut SerdeSerializer<'_, S>,
+    ) -> slog::Result
+    where
+        S: serde::Serializer,
+    {
+        use slog::Level::*;
+
+        match self {
+            LevelFormat::Short => {
+                serializer.emit_entry(key, level.as_short_str())
+            }
+            LevelFormat::Word => serializer.emit_entry(
+                key,
+                match level {
+                    Critical => "critical",
+                    Error => "error",
+                    Warning => "warning",
+                    Info => "info",
+                    Debug => "debug",
+                    Trace => "trace",
+                },
+            ),
+            LevelFormat::Tracing => serializer.emit_entry(
+                key,
+                match level {
+                    Critical | Error => "error",
+                    Warning => "warn",
+                    Info => "info",
+                    Debug => "debug",
+                    Trace => "trace",
+                },
+            ),
+            LevelFormat::Syslog => serializer.emit_entry(
+                key,
+                &match level {
+                    Critical => 2,
+                    Error => 3,
+                    Warning => 4,
+                    Info => 6,
+                    Debug | Trace => 7,
+                },
+            ),
+            LevelFormat::Bunyan => {
+                serializer.emit_entry(key, &(70 - 10 * level.as_usize()))
+            }
+        }
+    }
+}
+
+/// Key-values of a record to serialize
+#[derive(Clone, Copy)]
+enum Body<'a> {
+    Record(&'a Record<'a>, &'a OwnedKVList),
+    /// As buffered and restructured, see `JsonConfig::buffered_kvs`
+    Buffered(&'a [(String, serde_json::Value)]),
+}
+
+/// Format records are written in
+#[derive(Clone)]
+enum Format {
+    Json,
+    Logfmt,
+    Canonical,
+    #[cfg(feature = "yaml")]
+    Yaml,
+    #[cfg(feature = "serializer-factory")]
+    Factory(Arc<dyn SerializerFactory + Send + Sync>),
+}
+
+/// How records are laid out in the output
+struct OutputOptions {
+    format: Format,
+    newlines: bool,
+    leading_comma: bool,
+    /// Byte written ahead of every record
+    type_tag: Option<u8>,
+    flush: bool,
+    pretty: bool,
+    escape_slashes: bool,
+    json5_keys: bool,
+    bom: bool,
+    /// Every how many records an offset is kept, see
+    /// `JsonBuilder::with_seek_index`
+    seek_every: Option<usize>,
+    seal: Option<HashAlgo>,
+    discard_errors: bool,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            format: Format::Json,
+            newlines: true,
+            leading_comma: false,
+            type_tag: None,
+            flush: false,
+            pretty: false,
+            escape_slashes: false,
+            json5_keys: false,
+            bom: false,
+            seek_every: None,
+            seal: None,
+            discard_errors: false,
+        }
+    }
+}
+
+/// The `msg`, `level` and `ts` default keys
+#[derive(Default)]
+struct MessageOptions {
+    /// Whether the drain emits the default keys itself, rather than
+    /// `add_default_keys` adding them as `FnValue`s to `values`: their
+    /// names, formats and placement depend on other options of the
+    /// builder, including ones set after `add_default_keys`
+    default_keys: bool,
+    /// Number of `values` emitted ahead of the default keys, added before
+    /// them
+    default_keys_at: usize,
+    default_key_names: DefaultKeys,
+    layout: Layout,
+    collapse_msg_newlines: bool,
+    trim_msg: bool,
+    /// Emitted as `msg` instead of messages that are only whitespace
+    blank_msg: Option<String>,
+    /// Length in characters of the `summary` key, if added
+    summary_len: Option<usize>,
+    level_debug_info: bool,
+    protect_reserved: bool,
+}
+
+/// Timestamps, and the clock they are read from
+struct TimeOptions {
+    timestamp_format: TimestampFormat,
+    timestamp_fn: Option<TimestampFn>,
+    timestamp_cache: Option<TimestampCache>,
+    /// Time epoch timestamps count from
+    epoch_origin: SystemTime,
+    kibana_timestamps: bool,
+    ecs_timestamps: bool,
+    delta_timestamps: bool,
+    /// Milliseconds since the Unix epoch `dt_ms` keys are relative to, set
+    /// when building
+    delta_base_ms: Option<i64>,
+    monotonic_ts: Option<MonotonicClock>,
+    clock: ClockFn,
+}
+
+impl Default for TimeOptions {
+    fn default() -> Self {
+        TimeOptions {
+            timestamp_format: TimestampFormat::default(),
+            timestamp_fn: None,
+            timestamp_cache: None,
+            epoch_origin: std::time::UNIX_EPOCH,
+            kibana_timestamps: false,
+            ecs_timestamps: false,
+            delta_timestamps: false,
+            delta_base_ms: None,
+            monotonic_ts: None,
+            clock: Arc::new(SystemTime::now),
+        }
+    }
+}
+
+/// Key-values added to every record, besides the default keys
+#[derive(Default)]
+struct FieldOptions {
+    values: Vec<OwnedKVList>,
+    /// Key-values computed once, when building
+    constants: Vec<(String, serde_json::Value)>,
+    kind: Option<KindFn>,
+    otel_context: Option<OtelContextFn>,
+    labels: serde_json::Map<String, serde_json::Value>,
+    labels_fn: Option<LabelsFn>,
+    hostname: Option<CachedHostname>,
+    record_index: bool,
+    source_location: bool,
+    line_as_string: bool,
+    #[cfg(feature = "cpu-time")]
+    cpu_time: bool,
+    spans: bool,
+    lift_subsystem: bool,
+    mdc: bool,
+    /// Key to add a random UUID under, unless logged
+    #[cfg(feature = "uuid")]
+    correlation_key: Option<String>,
+}
+
+/// Changes to the key-values of records, which are buffered to be made
+#[derive(Default)]
+struct RestructureOptions {
+    stable_output: bool,
+    unflatten: bool,
+    collapse_duplicates: bool,
+    fields_count: bool,
+    distinct_key_count: bool,
+    duplicate_keys: DuplicateKeyPolicy,
+}
+
+/// Keys describing the JSON output of records, computed while writing it
+#[derive(Default)]
+struct TrackingOptions {
+    record_size: bool,
+    serialize_timing: bool,
+    content_hash: Option<HashAlgo>,
+}
+
+/// Which records are written at all
+#[derive(Default)]
+struct AdmissionOptions {
+    rate_limiter: Option<RateLimiter>,
+    level_summary: Option<LevelSummary>,
+    record_filter: Option<RecordFilter>,
+    #[cfg(feature = "jsonschema")]
+    schema: Option<SchemaValidator>,
+}
+
+/// Formatting configuration of a `Json` `Drain`, without its `io`
+///
+/// Create with `JsonBuilder::build_config`. Used by drains picking one of
+/// several configurations per record, like `TieredJson`.
+pub struct JsonConfig {
+    output: OutputOptions,
+    message: MessageOptions,
+    time: TimeOptions,
+    fields: FieldOptions,
+    restructure: RestructureOptions,
+    tracking: TrackingOptions,
+    admission: AdmissionOptions,
+    serde: SerdeOptions,
+}
+
+impl JsonConfig {
+    fn new() -> Self {
+        JsonConfig {
+            output: OutputOptions::default(),
+            message: MessageOptions::default(),
+            time: TimeOptions::default(),
+            fields: FieldOptions::default(),
+            restructure: RestructureOptions::default(),
+            tracking: TrackingOptions::default(),
+            admission: AdmissionOptions::default(),
+            serde: SerdeOptions::default(),
+        }
+    }
+
+    fn log_impl<W, F>(
+        &self,
+        serializer: &mut serde_json::ser::Serializer<&mut W, F>,
+        index: u64,
+        tracker: Option<&Tracker>,
+        body: Body<'_>,
+    ) -> io::Result<()>
+    where
+        W: io::Write,
+        F: serde_json::ser::Formatter,
+    {
+        let mut serializer =
+            SerdeSerializer::start(&mut *serializer, None, &self.serde)?;
+
+        match body {
+            Body::Record(rinfo, logger_values) => self.emit_body(
+                index,
+                tracker,
+                rinfo,
+                logger_values,
+                &mut serializer,
+            )?,
+            Body::Buffered(kvs) => {
+                for (k, v) in kvs {
+                    self.emit_buffered(k, v, tracker, &mut serializer)?;
+                }
+            }
+        }
+
+        if let Some(id) = tracker.and_then(Tracker::hash) {
+            serializer.emit_entry("event_id", &id)?;
+        }
+
+        if let (true, Some(tracker)) = (self.tracking.serialize_timing, tracker)
+        {
+            let ns = tracker.started.elapsed().as_nanos() as u64;
+            serializer.emit_entry("serialize_ns", &ns)?;
+        }
+
+        if let (true, Some(tracker)) = (self.tracking.record_size, tracker) {
+            // The closing brace is still to come, on its own line if pretty
+            let closing = if self.output.pretty { 2 } else { 1 };
+            let size = tracker.written.get() + closing;
+            serializer.emit_entry("record_bytes", &size)?;
+        }
+
+        let res = serializer.end();
+
+        res.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+        Ok(())
+    }
+
+    /// Emit every key-value of the record, but the ones computed from the
+    /// serialized output
+    fn emit_body<S>(
+        &self,
+        index: u64,
+        tracker: Option<&Tracker>,
+        rinfo: &Record,
+        logger_values: &OwnedKVList,
+        serializer: &mut SerdeSerializer<'_, S>,
+    ) -> slog::Result
+    where
+        S: serde::Serializer,
+    {
+        // Serialized ahead, for the subsystem to be found
+        let logger_kvs = if self.fields.lift_subsystem && !self.fields.spans {
+            let mut collector = KvCollector::new(&self.serde);
+            let res = logger_values.serialize(rinfo, &mut collector);
+            self.serde.soften(res)?;
+            Some(collector.into_kvs())
+        } else {
+            None
+        };
+
+        let (early_values, values) =
+            self.fields.values.split_at(self.message.default_keys_at);
+        for kv in early_values {
+            self.serde.soften(kv.serialize(rinfo, serializer))?;
+        }
+
+        if self.message.default_keys {
+            self.emit_default_keys(rinfo, tracker, serializer)?;
+        }
+
+        if self.fields.lift_subsystem {
+            self.emit_subsystem(
+                rinfo,
+                logger_values,
+                logger_kvs.as_deref(),
+                serializer,
+            )?;
+        }
+
+        if self.time.kibana_timestamps {
+            let now = time::OffsetDateTime::from(self.now());
+            let ts = TimestampFormat::Rfc3339.format(now);
+            let ms = (now.unix_timestamp_nanos() / 1_000_000) as i64;
+            Tracker::volatile(tracker, || {
+                serializer.emit_entry("@timestamp", &ts)?;
+                serializer.emit_entry("timestamp_ms", &ms)
+            })?;
+        }
+
+        if self.time.ecs_timestamps && !self.time.kibana_timestamps {
+            let now = time::OffsetDateTime::from(self.now());
+            let ts = TimestampFormat::Rfc3339.format(now);
+            Tracker::volatile(tracker, || {
+                serializer.emit_entry("@timestamp", &ts)
+            })?;
+        }
+
+        if let Some(base) = self.time.delta_base_ms {
+            let dt = unix_ms(self.now()) - base;
+            Tracker::volatile(tracker, || serializer.emit_entry("dt_ms", &dt))?;
+        }
+
+        if let Some(ref clock) = self.time.monotonic_ts {
+            let ns = clock.read();
+            Tracker::volatile(tracker, || {
+                serializer.emit_entry(&clock.key, &ns)
+            })?;
+        }
+
+        if let Some(kind) = self.fields.kind.as_ref().and_then(|f| f(rinfo)) {
+            serializer.emit_entry("kind", &kind)?;
+        }
+
+        if let Some((trace_id, span_id)) =
+            self.fields.otel_context.as_ref().and_then(|f| f())
+        {
+            serializer.emit_entry("trace.id", &trace_id)?;
+            serializer.emit_entry("span.id", &span_id)?;
+        }
+
+        if self.fields.record_index {
+            Tracker::volatile(tracker, || {
+                serializer.emit_entry("record_index", &index)
+            })?;
+        }
+
+        if self.fields.source_location {
+            serializer.emit_entry("file", rinfo.file())?;
+            if self.fields.line_as_string {
+                serializer.emit_entry("line", &rinfo.line().to_string())?;
+            } else {
+                serializer.emit_entry("line", &rinfo.line())?;
+            }
+        }
+
+        #[cfg(feature = "cpu-time")]
+        if self.fields.cpu_time {
+            let cpu_ms = cpu_time::thread_cpu_ms();
+            Tracker::volatile(tracker, || {
+                serializer.emit_entry("cpu_ms", &cpu_ms)
+            })?;
+        }
+
+        for kv in values {
+            self.serde.soften(kv.serialize(rinfo, serializer))?;
+        }
+
+        for (k, v) in &self.fields.constants {
+            serializer.emit_entry(k, v)?;
+        }
+
+        if let Some(ref hostname) = self.fields.hostname {
+            serializer.emit_entry("hostname", &hostname.get())?;
+        }
+
+        if let Some(ref f) = self.fields.labels_fn {
+            let mut labels = self.fields.labels.clone();
+            labels.extend(f(rinfo));
+            serializer.emit_entry("labels", &labels)?;
+        } else if !self.fields.labels.is_empty() {
+            serializer.emit_entry("labels", &self.fields.labels)?;
+        }
+
+        if self.fields.mdc {
+            mdc::with(|mdc| {
+                mdc.iter()
+                    .try_for_each(|(k, v)| serializer.emit_user_entry(k, v))
+            })?;
+        }
+
+        if self.fields.spans {
+            self.emit_spans(rinfo, logger_values, serializer)?;
+        } else if let Some(kvs) = logger_kvs {
+            for (k, v) in kvs.iter().filter(|(k, _)| k != SUBSYSTEM_KEY) {
+                serializer.emit_raw_entry(&self.serde.user_key(k)?, v)?;
+            }
+        } else {
+            let res = logger_values.serialize(rinfo, serializer);
+            self.serde.soften(res)?;
+        }
+
+        let res = rinfo.kv().serialize(rinfo, serializer);
+        self.serde.soften(res)?;
+
+        if self.time.ecs_timestamps {
+            let now = time::OffsetDateTime::from(self.now());
+            let created = TimestampFormat::Rfc3339.format(now);
+            Tracker::volatile(tracker, || {
+                serializer.emit_entry("event.created", &created)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Whether `key` is the one of `JsonBuilder::add_monotonic_ts`, once
+    /// transformed
+    fn is_monotonic_ts(&self, key: &str) -> bool {
+        self.time
+            .monotonic_ts
+            .as_ref()
+            .map_or(false, |clock| self.serde.key(&clock.key) == key)
+    }
+
+    /// Emit the default keys after the `values` added so far
+    fn set_default_keys(&mut self) {
+        if !self.message.default_keys {
+            self.message.default_keys = true;
+            self.message.default_keys_at = self.fields.values.len();
+        }
+    }
+
+    /// Whether other keys are emitted in place of the `ts` default key
+    fn replaces_ts(&self) -> bool {
+        self.time.kibana_timestamps
+            || self.time.ecs_timestamps
+            || self.time.delta_base_ms.is_some()
+    }
+
+    /// Write what precedes the first record: the byte order mark of
+    /// `JsonBuilder::with_bom` and the header of
+    /// `JsonBuilder::delta_timestamps`
+    fn write_preamble<W>(&self, io: &mut W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        if self.output.bom {
+            io.write_all(UTF8_BOM)?;
+        }
+        if let Some(base) = self.time.delta_base_ms {
+            let ts = time::OffsetDateTime::from_unix_timestamp_nanos(
+                i128::from(base) * 1_000_000,
+            )
+            .ok()
+            .and_then(|ts| TimestampFormat::Rfc3339.format(ts));
+            let header = serde_json::json!({
+                "base_ts": ts,
+                "base_ts_ms": base,
+            });
+            serde_json::to_writer(&mut *io, &header)?;
+            io.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn is_yaml(&self) -> bool {
+        #[cfg(feature = "yaml")]
+        return matches!(self.output.format, Format::Yaml);
+        #[cfg(not(feature = "yaml"))]
+        return false;
+    }
+
+    fn has_correlation_id(&self) -> bool {
+        #[cfg(feature = "uuid")]
+        return self.fields.correlation_key.is_some();
+        #[cfg(not(feature = "uuid"))]
+        return false;
+    }
+
+    fn has_schema(&self) -> bool {
+        #[cfg(feature = "jsonschema")]
+        return self.admission.schema.is_some();
+        #[cfg(not(feature = "jsonschema"))]
+        return false;
+    }
+
+    /// Buffer the output of `emit_body`, see `JsonBuilder::stable_output`
+    /// and `JsonBuilder::unflatten_dotted_keys`
+    fn collect_body(
+        &self,
+        index: u64,
+        rinfo: &Record,
+        logger_values: &OwnedKVList,
+    ) -> io::Result<Vec<(String, serde_json::Value)>> {
+        let mut buf = Vec::with_capacity(256);
+        let mut ser = serde_json::Serializer::new(&mut buf);
+        let mut serializer =
+            SerdeSerializer::start(&mut ser, None, &self.serde)?;
+        self.emit_body(index, None, rinfo, logger_values, &mut serializer)?;
+        serializer
+            .end()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+        let KvPairs(kvs) = serde_json::from_slice(&buf)?;
+        Ok(kvs)
+    }
+
+    /// Whether records are buffered before being emitted, to be looked at
+    /// as a whole
+    fn is_buffered(&self) -> bool {
+        self.restructure.stable_output
+            || self.restructure.unflatten
+            || self.restructure.collapse_duplicates
+            || self.restructure.fields_count
+            || self.restructure.distinct_key_count
+            || self.restructure.duplicate_keys != DuplicateKeyPolicy::EmitAll
+            || self.message.layout.is_buffered()
+            || self.has_correlation_id()
+            || self.has_schema()
+            || !matches!(self.output.format, Format::Json)
+    }
+
+    /// Key-values of a record, as buffered and restructured
+    fn buffered_kvs(
+        &self,
+        index: u64,
+        rinfo: &Record,
+        logger_values: &OwnedKVList,
+    ) -> io::Result<Vec<(String, serde_json::Value)>> {
+        let mut kvs = self.collect_body(index, rinfo, logger_values)?;
+        if self.restructure.collapse_duplicates {
+            kvs = collapse_identical(kvs);
+        }
+        if self.restructure.duplicate_keys == DuplicateKeyPolicy::Suffix {
+            kvs = suffix_duplicates(kvs);
+        }
+        #[cfg(feature = "uuid")]
+        if let Some(ref key) = self.fields.correlation_key {
+            let key = self.serde.key(key);
+            if !kvs.iter().any(|(k, _)| *k == key) {
+                let id = uuid::Uuid::new_v4().to_string();
+                kvs.push((key.into_owned(), id.into()));
+            }
+        }
+        if self.restructure.fields_count || self.restructure.distinct_key_count
+        {
+            let keys = self.logged_keys(rinfo, logger_values);
+            if self.restructure.fields_count {
+                kvs.push(("fields_count".into(), keys.len().into()));
+            }
+            if self.restructure.distinct_key_count {
+                let distinct: HashSet<_> = keys.into_iter().collect();
+                kvs.push(("distinct_keys".into(), distinct.len().into()));
+            }
+        }
+        if self.restructure.unflatten {
+            kvs = unflatten_dotted(kvs);
+        }
+        kvs = self
+            .message
+            .layout
+            .apply(&self.message.default_key_names, kvs);
+        if self.restructure.stable_output {
+            kvs = self.stable_order(kvs);
+        }
+        Ok(kvs)
+    }
+
+    /// Keys of the key-values of the record and its loggers, once per
+    /// key-value, see `JsonBuilder::add_fields_count`
+    fn logged_keys(
+        &self,
+        rinfo: &Record,
+        logger_values: &OwnedKVList,
+    ) -> Vec<String> {
+        let mut collector = KvCollector::new(&self.serde);
+        let _ = logger_values.serialize(rinfo, &mut collector);
+        let _ = rinfo.kv().serialize(rinfo, &mut collector);
+        collector
+            .into_kvs()
+            .into_iter()
+            .map(|(k, _)| k)
+            .filter(|k| k != SPAN_KEY)
+            .collect()
+    }
+
+    /// `ts`, `level` and `msg`, then the other key-values sorted by key
+    fn stable_order(
+        &self,
+        kvs: Vec<(String, serde_json::Value)>,
+    ) -> Vec<(String, serde_json::Value)> {
+        // Keeps the value logged last of a key logged more than once
+        let mut kvs: serde_json::Map<_, _> = kvs.into_iter().collect();
+        let names = &self.message.default_key_names;
+        let mut out = vec![];
+        for key in [&names.ts, &names.level, &names.msg].iter() {
+            if let Some(v) = kvs.remove(key.as_ref()) {
+                out.push((key.to_string(), v));
+            }
+        }
+
+        // Sorted here, as `serde_json::Map` keeps insertion order when its
+        // `preserve_order` feature is enabled anywhere in the build
+        let mut rest: Vec<_> = kvs.into_iter().collect();
+        rest.sort_by(|(a, _), (b, _)| a.cmp(b));
+        out.extend(rest);
+        out
+    }
+
+    /// Emit a key-value buffered by `collect_body`
+    fn emit_buffered<S>(
+        &self,
+        key: &str,
+        val: &serde_json::Value,
+        tracker: Option<&Tracker>,
+        serializer: &mut SerdeSerializer<'_, S>,
+    ) -> slog::Result
+    where
+        S: serde::Serializer,
+    {
+        let volatile = match key {
+            _ if key == self.message.default_key_names.ts => {
+                self.message.default_keys && !self.replaces_ts()
+            }
+            "@timestamp" => {
+                self.time.kibana_timestamps || self.time.ecs_timestamps
+            }
+            "timestamp_ms" => self.time.kibana_timestamps,
+            "event.created" => self.time.ecs_timestamps,
+            "dt_ms" => self.time.delta_base_ms.is_some(),
+            _ if self.is_monotonic_ts(key) => true,
+            "record_index" => self.fields.record_index,
+            #[cfg(feature = "cpu-time")]
+            "cpu_ms" => self.fields.cpu_time,
+            _ => false,
+        };
+        if volatile {
+            Tracker::volatile(tracker, || serializer.emit_raw_entry(key, val))
+        } else {
+            serializer.emit_raw_entry(key, val)
+        }
+    }
+
+    fn serialize<W>(
+        &self,
+        io: &mut W,
+        index: u64,
+        tracker: Option<&Tracker>,
+        body: Body<'_>,
+    ) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        use serde_json::ser::{CompactFormatter, PrettyFormatter};
+        use serde_json::Serializer;
+
+        if !self.output.escape_slashes && !self.output.json5_keys {
+            return if self.output.pretty {
+                let mut serializer = Serializer::pretty(io);
+                self.log_impl(&mut serializer, index, tracker, body)
+            } else {
+                let mut serializer = Serializer::new(io);
+                self.log_impl(&mut serializer, index, tracker, body)
+            };
+        }
+
+        if self.output.pretty {
+            let formatter = OutputFormatter::new(PrettyFormatter::new(), self);
+            let mut serializer = Serializer::with_formatter(io, formatter);
+            self.log_impl(&mut serializer, index, tracker, body)
+        } else {
+            let formatter = OutputFormatter::new(CompactFormatter, self);
+            let mut serializer = Serializer::with_formatter(io, formatter);
+            self.log_impl(&mut serializer, index, tracker, body)
+        }
+    }
+
+    fn write_record<W>(
+        &self,
+        io: &mut W,
+        index: u64,
+        rinfo: &Record,
+        logger_values: &OwnedKVList,
+    ) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        if self.is_buffered() {
+            let kvs = self.buffered_kvs(index, rinfo, logger_values)?;
+            return self.write_kvs(io, index, &kvs);
+        }
+        if let Some(tag) = self.output.type_tag {
+            io.write_all(&[tag])?;
+        }
+        self.write_json(io, index, Body::Record(rinfo, logger_values))
+    }
+
+    /// Write a record from its buffered key-values, see `buffered_kvs`
+    fn write_kvs<W>(
+        &self,
+        io: &mut W,
+        index: u64,
+        kvs: &[(String, serde_json::Value)],
+    ) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        if let Some(tag) = self.output.type_tag {
+            io.write_all(&[tag])?;
+        }
+        let mut out = Vec::with_capacity(256);
+        match self.output.format {
+            Format::Json => {
+                return self.write_json(io, index, Body::Buffered(kvs))
+            }
+            Format::Logfmt => logfmt::write_record(&mut out, kvs),
+            Format::Canonical => canonical::write_object(&mut out, kvs)?,
+            #[cfg(feature = "yaml")]
+            Format::Yaml => yaml::write_document(&mut out, kvs)?,
+            #[cfg(feature = "serializer-factory")]
+            Format::Factory(ref factory) => {
+                factory::write_record(&**factory, &mut out, kvs)?
+            }
+        }
+        // YAML documents end with a newline of their own
+        if self.output.newlines && !self.is_yaml() {
+            out.push(b'\n');
+        }
+        io.write_all(&out)?;
+        if self.output.flush {
+            io.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write a record as JSON, followed by its newline
+    fn write_json<W>(
+        &self,
+        io: &mut W,
+        index: u64,
+        body: Body<'_>,
+    ) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        if self.tracking.record_size
+            || self.tracking.content_hash.is_some()
+            || self.tracking.serialize_timing
+        {
+            let tracker = Tracker::new(self.tracking.content_hash);
+            let mut tracking = TrackingWriter {
+                io: &mut *io,
+                tracker: &tracker,
+            };
+            let tracker = Some(&tracker);
+            self.serialize(&mut tracking, index, tracker, body)?;
+        } else {
+            self.serialize(io, index, None, body)?;
+        }
+        if self.output.newlines {
+            io.write_all("\n".as_bytes())?;
+        }
+        if self.output.flush {
+            io.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Emit logger values grouped by `SPAN_KEY`, see `JsonBuilder::set_spans`
+    fn emit_spans<S>(
+        &self,
+        rinfo: &Record,
+        logger_values: &OwnedKVList,
+        serializer: &mut SerdeSerializer<'_, S>,
+    ) -> slog::Result
+    where
+        S: serde::Serializer,
+    {
+        let mut collector = KvCollector::new(&self.serde);
+        let res = logger_values.serialize(rinfo, &mut collector);
+        self.serde.soften(res)?;
+
+        // Logger values are serialized innermost scope first, and the keys
+        // of each scope last to first, so a scope ends at its marker.
+        let mut spans = vec![];
+        let mut scope = serde_json::Map::new();
+        for (k, v) in collector.into_kvs() {
+            if self.fields.lift_subsystem && k == SUBSYSTEM_KEY {
+                continue;
+            }
+            let is_marker = k == SPAN_KEY;
+            scope.insert(self.serde.user_key(&k)?.into_owned(), v);
+            if is_marker {
+                spans.push(serde_json::Value::Object(scope));
+                scope = serde_json::Map::new();
+            }
+        }
+        spans.reverse();
+
+        for (k, v) in &scope {
+            serializer.emit_raw_entry(k, v)?;
+        }
+        serializer.emit_entry("spans", &spans)
+    }
+
+    /// Emit the `subsystem` key, from the innermost logger with one, see
+    /// `JsonBuilder::lift_subsystem`
+    ///
+    /// `logger_kvs` are the key-values of the loggers, if already
+    /// collected.
+    fn emit_subsystem<S>(
+        &self,
+        rinfo: &Record,
+        logger_values: &OwnedKVList,
+        logger_kvs: Option<&[(String, serde_json::Value)]>,
+        serializer: &mut SerdeSerializer<'_, S>,
+    ) -> slog::Result
+    where
+        S: serde::Serializer,
+    {
+        let collected;
+        let kvs = match logger_kvs {
+            Some(kvs) => kvs,
+            None => {
+                let mut collector = KvCollector::new(&self.serde);
+                let _ = logger_values.serialize(rinfo, &mut collector);
+                collected = collector.into_kvs();
+                &collected
+            }
+        };
+        // Innermost loggers come first
+        match kvs.iter().find(|(k, _)| k == SUBSYSTEM_KEY) {
+            Some((_, subsystem)) => {
+                serializer.emit_entry("subsystem", subsystem)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Emit `msg`, `level` and `ts`, see `JsonBuilder::add_default_keys`
+    fn emit_default_keys<S>(
+        &self,
+        rinfo: &Record,
+        tracker: Option<&Tracker>,
+        serializer: &mut SerdeSerializer<'_, S>,
+    ) -> slog::Result
+    where
+        S: serde::Serializer,
+    {
+        let names = &self.message.default_key_names;
+        TL_BUF.with(|buf| {
+            let mut buf = buf.borrow_mut();
+
+            buf.write_fmt(*rinfo.msg()).unwrap();
+
+            let msg = match self.message.blank_msg {
+                Some(ref placeholder) if buf.trim().is_empty() => placeholder,
+                _ if self.message.trim_msg => buf.trim(),
+                _ => &buf,
+            };
+            let msg = if self.message.collapse_msg_newlines {
+                Cow::Owned(collapse_newlines(msg))
+            } else {
+                Cow::Borrowed(msg)
+            };
+            let res =
+                serializer.emit_entry(&names.msg, &*msg).and_then(
+                    |_| match self.message.summary_len {
+                        Some(len) => {
+                            let end = msg.char_indices().nth(len);
+                            let summary =
+                                end.map_or(&*msg, |(end, _)| &msg[..end]);
+                            serializer.emit_entry("summary", summary)
+                        }
+                        None => Ok(()),
+                    },
+                );
+            buf.clear();
+            res
+        })?;
+
+        names
+            .level_format
+            .emit(&names.level, rinfo.level(), serializer)?;
+        if self.message.level_debug_info {
+            serializer.emit_entry("level_usize", &rinfo.level().as_usize())?;
+        }
+        if self.replaces_ts() {
+            return Ok(());
+        }
+
+        if let Some(precision) = names.epoch_precision {
+            let ts = precision.since(self.time.epoch_origin, self.now());
+            return Tracker::volatile(tracker, || {
+                serializer.emit_entry(&names.ts, &ts)
+            });
+        }
+        if names.epoch_ts {
+            let ms = ms_since(self.time.epoch_origin, self.now());
+            let secs = ms as f64 / 1000.0;
+            return Tracker::volatile(tracker, || {
+                serializer.emit_entry(&names.ts, &secs)
+            });
+        }
+
+        let now = self.now();
+        let mut emit = |ts: Option<&str>| {
+            Tracker::volatile(tracker, || serializer.emit_entry(&names.ts, &ts))
+        };
+        match self.time.timestamp_cache {
+            Some(ref cache) => {
+                cache.with(now, |time| self.format_ts(time), emit)
+            }
+            None => emit(self.format_ts(now).as_deref()),
+        }
+    }
+
+    /// Current time, as read from the clock of `JsonBuilder::with_clock`
+    fn now(&self) -> SystemTime {
+        (self.time.clock)()
+    }
+
+    /// Value of the `ts` default key at `time`, as a string
+    fn format_ts(&self, time: SystemTime) -> Option<String> {
+        match self.time.timestamp_fn {
+            Some(ref f) => Some(f(time)),
+            None => self.time.timestamp_format.format(time.into()),
+        }
+    }
+}
+
+/// Drop key-values identical to an earlier one, see
+/// `JsonBuilder::collapse_identical_duplicates`
+fn collapse_identical(
+    kvs: Vec<(String, serde_json::Value)>,
+) -> Vec<(String, serde_json::Value)> {
+    let mut out: Vec<(String, serde_json::Value)> =
+        Vec::with_capacity(kvs.len());
+    for kv in kvs {
+        if !out.contains(&kv) {
+            out.push(kv);
+        }
+    }
+    out
+}
+
+/// What to do with keys emitted more than once in a record, see
+/// `JsonBuilder::duplicate_key_policy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DuplicateKeyPolicy {
+    /// Emit every key-value as is, the default
+    EmitAll,
+    /// Rename the repeats of a key `key_2`, `key_3` and so on, in order
+    Suffix,
+}
+
+impl Default for DuplicateKeyPolicy {
+    fn default() -> Self {
+        DuplicateKeyPolicy::EmitAll
+    }
+}
+
+/// Rename the repeats of keys, see `DuplicateKeyPolicy::Suffix`
+fn suffix_duplicates(
+    kvs: Vec<(String, serde_json::Value)>,
+) -> Vec<(String, serde_json::Value)> {
+    let logged: HashSet<String> = kvs.iter().map(|(k, _)| k.clone()).collect();
+    let mut taken = HashSet::with_capacity(kvs.len());
+    let mut out = Vec::with_capacity(kvs.len());
+    for (key, val) in kvs {
+        let key = if taken.contains(&key) {
+            // Skips suffixed keys that were logged as such
+            (2..)
+                .map(|n| format!("{}_{}", key, n))
+                .find(|k| !taken.contains(k) && !logged.contains(k))
+                .unwrap()
+        } else {
+            key
+        };
+        taken.insert(key.clone());
+        out.push((key, val));
+    }
+    out
+}
+
+/// Hash algorithm of `JsonBuilder::content_hash_id`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HashAlgo {
+    /// 64-bit FNV-1a, emitted as 16 hex digits
+    Fnv1a64,
+}
+
+impl HashAlgo {
+    fn initial(self) -> u64 {
+        match self {
+            HashAlgo::Fnv1a64 => 0xcbf2_9ce4_8422_2325,
+        }
+    }
+
+    fn update(self, hash: u64, bytes: &[u8]) -> u64 {
+        match self {
+            HashAlgo::Fnv1a64 => bytes.iter().fold(hash, |hash, &b| {
+                (hash ^ u64::from(b)).wrapping_mul(0x100_0000_01b3)
+            }),
+        }
+    }
+}
+
+/// Measurements of the record being written, see `TrackingWriter`
+struct Tracker {
+    /// Bytes written so far
+    written: Cell<usize>,
+    /// Running hash of the bytes written so far, but volatile ones
+    hash: Option<(HashAlgo, Cell<u64>)>,
+    /// Whether volatile entries are being written
+    volatile: Cell<bool>,
+    /// When the record started being written
+    started: Instant,
+}
+
+impl Tracker {
+    fn new(hash: Option<HashAlgo>) -> Self {
+        Tracker {
+            written: Cell::new(0),
+            hash: hash.map(|algo| (algo, Cell::new(algo.initial()))),
+            volatile: Cell::new(false),
+            started: Instant::now(),
+        }
+    }
+
+    /// Tracker carrying on with a running `hash`
+    fn resume(algo: HashAlgo, hash: u64) -> Self {
+        Tracker {
+            written: Cell::new(0),
+            hash: Some((algo, Cell::new(hash))),
+            volatile: Cell::new(false),
+            started: Instant::now(),
+        }
+    }
+
+    fn track(&self, buf: &[u8]) {
+        self.written.set(self.written.get() + buf.len());
+        if let (Some((algo, hash)), false) = (&self.hash, self.volatile.get()) {
+            hash.set(algo.update(hash.get(), buf));
+        }
+    }
+
+    /// Hash so far, as hex
+    fn hash(&self) -> Option<String> {
+        self.hash_value().map(|hash| format!("{:016x}", hash))
+    }
+
+    fn hash_value(&self) -> Option<u64> {
+        self.hash.as_ref().map(|(_, hash)| hash.get())
+    }
+
+    /// Run `f`, writing entries that are left out of the hash
+    ///
+    /// Those change on every record, like the timestamp.
+    fn volatile<F, R>(tracker: Option<&Tracker>, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        if let Some(tracker) = tracker {
+            tracker.volatile.set(true);
+        }
+        let res = f();
+        if let Some(tracker) = tracker {
+            tracker.volatile.set(false);
+        }
+        res
+    }
+}
+
+/// `io::Write` tracking the bytes written through it
+struct TrackingWriter<'a, W: io::Write> {
+    io: W,
+    tracker: &'a Tracker,
+}
+
+impl<'a, W> io::Write for TrackingWriter<'a, W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.io.write(buf)?;
+        self.tracker.track(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+/// Replace line breaks (`\n`, `\r\n` and `\r`) in `s` with a space
+fn collapse_newlines(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' => {
+                if chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+                out.push(' ');
+            }
+            '\n' => out.push(' '),
+            c => out.push(c),
+        }
+    }
+    out
+}
+// }}}
+
+// {{{ DrainState
+/// Byte order mark written ahead of the first record, see
+/// `JsonBuilder::with_bom`
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// State of a drain shared by all its `JsonConfig`s
+struct DrainState {
+    dropped: AtomicU64,
+    index: AtomicU64,
+    /// Whether the first record was logged
+    started: AtomicBool,
+    /// Records written so far and their running hash, see
+    /// `JsonBuilder::with_sealed_trailer`
+    seal: Mutex<Option<Seal>>,
+    seek: SeekIndex,
+}
+
+struct Seal {
+    count: u64,
+    hash: u64,
+}
+
+/// Line written by a drain
+#[derive(Clone, Copy)]
+enum Line<'a> {
+    Record(&'a Record<'a>, &'a OwnedKVList),
+    /// Key-values of a record, as buffered and restructured
+    #[cfg(feature = "jsonschema")]
+    Buffered(&'a [(String, serde_json::Value)]),
+    /// Records logged at each level, see `JsonBuilder::with_level_summary`
+    LevelSummary(&'a serde_json::Map<String, serde_json::Value>),
+}
+
+impl Line<'_> {
+    fn write<W>(
+        self,
+        config: &JsonConfig,
+        io: &mut W,
+        index: u64,
+    ) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        match self {
+            Line::Record(rinfo, logger_values) => {
+                config.write_record(io, index, rinfo, logger_values)
+            }
+            #[cfg(feature = "jsonschema")]
+            Line::Buffered(kvs) => config.write_kvs(io, index, kvs),
+            Line::LevelSummary(counts) => {
+                if let Some(tag) = config.output.type_tag {
+                    io.write_all(&[tag])?;
+                }
+                let summary = serde_json::json!({ "summary": counts });
+                serde_json::to_writer(&mut *io, &summary)?;
+                if config.output.newlines {
+                    io.write_all(b"\n")?;
+                }
+                if config.output.flush {
+                    io.flush()?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Offsets of records, see `JsonBuilder::with_seek_index`
+struct SeekIndex {
+    /// Bytes written so far
+    written: AtomicU64,
+    records: AtomicU64,
+    offsets: Mutex<Vec<u64>>,
+}
+
+impl SeekIndex {
+    /// Take note of the offset of a record starting, if it is an `every`th
+    fn mark(&self, every: Option<usize>) {
+        let every = match every {
+            Some(every) => every as u64,
+            None => return,
+        };
+        if self.records.fetch_add(1, Ordering::Relaxed) % every == 0 {
+            let offset = self.written.load(Ordering::Relaxed);
+            let mut offsets =
+                self.offsets.lock().unwrap_or_else(|e| e.into_inner());
+            offsets.push(offset);
+        }
+    }
+}
+
+/// `io::Write` counting the bytes written to `io`
+struct CountingWriter<'a, W: io::Write> {
+    io: W,
+    written: &'a AtomicU64,
+}
+
+impl<'a, W> io::Write for CountingWriter<'a, W>
+where
+    W: io::Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.io.write(buf)?;
+        self.written.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl DrainState {
+    fn new() -> Self {
+        DrainState {
+            dropped: AtomicU64::new(0),
+            index: AtomicU64::new(0),
+            started: AtomicBool::new(false),
+            seal: Mutex::new(None),
+            seek: SeekIndex {
+                written: AtomicU64::new(0),
+                records: AtomicU64::new(0),
+                offsets: Mutex::new(vec![]),
+            },
+        }
+    }
+
+    fn log<W>(
+        &self,
+        config: &JsonConfig,
+        io: &mut W,
+        rinfo: &Record,
+        logger_values: &OwnedKVList,
+    ) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        let res = if config.output.seek_every.is_some() {
+            let mut counting = CountingWriter {
+                io: &mut *io,
+                written: &self.seek.written,
+            };
+            self.log_records(config, &mut counting, rinfo, logger_values)
+        } else {
+            self.log_records(config, io, rinfo, logger_values)
+        };
+        self.settle(config, res)
+    }
+
+    /// `res`, unless an error discarded by `JsonBuilder::discard_errors`,
+    /// which is counted instead
+    fn settle(
+        &self,
+        config: &JsonConfig,
+        res: io::Result<()>,
+    ) -> io::Result<()> {
+        match res {
+            Err(_) if config.output.discard_errors => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            res => res,
+        }
+    }
+
+    fn log_records<W>(
+        &self,
+        config: &JsonConfig,
+        io: &mut W,
+        rinfo: &Record,
+        logger_values: &OwnedKVList,
+    ) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        if let Some(ref filter) = config.admission.record_filter {
+            if !filter(rinfo, logger_values) {
+                return Ok(());
+            }
+        }
+        if let Some(ref limiter) = config.admission.rate_limiter {
+            let mut throttled = vec![];
+            let admitted =
+                limiter.admit(rinfo.level(), config.now(), |level, n| {
+                    throttled.push((level, n))
+                });
+            for (level, n) in throttled {
+                self.write_throttled_summary(config, io, level, n)?;
+            }
+            if !admitted {
+                return Ok(());
+            }
+        }
+        if let Some(ref summary) = config.admission.level_summary {
+            if let Some(counts) = summary.take_due(config.now()) {
+                self.write(config, io, Line::LevelSummary(&counts))?;
+            }
+            summary.count(rinfo.level());
+        }
+        self.write(config, io, Line::Record(rinfo, logger_values))
+    }
+
+    /// Write a record with the number of records throttled at `level`, see
+    /// `JsonBuilder::rate_limit_per_level`
+    fn write_throttled_summary<W>(
+        &self,
+        config: &JsonConfig,
+        io: &mut W,
+        level: slog::Level,
+        dropped: u64,
+    ) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        static LOCATION: slog::RecordLocation = slog::RecordLocation {
+            file: file!(),
+            line: line!(),
+            column: column!(),
+            function: "",
+            module: module_path!(),
+        };
+        let rstatic = slog::RecordStatic {
+            location: &LOCATION,
+            tag: "",
+            level,
+        };
+        self.write(
+            config,
+            io,
+            Line::Record(
+                &Record::new(
+                    &rstatic,
+                    &format_args!("records dropped by rate limiting"),
+                    b!("dropped" => dropped),
+                ),
+                &OwnedKVList::from(o!()),
+            ),
+        )
+    }
+
+    fn write<W>(
+        &self,
+        config: &JsonConfig,
+        io: &mut W,
+        line: Line<'_>,
+    ) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        // Checked before anything is written, for records to be dropped
+        #[cfg(feature = "jsonschema")]
+        let kvs;
+        #[cfg(feature = "jsonschema")]
+        let line = match (line, config.admission.schema.as_ref()) {
+            (Line::Record(rinfo, logger_values), Some(validator)) => {
+                let index = self.index.load(Ordering::Relaxed);
+                let record =
+                    config.buffered_kvs(index, rinfo, logger_values)?;
+                kvs = match validator.check(record)? {
+                    Some(kvs) => kvs,
+                    None => return Ok(()),
+                };
+                Line::Buffered(&kvs)
+            }
+            (line, _) => line,
+        };
+
+        let index = self.index.fetch_add(1, Ordering::Relaxed);
+        self.start_record(config, io)
+            .map(|_| self.seek.mark(config.output.seek_every))
+            .and_then(|_| match config.output.seal {
+                Some(algo) => self.write_sealed(algo, config, io, index, line),
+                None => line.write(config, io, index),
+            })
+    }
+
+    /// Write what precedes a record: the preamble of the first one, or the
+    /// comma of `JsonBuilder::with_leading_comma`
+    fn start_record<W>(&self, config: &JsonConfig, io: &mut W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        if !self.started.swap(true, Ordering::Relaxed) {
+            config.write_preamble(io)
+        } else if config.output.leading_comma {
+            io.write_all(b",")
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Write a record, adding it to the running hash of the trailer
+    fn write_sealed<W>(
+        &self,
+        algo: HashAlgo,
+        config: &JsonConfig,
+        io: &mut W,
+        index: u64,
+        line: Line<'_>,
+    ) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        let mut seal = self.seal.lock().unwrap_or_else(|e| e.into_inner());
+        let seal = seal.get_or_insert(Seal {
+            count: 0,
+            hash: algo.initial(),
+        });
+        let tracker = Tracker::resume(algo, seal.hash);
+        let mut tracking = TrackingWriter {
+            io: &mut *io,
+            tracker: &tracker,
+        };
+        let res = line.write(config, &mut tracking, index);
+        // Bytes written by a failed record are part of the output all the same
+        seal.hash = tracker.hash_value().unwrap_or(seal.hash);
+        if res.is_ok() {
+            seal.count += 1;
+        }
+        res
+    }
+
+    /// Write the trailer of `JsonBuilder::with_sealed_trailer` and the
+    /// footer of `JsonBuilder::with_seek_index`, if any
+    fn write_trailer<W>(
+        &self,
+        config: &JsonConfig,
+        io: &mut W,
+    ) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        if let Some(algo) = config.output.seal {
+            self.write_seal(algo, io)?;
+        }
+        if let Some(every) = config.output.seek_every {
+            let offsets =
+                self.seek.offsets.lock().unwrap_or_else(|e| e.into_inner());
+            let footer = serde_json::json!({
+                "_seek_index": {
+                    "every": every,
+                    "offsets": *offsets,
+                }
+            });
+            serde_json::to_writer(&mut *io, &footer)?;
+            io.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn write_seal<W>(&self, algo: HashAlgo, io: &mut W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        let seal = self.seal.lock().unwrap_or_else(|e| e.into_inner());
+        let (count, hash) = match *seal {
+            Some(ref seal) => (seal.count, seal.hash),
+            None => (0, algo.initial()),
+        };
+        let trailer = serde_json::json!({
+            "_trailer": {
+                "count": count,
+                "hash": format!("{:016x}", hash),
+            }
+        });
+        serde_json::to_writer(&mut *io, &trailer)?;
+        io.write_all(b"\n")
+    }
+}
+// }}}
+
+// {{{ Json
+/// Json `Drain`
+///
+/// Each record will be printed as a Json map
+/// to a given `io`
+pub struct Json<W: io::Write> {
+    config: JsonConfig,
+    state: DrainState,
+    io: RefCell<W>,
+    /// Writers of `JsonBuilder::add_writer`, from the second one on
+    routes: Vec<(DrainState, RefCell<W>)>,
+    selector: Option<WriterSelector>,
+}
+
+impl<W> Json<W>
+where
+    W: io::Write,
+{
+    /// New `Json` `Drain` with default key-value pairs added
+    pub fn default(io: W) -> Json<W> {
+        JsonBuilder::new(io).add_default_keys().build()
+    }
+
+    /// Build custom `Json` `Drain`
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(io: W) -> JsonBuilder<W> {
+        JsonBuilder::new(io)
+    }
+
+    /// Number of records that failed to be serialized or written, and
+    /// were discarded
+    ///
+    /// Only errors discarded by `JsonBuilder::discard_errors` are counted:
+    /// the ones returned from `Drain::log` are left to the caller.
+    pub fn dropped_count(&self) -> u64 {
+        self.states()
+            .map(|state| state.dropped.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Restart the `record_index` key from 0
+    ///
+    /// See `JsonBuilder::add_record_index`.
+    pub fn reset_index(&self) {
+        for state in self.states() {
+            state.index.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Finish logging, returning the `io`
+    ///
+    /// Writes the trailer of `JsonBuilder::with_sealed_trailer`, if
+    /// enabled, and flushes the `io`. A drain shared behind a `Mutex` can
+    /// be taken back with `Mutex::into_inner` once all loggers are dropped.
+    ///
+    /// Writers registered with `JsonBuilder::add_writer` are finished too,
+    /// and dropped; see `finish_all` to get them back.
+    pub fn finish(self) -> io::Result<W> {
+        let mut writers = self.finish_all()?;
+        Ok(writers.swap_remove(0))
+    }
+
+    /// Finish logging, returning every writer, see `finish`
+    ///
+    /// The `io` of `Json::new` comes first, followed by the writers of
+    /// `JsonBuilder::add_writer` in the order they were registered.
+    pub fn finish_all(self) -> io::Result<Vec<W>> {
+        let config = self.config;
+        if let Some(ref limiter) = config.admission.rate_limiter {
+            let mut throttled = vec![];
+            limiter.flush(|level, n| throttled.push((level, n)));
+            let io = &mut *self.io.borrow_mut();
+            for (level, n) in throttled {
+                self.state.write_throttled_summary(&config, io, level, n)?;
+            }
+        }
+        std::iter::once((self.state, self.io))
+            .chain(self.routes)
+            .map(|(state, io)| {
+                let mut io = io.into_inner();
+                state.write_trailer(&config, &mut io)?;
+                io.flush()?;
+                Ok(io)
+            })
+            .collect()
+    }
+
+    fn states(&self) -> impl Iterator<Item = &DrainState> {
+        std::iter::once(&self.state)
+            .chain(self.routes.iter().map(|(state, _)| state))
+    }
+
+    /// State and writer of a record, as picked by the writer selector
+    fn route(
+        &self,
+        rinfo: &Record,
+        logger_values: &OwnedKVList,
+    ) -> io::Result<(&DrainState, &RefCell<W>)> {
+        let selector = match self.selector {
+            Some(ref selector) => selector,
+            None => return Ok((&self.state, &self.io)),
+        };
+        selector(rinfo, logger_values).and_then(|i| match i {
+            0 => Ok((&self.state, &self.io)),
+            _ => match self.routes.get(i - 1) {
+                Some((state, io)) => Ok((state, io)),
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("no writer {}", i),
+                )),
+            },
+        })
+    }
+}
+
+impl<W> slog::Drain for Json<W>
+where
+    W: io::Write,
+{
+    type Ok = ();
+    type Err = io::Error;
+    fn log(
+        &self,
+        rinfo: &Record,
+        logger_values: &OwnedKVList,
+    ) -> io::Result<()> {
+        let (state, io) = match self.route(rinfo, logger_values) {
+            Ok(route) => route,
+            Err(e) => return self.state.settle(&self.config, Err(e)),
+        };
+        let mut io = io.borrow_mut();
+        state.log(&self.config, &mut *io, rinfo, logger_values)
+    }
+}
+
+// }}}
+
+// {{{ TieredJson
+/// Json `Drain` picking one of two configurations by record level
+///
+/// Records at or above the threshold level use the verbose configuration,
+/// all others the compact one. Eg. to only emit the source location of
+/// errors:
+///
+/// ```
+/// use slog::{error, info, o, Drain, FnValue, Level, Record};
+/// use slog_json::{JsonBuilder, SharedBuffer, TieredJson};
+///
+/// let verbose = JsonBuilder::config()
+///     .add_key_value(o!(
+///         "file" => FnValue(|r: &Record| r.file()),
+///         "line" => FnValue(|r: &Record| r.line()),
+///     ))
+///     .build_config();
+/// let compact = JsonBuilder::config().build_config();
+/// let buf = SharedBuffer::new();
+/// let drain = TieredJson::new(buf.clone(), Level::Error, verbose, compact);
+/// let log = slog::Logger::root(std::sync::Mutex::new(drain).fuse(), o!());
+/// info!(log, "fine"; "n" => 1);
+/// error!(log, "failed"; "n" => 2);
+///
+/// let output = buf.to_string_lossy();
+/// let lines: Vec<serde_json::Value> = output
+///     .lines()
+///     .map(|line| serde_json::from_str(line).unwrap())
+///     .collect();
+/// assert_eq!(lines[0], serde_json::json!({"n": 1}));
+/// assert!(lines[1]["file"].is_string() && lines[1]["line"].is_u64());
+/// assert_eq!(lines[1]["n"], 2);
+/// ```
+pub struct TieredJson<W: io::Write> {
+    threshold: slog::Level,
+    verbose: JsonConfig,
+    compact: JsonConfig,
+    state: DrainState,
+    io: RefCell<W>,
+}
+
+impl<W> TieredJson<W>
+where
+    W: io::Write,
+{
+    /// New `TieredJson` `Drain`
+    ///
+    /// Records at `threshold` or more severe use `verbose`.
+    pub fn new(
+        io: W,
+        threshold: slog::Level,
+        verbose: JsonConfig,
+        compact: JsonConfig,
+    ) -> Self {
+        TieredJson {
+            threshold,
+            verbose,
+            compact,
+            state: DrainState::new(),
+            io: RefCell::new(io),
+        }
+    }
+
+    /// Number of records that failed to be serialized or written, and
+    /// were discarded
+    ///
+    /// See `Json::dropped_count`.
+    pub fn dropped_count(&self) -> u64 {
+        self.state.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Restart the `record_index` key from 0
+    ///
+    /// See `JsonBuilder::add_record_index`.
+    pub fn reset_index(&self) {
+        self.state.index.store(0, Ordering::Relaxed);
+    }
+}
+
+impl<W> slog::Drain for TieredJson<W>
+where
+    W: io::Write,
+{
+    type Ok = ();
+    type Err = io::Error;
+    fn log(
+        &self,
+        rinfo: &Record,
+        logger_values: &OwnedKVList,
+    ) -> io::Result<()> {
+        let config = if rinfo.level().is_at_least(self.threshold) {
+            &self.verbose
+        } else {
+            &self.compact
+        };
+        let mut io = self.io.borrow_mut();
+        self.state.log(config, &mut *io, rinfo, logger_values)
+    }
+}
+// }}}
+
+// {{{ ShardedJson
+/// Json `Drain` spreading records over several writers by a key's value
+///
+/// Each record goes to the writer picked by the hash of the value of the
+/// shard key, so that all records with the same value, eg. of the same
+/// tenant, land in the same writer. Records without the key go to the
+/// first writer.
+///
+/// ```
+/// use slog::{info, o, Drain};
+/// use slog_json::{JsonBuilder, ShardedJson};
+/// use std::sync::Mutex;
+///
+/// let config = JsonBuilder::config().add_default_keys().build_config();
+/// let drain = ShardedJson::new(vec![vec![], vec![]], "tenant_id", config);
+/// let drain = Mutex::new(drain);
+/// let log = slog::Logger::root(drain.fuse(), o!());
+/// info!(log, "hello"; "tenant_id" => "acme");
+/// ```
+///
+/// The writers are otherwise independent: the `record_index` of
+/// `JsonBuilder::add_record_index`, the header of `with_bom` or
+/// `delta_timestamps` and the trailer of `with_sealed_trailer` are all
+/// per writer.
+pub struct ShardedJson<W: io::Write> {
+    config: JsonConfig,
+    key: String,
+    shards: Vec<(DrainState, RefCell<W>)>,
+}
+
+impl<W> ShardedJson<W>
+where
+    W: io::Write,
+{
+    /// New `ShardedJson` `Drain`, sharding records by the value of `key`
+    ///
+    /// Panics if `writers` is empty.
+    pub fn new(writers: Vec<W>, key: &str, config: JsonConfig) -> Self {
+        assert!(!writers.is_empty(), "ShardedJson needs a writer");
+        ShardedJson {
+            config,
+            key: key.to_owned(),
+            shards: writers
+                .into_iter()
+                .map(|io| (DrainState::new(), RefCell::new(io)))
+                .collect(),
+        }
+    }
+
+    /// Number of records that failed to be serialized or written, and
+    /// were discarded, over all writers
+    ///
+    /// See `Json::dropped_count`.
+    pub fn dropped_count(&self) -> u64 {
+        self.shards
+            .iter()
+            .map(|(state, _)| state.dropped.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Finish logging, returning the writers
+    ///
+    /// See `Json::finish`.
+    pub fn finish(self) -> io::Result<Vec<W>> {
+        let config = self.config;
+        self.shards
+            .into_iter()
+            .map(|(state, io)| {
+                let mut io = io.into_inner();
+                state.write_trailer(&config, &mut io)?;
+                io.flush()?;
+                Ok(io)
+            })
+            .collect()
+    }
+
+    /// Index of the writer of a record
+    fn shard(&self, rinfo: &Record, logger_values: &OwnedKVList) -> usize {
+        let mut collector = KvCollector::new(&self.config.serde);
+        let _ = rinfo.kv().serialize(rinfo, &mut collector);
+        let _ = logger_values.serialize(rinfo, &mut collector);
+        for kv in &self.config.fields.values {
+            let _ = kv.serialize(rinfo, &mut collector);
+        }
+        let value = match collector
+            .into_kvs()
+            .into_iter()
+            .find(|(k, _)| *k == self.key)
+        {
+            Some((_, value)) => value,
+            None => return 0,
+        };
+        let algo = HashAlgo::Fnv1a64;
+        let hash = algo.update(algo.initial(), value.to_string().as_bytes());
+        (hash % self.shards.len() as u64) as usize
+    }
+}
+
+impl<W> slog::Drain for ShardedJson<W>
+where
+    W: io::Write,
+{
+    type Ok = ();
+    type Err = io::Error;
+    fn log(
+        &self,
+        rinfo: &Record,
+        logger_values: &OwnedKVList,
+    ) -> io::Result<()> {
+        let (ref state, ref io) = self.shards[self.shard(rinfo, logger_values)];
+        let mut io = io.borrow_mut();
+        state.log(&self.config, &mut *io, rinfo, logger_values)
+    }
+}
+// }}}
+
+// {{{ ChannelJson
+/// What `ChannelJson` does with records once the receiver is gone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectPolicy {
+    /// Return an `io::ErrorKind::BrokenPipe` error
+    Error,
+    /// Drop the record silently, only counting it in `dropped_count`
+    Discard,
+}
+
+/// Json `Drain` sending records over a channel
+///
+/// Each record is serialized into a `String`, as it would be written to an
+/// `io::Write` (so with a trailing newline, unless disabled with
+/// `JsonBuilder::set_newlines`), and sent to the `Sender`.
+///
+/// ```
+/// use slog::{info, o, Drain};
+/// use slog_json::{ChannelJson, JsonBuilder};
+/// use std::sync::{mpsc, Mutex};
+///
+/// let (tx, rx) = mpsc::channel();
+/// let config = JsonBuilder::config().add_default_keys().build_config();
+/// let drain = ChannelJson::new(tx, config);
+/// let log = slog::Logger::root(Mutex::new(drain).fuse(), o!());
+/// info!(log, "hello");
+/// assert!(rx.recv().unwrap().contains(r#""msg":"hello""#));
+/// ```
+pub struct ChannelJson {
+    config: JsonConfig,
+    state: DrainState,
+    sender: mpsc::Sender<String>,
+    policy: DisconnectPolicy,
+}
+
+impl ChannelJson {
+    /// New `ChannelJson` `Drain`
+    ///
+    /// Records sent after the receiver is dropped are errors, see
+    /// `set_disconnect_policy`.
+    pub fn new(sender: mpsc::Sender<String>, config: JsonConfig) -> Self {
+        ChannelJson {
+            config,
+            state: DrainState::new(),
+            sender,
+            policy: DisconnectPolicy::Error,
+        }
+    }
+
+    /// Set what to do with records once the receiver is gone
+    pub fn set_disconnect_policy(mut self, policy: DisconnectPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Number of records that failed to be serialized or sent, and were
+    /// discarded
+    ///
+    /// Records sent once the receiver is gone are counted with
+    /// `DisconnectPolicy::Discard`; see `Json::dropped_count` for others.
+    pub fn dropped_count(&self) -> u64 {
+        self.state.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl slog::Drain for ChannelJson {
+    type Ok = ();
+    type Err = io::Error;
+    fn log(
+        &self,
+        rinfo: &Record,
+        logger_values: &OwnedKVList,
+    ) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(256);
+        self.state
+            .log(&self.config, &mut buf, rinfo, logger_values)?;
+        let line = String::from_utf8(buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if self.sender.send(line).is_ok() {
+            return Ok(());
+        }
+        match self.policy {
+            DisconnectPolicy::Error => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "channel receiver disconnected",
+            )),
+            DisconnectPolicy::Discard => {
+                self.state.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+        }
+    }
+}
+// }}}
+
+// {{{ SharedBuffer
+/// `io::Write` into a buffer shared by its clones, to read the output of a
+/// drain back
+///
+/// ```
+/// use slog::{info, o, Drain};
+///
+/// let (drain, buf) = slog_json::shared_buffer();
+/// let log = slog::Logger::root(std::sync::Mutex::new(drain).fuse(), o!());
+/// info!(log, "one");
+/// info!(log, "two");
+///
+/// let output = buf.to_string_lossy();
+/// let lines: Vec<_> = output.lines().collect();
+/// assert_eq!(lines.len(), 2);
+/// assert!(lines[1].contains(r#""msg":"two""#));
+/// ```
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    /// New empty buffer
+    pub fn new() -> Self {
+        SharedBuffer::default()
+    }
+
+    /// Bytes written so far
+    ///
+    /// The buffer stays locked, and writes to it wait, until the guard is
+    /// dropped.
+    pub fn bytes(&self) -> std::sync::MutexGuard<'_, Vec<u8>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Bytes written so far, as a string
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.bytes()).into_owned()
+    }
+
+    /// Empty the buffer
+    pub fn clear(&self) {
+        self.bytes().clear();
+    }
+}
+
+impl io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// `Json` `Drain` with default keys writing to a `SharedBuffer`, along with
+/// a clone of the buffer
+///
+/// For tests reading the output back. Drains with other settings can be
+/// built with `Json::new(SharedBuffer::new())`, keeping a clone of the
+/// buffer.
+pub fn shared_buffer() -> (Json<SharedBuffer>, SharedBuffer) {
+    let buf = SharedBuffer::new();
+    (Json::default(buf.clone()), buf)
+}
+// }}}
+
+// {{{ JsonBuilder
+/// Json `Drain` builder
+///
+/// Create with `Json::new`.
+///
+/// Records can be written in other formats than JSON, with `build_logfmt`,
+/// `build_yaml`, `build_with_factory` and `canonical`, the last one set
+/// winning. They have the same key-values as JSON records, but for the
+/// ones describing the JSON output: `add_record_size`,
+/// `add_serialize_timing` and `content_hash_id` only apply to JSON, as do
+/// `set_pretty`, `escape_forward_slashes` and `json5_unquoted_keys`.
+pub struct JsonBuilder<W: io::Write> {
+    config: JsonConfig,
+    flattened_objects: Vec<serde_json::Value>,
+    flatten_style: FlattenStyle,
+    io: W,
+    /// Writers of `add_writer`
+    extra_io: Vec<W>,
+    selector: Option<WriterSelector>,
+}
+
+impl JsonBuilder<io::Sink> {
+    /// Builder of a `JsonConfig` alone, to finish with `build_config`
+    pub fn config() -> Self {
+        JsonBuilder::new(io::sink())
+    }
+}
+
+impl<W> JsonBuilder<W>
+where
+    W: io::Write,
+{
+    fn new(io: W) -> Self {
+        JsonBuilder {
+            config: JsonConfig::new(),
+            flattened_objects: vec![],
+            flatten_style: FlattenStyle::default(),
+            io,
+            extra_io: vec![],
+            selector: None,
+        }
+    }
+
+    /// Compress the output with `codec`
+    ///
+    /// Records are serialized as without compression, and the resulting
+    /// stream, newlines included, is compressed. Every record is sent
+    /// compressed when flushed, see `set_flush`. The compressed stream is
+    /// ended by `CompressedWriter::finish`, on the writer returned by
+    /// `Json::finish`, or when the drain is dropped.
+    pub fn with_compression(
+        self,
+        codec: Compression,
+    ) -> JsonBuilder<CompressedWriter<W>> {
+        JsonBuilder {
+            config: self.config,
+            flattened_objects: self.flattened_objects,
+            flatten_style: self.flatten_style,
+            io: CompressedWriter::new(self.io, codec),
+            extra_io: self
+                .extra_io
+                .into_iter()
+                .map(|io| CompressedWriter::new(io, codec))
+                .collect(),
+            selector: self.selector,
+        }
+    }
+
+    /// Build `Json` `Drain`
+    ///
+    /// This consumes the builder.
+    pub fn build(mut self) -> Json<W> {
+        let routes = std::mem::take(&mut self.extra_io)
+            .into_iter()
+            .map(|io| (DrainState::new(), RefCell::new(io)))
+            .collect();
+        let selector = self.selector.take();
+        let (config, io) = self.into_parts();
+        Json {
+            config,
+            state: DrainState::new(),
+            io: RefCell::new(io),
+            routes,
+            selector,
+        }
+    }
+
+    /// Build a `Yaml` `Drain`, writing records as YAML documents
+    ///
+    /// This consumes the builder.
+    #[cfg(feature = "yaml")]
+    pub fn build_yaml(mut self) -> Yaml<W> {
+        self.config.output.format = Format::Yaml;
+        Yaml(self.build())
+    }
+
+    /// Build a `Logfmt` `Drain`, writing records as `key=value` pairs with
+    /// JSON values
+    ///
+    /// This consumes the builder.
+    pub fn build_logfmt(mut self) -> Logfmt<W> {
+        self.config.output.format = Format::Logfmt;
+        Logfmt(self.build())
+    }
+
+    /// Build a `SerdeDrain`, writing records in the format of `factory`
+    ///
+    /// Set `set_newlines(false)` for formats that are not line-based, like
+    /// binary ones. This consumes the builder.
+    ///
+    /// ```
+    /// use serde_json::ser::PrettyFormatter;
+    /// use slog::{info, o, Drain};
+    /// use std::io;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// struct TabbedJson;
+    ///
+    /// impl slog_json::SerializerFactory for TabbedJson {
+    ///     fn serialize(
+    ///         &self,
+    ///         io: &mut dyn io::Write,
+    ///         record: &dyn erased_serde::Serialize,
+    ///     ) -> io::Result<()> {
+    ///         let formatter = PrettyFormatter::with_indent(b"\t");
+    ///         let mut ser = serde_json::Serializer::with_formatter(io, formatter);
+    ///         erased_serde::serialize(record, &mut ser)?;
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let out = Arc::new(Mutex::new(vec![]));
+    /// # struct Shared(Arc<Mutex<Vec<u8>>>);
+    /// # impl io::Write for Shared {
+    /// #     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    /// #         self.0.lock().unwrap().write(buf)
+    /// #     }
+    /// #     fn flush(&mut self) -> io::Result<()> {
+    /// #         Ok(())
+    /// #     }
+    /// # }
+    /// let drain = slog_json::Json::new(Shared(out.clone()))
+    ///     .build_with_factory(TabbedJson);
+    /// let log = slog::Logger::root(Mutex::new(drain).fuse(), o!());
+    /// info!(log, "hello"; "n" => 1);
+    ///
+    /// let out = String::from_utf8(out.lock().unwrap().clone()).unwrap();
+    /// assert_eq!(out, "{\n\t\"n\": 1\n}\n");
+    /// ```
+    #[cfg(feature = "serializer-factory")]
+    pub fn build_with_factory<F>(mut self, factory: F) -> SerdeDrain<W>
+    where
+        F: SerializerFactory + Send + Sync + 'static,
+    {
+        self.config.output.format = Format::Factory(Arc::new(factory));
+        SerdeDrain(self.build())
+    }
+
+    /// Build only the `JsonConfig`, dropping the `io`
+    pub fn build_config(self) -> JsonConfig {
+        self.into_parts().0
+    }
+
+    fn into_parts(mut self) -> (JsonConfig, W) {
+        if self.config.time.delta_timestamps {
+            let now = self.config.now();
+            self.config.time.delta_base_ms = Some(unix_ms(now));
+        }
+        let mut constants = self.config.message.layout.constants();
+        constants.append(&mut self.config.fields.constants);
+        self.config.fields.constants = constants;
+        if self.config.message.protect_reserved
+            && self.config.message.default_keys
+        {
+            let names = &self.config.message.default_key_names;
+            self.config.serde.reserved_keys = vec![
+                names.msg.to_string(),
+                names.level.to_string(),
+                names.ts.to_string(),
+            ];
+        }
+        for value in self.flattened_objects {
+            flatten_value(
+                self.flatten_style,
+                None,
+                value,
+                &mut self.config.fields.constants,
+            );
+        }
+        (self.config, self.io)
+    }
+
+    /// Set writing a newline after every log record
+    pub fn set_newlines(mut self, enabled: bool) -> Self {
+        self.config.output.newlines = enabled;
+        self
+    }
+
+    /// Write a comma ahead of every record but the first
+    ///
+    /// For records to be concatenated into a JSON array assembled by the
+    /// caller, along with `set_newlines(false)`: the caller owns the
+    /// surrounding brackets, and writes `[` before the first record and
+    /// `]` after the last one.
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let drain = slog_json::Json::new(vec![b'['])
+    ///     .set_newlines(false)
+    ///     .with_leading_comma(true)
+    ///     .build();
+    /// let drain = Arc::new(Mutex::new(drain));
+    /// let log = slog::Logger::root(drain.clone().fuse(), o!());
+    /// info!(log, "first"; "n" => 1);
+    /// info!(log, "second"; "n" => 2);
+    ///
+    /// drop(log);
+    /// let drain = Arc::try_unwrap(drain).ok().unwrap();
+    /// let mut out = drain.into_inner().unwrap().finish().unwrap();
+    /// out.push(b']');
+    /// assert_eq!(out, br#"[{"n":1},{"n":2}]"#);
+    /// ```
+    pub fn with_leading_comma(mut self, enabled: bool) -> Self {
+        self.config.output.leading_comma = enabled;
+        self
+    }
+
+    /// Write `tag` ahead of every record
+    ///
+    /// For streams multiplexing several kinds of records, telling them
+    /// apart by their first byte. The tag comes right before the record,
+    /// which is still followed by the newline, if enabled.
+    pub fn with_type_tag_byte(mut self, tag: u8) -> Self {
+        self.config.output.type_tag = Some(tag);
+        self
+    }
+
+    /// Enable flushing of the `io::Write` after every log record
+    pub fn set_flush(mut self, enabled: bool) -> Self {
+        self.config.output.flush = enabled;
+        self
+    }
+
+    /// Discard records failing to be serialized or written, counting them
+    /// in `Json::dropped_count`, rather than returning the error
+    ///
+    /// For setups where errors would be ignored anyway, eg. with
+    /// `Drain::ignore_res`, while keeping track of the records lost.
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    /// use std::io;
+    ///
+    /// struct Broken;
+    ///
+    /// impl io::Write for Broken {
+    ///     fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+    ///         Err(io::Error::new(io::ErrorKind::Other, "broken"))
+    ///     }
+    ///     fn flush(&mut self) -> io::Result<()> {
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let drain = slog_json::Json::new(Broken).discard_errors(true).build();
+    /// let drain = std::sync::Arc::new(std::sync::Mutex::new(drain));
+    /// let log = slog::Logger::root(drain.clone().fuse(), o!());
+    /// info!(log, "lost");
+    /// assert_eq!(drain.lock().unwrap().dropped_count(), 1);
+    /// ```
+    pub fn discard_errors(mut self, enabled: bool) -> Self {
+        self.config.output.discard_errors = enabled;
+        self
+    }
+
+    /// Set whether or not pretty formatted logging should be used
+    pub fn set_pretty(mut self, enabled: bool) -> Self {
+        self.config.output.pretty = enabled;
+        self
+    }
+
+    /// Escape `/` in strings as `\/`
+    ///
+    /// Valid, if unusual, JSON that keeps a `</script>` logged in a value
+    /// from closing the script element records are embedded in.
+    pub fn escape_forward_slashes(mut self, enabled: bool) -> Self {
+        self.config.output.escape_slashes = enabled;
+        self
+    }
+
+    /// Leave keys that are identifiers unquoted, as JSON5 allows
+    ///
+    /// Keys like `msg` or `user_id` are written bare, while keys that need
+    /// quotes in JSON5, like `http.status` or `user-agent`, keep them.
+    ///
+    /// Note that the output is then JSON5, and no longer JSON: only use
+    /// this for consumers that parse JSON5.
+    pub fn json5_unquoted_keys(mut self, enabled: bool) -> Self {
+        self.config.output.json5_keys = enabled;
+        self
+    }
+
+    /// Write a UTF-8 byte order mark (`EF BB BF`) ahead of the first record
+    ///
+    /// For legacy tools that require one to detect the encoding. The mark
+    /// is written once per drain, so a drain appending to a file that
+    /// already has one writes it again.
+    pub fn with_bom(mut self, enabled: bool) -> Self {
+        self.config.output.bom = enabled;
+        self
+    }
+
+    /// Emit at most `limits[i]` records per second at each level, from
+    /// `Critical` to `Trace`
+    ///
+    /// Every level has a token bucket holding up to a second worth of
+    /// records, so short bursts go through. Records over the limit are
+    /// discarded; at most once per second, the next record logged, whether
+    /// let through or not, is preceded by a summary record per throttled
+    /// level, with message `records dropped by rate limiting` and a
+    /// `dropped` key counting them. `Json::finish` writes the summaries
+    /// still due. Throttled records are not counted by
+    /// `Json::dropped_count`. Time is read from the clock of `with_clock`.
+    ///
+    /// ```
+    /// let drain = slog_json::Json::new(std::io::stderr())
+    ///     .add_default_keys()
+    ///     .rate_limit_per_level([u32::MAX, u32::MAX, 1000, 100, 10, 10])
+    ///     .build();
+    /// ```
+    pub fn rate_limit_per_level(mut self, limits: [u32; 6]) -> Self {
+        self.config.admission.rate_limiter = Some(RateLimiter::new(limits));
+        self
+    }
+
+    /// Write a record counting the records logged at each level, every
+    /// `interval`
+    ///
+    /// The summary is an object of counts keyed by
+    /// `slog::Level::as_short_str`, covering all levels, like
+    /// `{"summary":{"CRIT":0,"DEBG":0,"ERRO":1,"INFO":12,"TRCE":0,"WARN":0}}`,
+    /// of the records logged since the previous one. It is written by the
+    /// first record logged once `interval` has elapsed, ahead of it, so no
+    /// summary is written while nothing is logged. Records dropped by
+    /// `with_record_filter` or `rate_limit_per_level` are not counted. The
+    /// first interval starts with the first record logged.
+    ///
+    /// The summary record has none of the key-values of the drain, but is
+    /// otherwise written like other records: it is covered by the trailer
+    /// of `with_sealed_trailer` and fails like them, see `discard_errors`.
+    /// Intervals are timed by the clock of `with_clock`.
+    pub fn with_level_summary(mut self, interval: Duration) -> Self {
+        self.config.admission.level_summary = Some(LevelSummary::new(interval));
+        self
+    }
+
+    /// Read the current time from `clock` rather than the system clock
+    ///
+    /// For tests, to control time. Timestamps are read from it: `ts` in any
+    /// format, and the keys of `kibana_timestamps`, `ecs_timestamps` and
+    /// `delta_timestamps`, as are the intervals of `with_level_summary` and
+    /// `rate_limit_per_level`.
+    /// Only `add_process_start_time` and `add_monotonic_ts` do not use it.
+    pub fn with_clock<F>(mut self, clock: F) -> Self
+    where
+        F: Fn() -> SystemTime + Send + Sync + 'static,
+    {
+        self.config.time.clock = Arc::new(clock);
+        self
+    }
+
+    /// Set the format of the `ts` default key
+    ///
+    /// Defaults to `TimestampFormat::Rfc3339`.
+    pub fn set_timestamp_format(mut self, format: TimestampFormat) -> Self {
+        self.config.time.timestamp_format = format;
+        self
+    }
+
+    /// Name and format the `ts` default key as `preset` requires
+    ///
+    /// For consumers that are picky about timestamps, rather than picking
+    /// the key and its format by hand:
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    /// use std::time::{Duration, UNIX_EPOCH};
+    ///
+    /// let buf = slog_json::SharedBuffer::new();
+    /// let drain = slog_json::Json::new(buf.clone())
+    ///     .add_default_keys()
+    ///     .timestamp_preset(slog_json::TimestampPreset::Elasticsearch)
+    ///     .with_clock(|| UNIX_EPOCH + Duration::from_nanos(1_700_000_000_123_456_789))
+    ///     .build();
+    /// let log = slog::Logger::root(std::sync::Mutex::new(drain).fuse(), o!());
+    /// info!(log, "hello");
+    /// assert_eq!(
+    ///     buf.to_string_lossy(),
+    ///     "{\"msg\":\"hello\",\"level\":\"INFO\",\
+    ///      \"@timestamp\":\"2023-11-14T22:13:20.123Z\"}\n"
+    /// );
+    /// ```
+    ///
+    /// Replaces the timestamp settings of earlier calls, including of
+    /// `set_timestamp_format`, `with_timestamp_fn` and presets like
+    /// `datadog`, and leaves the other default keys alone.
+    pub fn timestamp_preset(mut self, preset: TimestampPreset) -> Self {
+        let (key, format, epoch_ts) = match preset {
+            TimestampPreset::Elasticsearch => {
+                ("@timestamp", TimestampFormat::Rfc3339Millis, false)
+            }
+            TimestampPreset::Splunk => ("time", TimestampFormat::Rfc3339, true),
+            TimestampPreset::Datadog => {
+                ("timestamp", TimestampFormat::Rfc3339Millis, false)
+            }
+            TimestampPreset::Loki => ("ts", TimestampFormat::Rfc3339, false),
+        };
+        let names = &mut self.config.message.default_key_names;
+        names.ts = key.into();
+        names.epoch_ts = epoch_ts;
+        names.epoch_precision = None;
+        self.config.time.timestamp_format = format;
+        self.config.time.timestamp_fn = None;
+        self
+    }
+
+    /// Count epoch timestamps from `origin` rather than the Unix epoch
+    ///
+    /// For systems with an epoch of their own. Applies to the `ts` default
+    /// key when emitted as a number, as by `set_ts_epoch`, `Layout::Gelf` or
+    /// `TimestampPreset::Splunk`, which is then the time since `origin`,
+    /// negative for records logged before it:
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    /// use std::sync::{Arc, Mutex};
+    /// use std::time::{Duration, UNIX_EPOCH};
+    ///
+    /// // 2020-01-01T00:00:00Z
+    /// let origin = UNIX_EPOCH + Duration::from_secs(1_577_836_800);
+    /// let now = Arc::new(Mutex::new(origin + Duration::from_millis(1500)));
+    /// let clock = now.clone();
+    /// let buf = slog_json::SharedBuffer::new();
+    /// let drain = slog_json::Json::new(buf.clone())
+    ///     .add_default_keys()
+    ///     .set_ts_epoch(slog_json::EpochPrecision::Millis)
+    ///     .with_epoch_origin(origin)
+    ///     .with_clock(move || *clock.lock().unwrap())
+    ///     .build();
+    /// let log = slog::Logger::root(Mutex::new(drain).fuse(), o!());
+    /// info!(log, "after");
+    /// *now.lock().unwrap() = origin - Duration::from_millis(250);
+    /// info!(log, "before");
+    ///
+    /// let output = buf.to_string_lossy();
+    /// let lines: Vec<serde_json::Value> = output
+    ///     .lines()
+    ///     .map(|line| serde_json::from_str(line).unwrap())
+    ///     .collect();
+    /// assert_eq!(lines[0]["ts"], 1500);
+    /// assert_eq!(lines[1]["ts"], -250);
+    /// ```
+    pub fn with_epoch_origin(mut self, origin: SystemTime) -> Self {
+        self.config.time.epoch_origin = origin;
+        self
+    }
+
+    /// Emit the `ts` default key as a whole number of `precision` units
+    /// since the Unix epoch
+    ///
+    /// For consumers reading numbers faster than strings, like ClickHouse
+    /// or BigQuery. Counts from `with_epoch_origin` if set, and takes
+    /// precedence over `set_timestamp_format` and `with_timestamp_fn`:
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    /// use std::sync::{Arc, Mutex};
+    /// use std::time::{SystemTime, UNIX_EPOCH};
+    ///
+    /// let drain = slog_json::Json::new(vec![])
+    ///     .add_default_keys()
+    ///     .set_ts_epoch(slog_json::EpochPrecision::Millis)
+    ///     .build();
+    /// let drain = Arc::new(Mutex::new(drain));
+    /// let log = slog::Logger::root(drain.clone().fuse(), o!());
+    /// let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    /// info!(log, "hello");
+    /// drop(log);
+    ///
+    /// let drain = Arc::try_unwrap(drain).ok().unwrap();
+    /// let out = drain.into_inner().unwrap().finish().unwrap();
+    /// let record: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    /// let ts = record["ts"].as_u64().unwrap();
+    /// assert!(ts >= before.as_millis() as u64);
+    /// ```
+    ///
+    /// `layout`, `timestamp_preset` and presets like `datadog` replace it,
+    /// and are to be called first.
+    pub fn set_ts_epoch(mut self, precision: EpochPrecision) -> Self {
+        self.config.message.default_key_names.epoch_precision = Some(precision);
+        self
+    }
+
+    /// Format the `ts` default key with `f`
+    ///
+    /// Takes precedence over `set_timestamp_format`, allowing any time crate
+    /// or format to be used:
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    /// use std::time::{Duration, UNIX_EPOCH};
+    ///
+    /// let buf = slog_json::SharedBuffer::new();
+    /// let drain = slog_json::Json::new(buf.clone())
+    ///     .add_default_keys()
+    ///     .with_timestamp_fn(|ts| {
+    ///         let since = ts.duration_since(UNIX_EPOCH).unwrap_or_default();
+    ///         format!("{}.{:03}", since.as_secs(), since.subsec_millis())
+    ///     })
+    ///     .with_clock(|| UNIX_EPOCH + Duration::from_millis(1_700_000_000_123))
+    ///     .build();
+    /// let log = slog::Logger::root(std::sync::Mutex::new(drain).fuse(), o!());
+    /// info!(log, "hello");
+    /// assert_eq!(
+    ///     buf.to_string_lossy(),
+    ///     "{\"msg\":\"hello\",\"level\":\"INFO\",\"ts\":\"1700000000.123\"}\n"
+    /// );
+    /// ```
+    pub fn with_timestamp_fn<F>(mut self, f: F) -> Self
+    where
+        F: Fn(SystemTime) -> String + Send + Sync + 'static,
+    {
+        self.config.time.timestamp_fn = Some(Arc::new(f));
+        self
+    }
+
+    /// Format the `ts` default key as `format`, in `strftime` syntax
+    ///
+    /// See `TsFormat` for the specifiers supported. The time is in UTC.
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    /// use slog_json::TsFormat;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let format = TsFormat::parse("%Y-%m-%dT%H:%M:%S%.3fZ").unwrap();
+    /// let drain = slog_json::Json::new(vec![])
+    ///     .add_default_keys()
+    ///     .set_ts_format(format)
+    ///     .build();
+    /// let drain = Arc::new(Mutex::new(drain));
+    /// let log = slog::Logger::root(drain.clone().fuse(), o!());
+    /// info!(log, "hello");
+    /// drop(log);
+    ///
+    /// let drain = Arc::try_unwrap(drain).ok().unwrap();
+    /// let out = drain.into_inner().unwrap().finish().unwrap();
+    /// let record: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    /// let ts = record["ts"].as_str().unwrap();
+    /// // Like `2022-02-20T12:34:56.789Z`
+    /// assert_eq!(ts.len(), 24);
+    /// assert_eq!(&ts[19..20], ".");
+    /// assert!(ts.ends_with('Z'));
+    /// ```
+    ///
+    /// Shorthand for `with_timestamp_fn`, replacing it.
+    pub fn set_ts_format(self, format: TsFormat) -> Self {
+        self.with_timestamp_fn(move |ts| format.format(ts.into()))
+    }
+
+    /// Add `@timestamp` and `timestamp_ms` keys, as expected by Kibana
+    ///
+    /// `@timestamp` is the RFC 3339 UTC time of the record and
+    /// `timestamp_ms` the same time as milliseconds since the Unix epoch,
+    /// for sorting. Both come from a single clock reading, and are emitted
+    /// right after the default keys, in place of `ts`.
+    pub fn kibana_timestamps(mut self) -> Self {
+        self.config.time.kibana_timestamps = true;
+        self
+    }
+
+    /// Add `@timestamp` and `event.created` keys, as expected by ECS
+    ///
+    /// ECS, the Elastic Common Schema, tells the time of the event,
+    /// `@timestamp`, from the time it was processed, `event.created`. Both
+    /// are RFC 3339 UTC times: `@timestamp` is read when the record starts
+    /// being serialized, right after the default keys and in place of `ts`,
+    /// and `event.created` once all of its key-values are, as the last key.
+    /// Records with slow to compute values, like `FnValue`s, get an
+    /// `event.created` later than their `@timestamp`.
+    ///
+    /// With `kibana_timestamps`, its `@timestamp` is the one of the record.
+    pub fn ecs_timestamps(mut self) -> Self {
+        self.config.time.ecs_timestamps = true;
+        self
+    }
+
+    /// Add a `dt_ms` key with the time of the record relative to a base,
+    /// in place of `ts`
+    ///
+    /// The base is the time the drain was built. It is written once, as a
+    /// `{"base_ts": ..., "base_ts_ms": ...}` header line ahead of the first
+    /// record, with `base_ts` in RFC 3339 and `base_ts_ms` in milliseconds
+    /// since the Unix epoch; the time of a record is then `base_ts_ms +
+    /// dt_ms`. A record logged after the clock was set back gets a negative
+    /// `dt_ms`.
+    pub fn delta_timestamps(mut self, enabled: bool) -> Self {
+        self.config.time.delta_timestamps = enabled;
+        self
+    }
+
+    /// Format the `ts` default key once per `granularity` at most
+    ///
+    /// For high rates of records, where formatting timestamps shows up in
+    /// profiles: timestamps are truncated to `granularity`, and formatted
+    /// again only once the clock reaches the next multiple of it. With a
+    /// granularity of a millisecond, all records of a given millisecond
+    /// have the same `ts`. Every logging thread keeps the timestamp it
+    /// formatted last, so threads do not contend for it.
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    /// use std::sync::{Arc, Mutex};
+    /// use std::time::{Duration, UNIX_EPOCH};
+    ///
+    /// let now = Arc::new(Mutex::new(UNIX_EPOCH));
+    /// let clock = now.clone();
+    /// let buf = slog_json::SharedBuffer::new();
+    /// let drain = slog_json::Json::new(buf.clone())
+    ///     .add_default_keys()
+    ///     .set_timestamp_format(slog_json::TimestampFormat::Rfc3339Millis)
+    ///     .cache_timestamps(Duration::from_millis(1))
+    ///     .with_clock(move || *clock.lock().unwrap())
+    ///     .build();
+    /// let log = slog::Logger::root(Mutex::new(drain).fuse(), o!());
+    /// for micros in &[1_100, 1_999, 2_000] {
+    ///     *now.lock().unwrap() = UNIX_EPOCH + Duration::from_micros(*micros);
+    ///     info!(log, "hello");
+    /// }
+    ///
+    /// let output = buf.to_string_lossy();
+    /// let ts: Vec<serde_json::Value> = output
+    ///     .lines()
+    ///     .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+    ///     .map(|record| record["ts"].clone())
+    ///     .collect();
+    /// assert_eq!(
+    ///     ts,
+    ///     [
+    ///         "1970-01-01T00:00:00.001Z",
+    ///         "1970-01-01T00:00:00.001Z",
+    ///         "1970-01-01T00:00:00.002Z",
+    ///     ]
+    /// );
+    /// ```
+    ///
+    /// Applies to timestamps formatted as strings, by `set_timestamp_format`
+    /// or `with_timestamp_fn`, not to numbers.
+    pub fn cache_timestamps(mut self, granularity: Duration) -> Self {
+        self.config.time.timestamp_cache =
+            Some(TimestampCache::new(granularity));
+        self
+    }
+
+    /// Add a key with the nanoseconds elapsed since the drain was built,
+    /// from a monotonic clock
+    ///
+    /// Unlike timestamps, which follow the wall clock and so can go back
+    /// when it is adjusted, values are strictly increasing over the records
+    /// of the drain: two records read in the same nanosecond are set one
+    /// apart. They are meant for ordering records within the process, and
+    /// are not comparable across processes. Emitted along with `ts`, right
+    /// after the default keys.
+    pub fn add_monotonic_ts(mut self, key: &str) -> Self {
+        self.config.time.monotonic_ts = Some(MonotonicClock::new(key));
+        self
+    }
+
+    /// Lowercase every emitted key
+    ///
+    /// For backends that are case-insensitive: keys that only differ in
+    /// case, like `UserID` and `userid`, are then emitted as the same key
+    /// by this drain rather than collapsed unpredictably downstream.
+    pub fn lowercase_keys(mut self, enabled: bool) -> Self {
+        self.config.serde.lowercase_keys = enabled;
+        self
+    }
+
+    /// Rename keys, from the keys of `map` to their values
+    ///
+    /// Applies to every emitted key, be it a key-value of records and
+    /// loggers or one added by the drain, like `hostname` or the default
+    /// keys. Keys are renamed
+    /// after `lowercase_keys` and the `user_` prefix of
+    /// `protect_reserved_keys`, and before the truncation of
+    /// `max_key_length`, so `map` is looked up with keys as they would
+    /// otherwise be emitted: with `lowercase_keys`, a `UID` key is renamed
+    /// by a `uid` entry.
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let renames = vec![("uid".to_owned(), "user_id".to_owned())];
+    /// let drain = slog_json::Json::new(vec![])
+    ///     .rename_keys(renames.into_iter().collect())
+    ///     .build();
+    /// let drain = Arc::new(Mutex::new(drain));
+    /// let log = slog::Logger::root(drain.clone().fuse(), o!());
+    /// info!(log, "login"; "uid" => 42, "ip" => "10.0.0.1");
+    ///
+    /// drop(log);
+    /// let drain = Arc::try_unwrap(drain).ok().unwrap();
+    /// let out = drain.into_inner().unwrap().finish().unwrap();
+    /// assert_eq!(out, b"{\"ip\":\"10.0.0.1\",\"user_id\":42}\n");
+    /// ```
+    ///
+    /// Keys inside nested values are not renamed.
+    pub fn rename_keys(mut self, map: HashMap<String, String>) -> Self {
+        self.config.serde.renames = map;
+        self
+    }
+
+    /// Remove control characters from string values
+    ///
+    /// They are otherwise escaped, like `\u0000`, which is valid JSON but
+    /// can still trip up consumers, like C-based parsers taking NUL for
+    /// the end of the string. Tabs and newlines are removed too, unless
+    /// kept with `keep_tabs_and_newlines`. Applies to the key-values of
+    /// records and loggers, not to the message.
+    pub fn strip_control_chars(mut self, enabled: bool) -> Self {
+        self.config.serde.strip_control_chars = enabled;
+        self
+    }
+
+    /// Normalize string values to `form`
+    ///
+    /// For backends indexing strings as they are, so that eg. an `é`
+    /// logged as a single code point or as `e` and a combining accent
+    /// match. Applies to the key-values of records and loggers, not to the
+    /// message nor to keys.
+    #[cfg(feature = "unicode-normalization")]
+    pub fn normalize_unicode(mut self, form: UnicodeForm) -> Self {
+        self.config.serde.unicode_form = Some(form);
+        self
+    }
+
+    /// Keep tabs and newlines when stripping control characters
+    ///
+    /// See `strip_control_chars`.
+    pub fn keep_tabs_and_newlines(mut self, enabled: bool) -> Self {
+        self.config.serde.keep_tabs_newlines = enabled;
+        self
+    }
+
+    /// Emit every value of the key-values of records and loggers as a
+    /// string
+    ///
+    /// For stores inferring the type of fields from their first values.
+    /// Numbers and booleans are emitted as their JSON text, like `"42"` and
+    /// `"true"`, and so are nested values, like `"[1,2]"`. `null`s are
+    /// left as is.
+    pub fn all_string_values(mut self, enabled: bool) -> Self {
+        self.config.serde.all_strings = enabled;
+        self
+    }
+
+    /// Keep only the first `n` elements of arrays in nested values
+    ///
+    /// Elements past the `n`th are not serialized, and stand for a last
+    /// element counting them, like `"<90 more>"`. Applies to arrays at any
+    /// depth, in values logged with `slog::Serde` or other `SerdeValue`s.
+    #[cfg(feature = "nested-values")]
+    pub fn max_array_len(mut self, n: usize) -> Self {
+        self.config.serde.max_array_len = Some(n);
+        self
+    }
+
+    /// Truncate keys longer than `n` bytes
+    ///
+    /// For backends limiting the length of field names. Truncation never
+    /// splits a character. Unless `n` is below 9, truncated keys end with
+    /// `~` and 8 hex digits hashing the full key, so that distinct keys
+    /// sharing a long prefix are not merged: `request_header_user_agent`
+    /// becomes e.g. `request_heade~6754407e` with `n` set to 22.
+    ///
+    /// Applies to the key-values of records and loggers, after
+    /// `lowercase_keys`; default keys are not affected.
+    pub fn max_key_length(mut self, n: usize) -> Self {
+        self.config.serde.max_key_len = Some(n);
+        self
+    }
+
+    /// Set what to do with keys longer than `max_key_length`
+    ///
+    /// By default they are truncated. With `LongKeyPolicy::HashReplace`,
+    /// they are replaced with a short key that is the same for all records,
+    /// like `k_6754407e` for `request_header_user_agent`, whatever the
+    /// limit. With `LongKeyPolicy::Error`, they fail to be serialized like
+    /// a failing value would, see `best_effort_fields`.
+    pub fn long_key_policy(mut self, policy: LongKeyPolicy) -> Self {
+        self.config.serde.long_keys = policy;
+        self
+    }
+
+    /// Emit a placeholder for fields failing to serialize, and go on
+    ///
+    /// By default a single failing field aborts the whole record. In
+    /// best-effort mode a value whose serialization fails is emitted as
+    /// `"<field error>"` instead. A `slog::Value` returning an error itself
+    /// still ends its list of key-values, as `slog` stops there, but the
+    /// rest of the record is emitted. Values are checked before being
+    /// written, which has a cost.
+    pub fn best_effort_fields(mut self, enabled: bool) -> Self {
+        self.config.serde.best_effort = enabled;
+        self
+    }
+
+    /// Only emit numbers under the given keys, stringify all others
+    ///
+    /// For strict schemas, eg. of Elasticsearch, where a key that was
+    /// first seen with a number has to stay numeric: numbers logged under
+    /// any other key are emitted as strings, `"port": "8080"`.
+    pub fn numeric_allowlist(mut self, keys: HashSet<String>) -> Self {
+        self.config.serde.numeric_allowlist = Some(keys);
+        self
+    }
+
+    /// Split W3C `traceparent` values logged under `key` into their fields
+    ///
+    /// A valid value, like
+    /// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`, is emitted
+    /// as `trace_id`, `span_id` and `trace_flags` keys instead. Other values
+    /// are emitted as they are.
+    pub fn parse_traceparent(mut self, key: &str) -> Self {
+        self.config.serde.traceparent_key = Some(key.to_owned());
+        self
+    }
+
+    /// Emit empty strings as `null`
+    ///
+    /// For schemas treating both the same. Applies to strings and
+    /// `Display` values (`%`), not to nested values.
+    pub fn empty_string_as_null(mut self, enabled: bool) -> Self {
+        self.config.serde.empty_string_as_null = enabled;
+        self
+    }
+
+    /// Only emit the key-values whose key `f` returns `true` for
+    ///
+    /// `f` gets the key as logged, before any other transformation, and
+    /// is consulted for every key-value, default keys included.
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    ///
+    /// // Keep internal fields out of production logs
+    /// let buf = slog_json::SharedBuffer::new();
+    /// let drain = slog_json::Json::new(buf.clone())
+    ///     .with_key_filter(|key| !key.starts_with('_'))
+    ///     .build();
+    /// let log = slog::Logger::root(std::sync::Mutex::new(drain).fuse(), o!());
+    /// info!(log, "hello"; "_trace" => "x", "user" => "ann");
+    /// assert_eq!(buf.to_string_lossy(), "{\"user\":\"ann\"}\n");
+    /// ```
+    pub fn with_key_filter<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.config.serde.key_filter = Some(Arc::new(f));
+        self
+    }
+
+    /// Emit strings under the given keys as numbers, when they are ones
+    ///
+    /// For values logged as strings by other code, like `"count": "42"`,
+    /// emitted as `"count": 42`. Only strings that are valid JSON numbers
+    /// are converted, so identifiers with leading zeros like `"007"` stay
+    /// strings, and so do `"+1"`, `"NaN"` or `" 42"`.
+    pub fn auto_number_strings(mut self, keys: HashSet<String>) -> Self {
+        self.config.serde.number_strings = keys;
+        self
+    }
+
+    /// Emit booleans under the given keys as `0` and `1`
+    pub fn bool_as_int_for(mut self, keys: HashSet<String>) -> Self {
+        self.config.serde.bool_as_int = keys;
+        self
+    }
+
+    /// Add custom values to be printed with this formatter
+    pub fn add_key_value<T>(mut self, value: slog::OwnedKV<T>) -> Self
+    where
+        T: SendSyncRefUnwindSafeKV + 'static,
+    {
+        self.config.fields.values.push(value.into());
+        self
+    }
+
+    /// Add a nested object flattened into dotted top-level keys
+    ///
+    /// `{"service": {"name": "api", "tags": ["a"]}}` is emitted as
+    /// `"service.name": "api", "service.tags.0": "a"`. Flattening happens
+    /// once, when building, not on every record. Top-level values that are
+    /// not objects or arrays have no key to be emitted under and are
+    /// ignored. Other key formats can be picked with `set_flatten_style`.
+    pub fn add_flattened_object(mut self, value: serde_json::Value) -> Self {
+        self.flattened_objects.push(value);
+        self
+    }
+
+    /// Add an `app` key with the given application name
+    ///
+    /// `app_name_kv!` adds the package name instead.
+    pub fn add_app_name<S>(mut self, name: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.config
+            .fields
+            .constants
+            .push(("app".into(), name.into().into()));
+        self
+    }
+
+    /// Add a `host.ip` key with the primary IP address of the host
+    ///
+    /// The address is the one of the default route, resolved once, here.
+    /// It is emitted as `null` if there is no such route.
+    pub fn add_host_ip(mut self) -> Self {
+        let ip = primary_ip().map(|ip| ip.to_string());
+        self.config
+            .fields
+            .constants
+            .push(("host.ip".into(), ip.into()));
+        self
+    }
+
+    /// Add `key` with a `null` value to every record
+    ///
+    /// Reserves the key for a value filled in downstream, like an ingest
+    /// timestamp, for pipelines expecting it to be present.
+    pub fn add_null_placeholder(mut self, key: &str) -> Self {
+        self.config
+            .fields
+            .constants
+            .push((key.to_owned(), serde_json::Value::Null));
+        self
+    }
+
+    /// Add a `process.start_time` key with the time the drain was built
+    ///
+    /// An RFC 3339 UTC timestamp, taken once, here. Build the drain at
+    /// startup for it to stand for the start of the process.
+    pub fn add_process_start_time(mut self) -> Self {
+        let now = time::OffsetDateTime::now_utc();
+        let start = TimestampFormat::Rfc3339.format(now);
+        self.config
+            .fields
+            .constants
+            .push(("process.start_time".into(), start.into()));
+        self
+    }
+
+    /// Add `host.name` and `host.os` keys describing the host
+    ///
+    /// `host.name` is the hostname, resolved once, here, and `null` if it
+    /// cannot be. `host.os` is the operating system the crate was built
+    /// for, as in `std::env::consts::OS`.
+    pub fn add_host_info(mut self) -> Self {
+        let constants = &mut self.config.fields.constants;
+        constants.push(("host.name".into(), hostname().into()));
+        constants.push(("host.os".into(), std::env::consts::OS.into()));
+        self
+    }
+
+    /// Add a `hostname` key with the name of the host
+    ///
+    /// The name is resolved here, and then again by the first record
+    /// logged once it is older than `refresh_interval`, if any: for hosts
+    /// that can be renamed while running, like containers moved around.
+    /// It is `null` if it cannot be resolved.
+    pub fn add_hostname(mut self, refresh_interval: Option<Duration>) -> Self {
+        self.config.fields.hostname =
+            Some(CachedHostname::new(hostname, refresh_interval));
+        self
+    }
+
+    /// Add `region` and `availability_zone` keys with the placement of the
+    /// cloud instance
+    ///
+    /// They are queried once, here, from the instance metadata service of
+    /// `provider`, waiting at most half a second overall; either is `null`
+    /// if it cannot be found, eg. when not running in that cloud. This call
+    /// blocks meanwhile, for the whole half second where the service does
+    /// not answer.
+    #[cfg(feature = "cloud-metadata")]
+    pub fn add_cloud_metadata(mut self, provider: CloudProvider) -> Self {
+        let (region, zone) = provider.placement();
+        let constants = &mut self.config.fields.constants;
+        constants.push(("region".into(), region.into()));
+        constants.push(("availability_zone".into(), zone.into()));
+        self
+    }
+
+    /// Set the format of the keys of flattened objects
+    ///
+    /// Defaults to `FlattenStyle::Dotted`.
+    pub fn set_flatten_style(mut self, style: FlattenStyle) -> Self {
+        self.flatten_style = style;
+        self
     }
 
     /// Add default key-values:
     ///
-    /// * `ts` - timestamp
-    /// * `level` - record logging level name
-    /// * `msg` - msg - formatted logging message
-    pub fn add_default_keys(self) -> Self {
-        self.add_key_value(o!(
-            "ts" => FnValue(move |_ : &Record| {
-                    time::OffsetDateTime::now_utc()
-                    .format(&time::format_description::well_known::Rfc3339)
+    /// * `ts` - timestamp
+    /// * `level` - record logging level name
+    /// * `msg` - msg - formatted logging message
+    ///
+    /// These are emitted ahead of all other key-values, but the ones of
+    /// `add_key_value` calls made before this one. `ts` is always in
+    /// UTC, whatever the local time zone: RFC 3339 with nanoseconds and a
+    /// `Z` offset, like `2022-02-20T12:34:56.789012345Z`, unless set
+    /// otherwise by `set_timestamp_format` or `with_timestamp_fn`.
+    pub fn add_default_keys(mut self) -> Self {
+        self.config.set_default_keys();
+        self
+    }
+
+    /// Name the `msg` default key `key`
+    ///
+    /// For consumers expecting other names, like `message`, without giving
+    /// up the default keys:
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let drain = slog_json::Json::new(vec![])
+    ///     .add_default_keys()
+    ///     .set_msg_key("message")
+    ///     .set_level_key("severity")
+    ///     .set_ts_key("@timestamp")
+    ///     .build();
+    /// let drain = Arc::new(Mutex::new(drain));
+    /// let log = slog::Logger::root(drain.clone().fuse(), o!());
+    /// info!(log, "hello");
+    /// drop(log);
+    ///
+    /// let drain = Arc::try_unwrap(drain).ok().unwrap();
+    /// let out = drain.into_inner().unwrap().finish().unwrap();
+    /// let record: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    /// assert_eq!(record["message"], "hello");
+    /// assert_eq!(record["severity"], "INFO");
+    /// assert!(record["@timestamp"].is_string());
+    /// ```
+    ///
+    /// `layout` and presets like `datadog` name the default keys anew, and
+    /// are to be called first.
+    pub fn set_msg_key(mut self, key: &str) -> Self {
+        self.config.message.default_key_names.msg = key.to_owned().into();
+        self
+    }
+
+    /// Name the `level` default key `key`, see `set_msg_key`
+    pub fn set_level_key(mut self, key: &str) -> Self {
+        self.config.message.default_key_names.level = key.to_owned().into();
+        self
+    }
+
+    /// Name the `ts` default key `key`, see `set_msg_key`
+    ///
+    /// `timestamp_preset` names it anew, and is to be called first.
+    pub fn set_ts_key(mut self, key: &str) -> Self {
+        self.config.message.default_key_names.ts = key.to_owned().into();
+        self
+    }
+
+    /// Emit the `level` default key as `tracing` names levels
+    ///
+    /// That is `error`, `warn`, `info`, `debug` and `trace`. `tracing` has
+    /// no critical level: `Critical` records are emitted as `error`.
+    pub fn tracing_level_names(mut self, enabled: bool) -> Self {
+        let names = &mut self.config.message.default_key_names;
+        if enabled {
+            names.level_format = LevelFormat::Tracing;
+        } else if names.level_format == LevelFormat::Tracing {
+            names.level_format = LevelFormat::Short;
+        }
+        self
+    }
+
+    /// Lay records out for a given consumer, see `Layout`
+    ///
+    /// Adds the default keys, named as the layout requires, replacing the
+    /// names set by a preset like `datadog`.
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    /// use std::time::{Duration, UNIX_EPOCH};
+    ///
+    /// let buf = slog_json::SharedBuffer::new();
+    /// let drain = slog_json::Json::new(buf.clone())
+    ///     .layout(slog_json::Layout::Gelf)
+    ///     .with_clock(|| UNIX_EPOCH + Duration::from_millis(1_700_000_000_500))
+    ///     .build();
+    /// let log = slog::Logger::root(std::sync::Mutex::new(drain).fuse(), o!());
+    /// info!(log, "hello"; "user" => "ann");
+    ///
+    /// let record: serde_json::Value =
+    ///     serde_json::from_str(&buf.to_string_lossy()).unwrap();
+    /// assert_eq!(record["version"], "1.1");
+    /// assert_eq!(record["short_message"], "hello");
+    /// assert_eq!(record["level"], 6);
+    /// assert_eq!(record["timestamp"], 1_700_000_000.5);
+    /// assert_eq!(record["_user"], "ann");
+    /// ```
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.config.set_default_keys();
+        self.config.message.default_key_names = layout.default_keys();
+        self.config.message.layout = layout;
+        self
+    }
+
+    /// Add a `kind` key with the given value, telling apart the kinds of
+    /// records sharing a stream
+    ///
+    /// The key is emitted right after the default keys.
+    pub fn with_kind(mut self, kind: &str) -> Self {
+        let kind = kind.to_owned();
+        self.config.fields.kind =
+            Some(Arc::new(move |_: &Record| Some(kind.clone())));
+        self
+    }
+
+    /// Add a `kind` key derived from each record by `f`
+    ///
+    /// No key is emitted for records `f` returns `None` for. Replaces the
+    /// value of `with_kind`.
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    ///
+    /// let buf = slog_json::SharedBuffer::new();
+    /// let drain = slog_json::Json::new(buf.clone())
+    ///     .with_dynamic_kind(|record| match record.tag() {
+    ///         "" => None,
+    ///         tag => Some(tag.to_owned()),
+    ///     })
+    ///     .build();
+    /// let log = slog::Logger::root(std::sync::Mutex::new(drain).fuse(), o!());
+    /// info!(log, #"audit", "login"; "user" => "ann");
+    /// info!(log, "hello"; "user" => "ann");
+    /// assert_eq!(
+    ///     buf.to_string_lossy(),
+    ///     "{\"kind\":\"audit\",\"user\":\"ann\"}\n{\"user\":\"ann\"}\n"
+    /// );
+    /// ```
+    ///
+    /// Key-values of the record can be read from `record.kv()` with a
+    /// `slog::Serializer`.
+    pub fn with_dynamic_kind<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Record) -> Option<String> + Send + Sync + 'static,
+    {
+        self.config.fields.kind = Some(Arc::new(f));
+        self
+    }
+
+    /// Register another writer, for `with_writer_selector` to pick
+    ///
+    /// The `io` of `Json::new` is writer 0, and writers registered here
+    /// come next, from 1 on, in order. Without a selector, they are not
+    /// written to.
+    pub fn add_writer(mut self, io: W) -> Self {
+        self.extra_io.push(io);
+        self
+    }
+
+    /// Write every record to the writer picked by `f`
+    ///
+    /// `f` returns the number of the writer, see `add_writer`, and is
+    /// called with each record and the key-values of its logger, before
+    /// anything is serialized. Eg. to route records by a `dest` key:
+    ///
+    /// ```
+    /// use slog::{info, o, Drain, Key, Record, KV};
+    /// use std::fmt;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// /// Writer of the `dest` key-value of a record, if any
+    /// struct Dest(usize);
+    ///
+    /// impl slog::Serializer for Dest {
+    ///     fn emit_arguments(
+    ///         &mut self,
+    ///         key: Key,
+    ///         val: &fmt::Arguments,
+    ///     ) -> slog::Result {
+    ///         if key == "dest" && val.to_string() == "audit" {
+    ///             self.0 = 1;
+    ///         }
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let drain = slog_json::Json::new(vec![])
+    ///     .add_writer(vec![])
+    ///     .with_writer_selector(|record: &Record, _| {
+    ///         let mut dest = Dest(0);
+    ///         record.kv().serialize(record, &mut dest)?;
+    ///         Ok(dest.0)
+    ///     })
+    ///     .build();
+    /// let drain = Arc::new(Mutex::new(drain));
+    /// let log = slog::Logger::root(drain.clone().fuse(), o!());
+    /// info!(log, "login"; "dest" => "audit");
+    /// info!(log, "ping"; "dest" => "main");
+    ///
+    /// drop(log);
+    /// let drain = Arc::try_unwrap(drain).ok().unwrap();
+    /// let out = drain.into_inner().unwrap().finish_all().unwrap();
+    /// assert_eq!(out[0], b"{\"dest\":\"main\"}\n");
+    /// assert_eq!(out[1], b"{\"dest\":\"audit\"}\n");
+    /// ```
+    ///
+    /// A record `f` fails for, or picks a writer that does not exist for,
+    /// is not written: the error, of kind `InvalidInput` for a missing
+    /// writer, is returned from `Drain::log`, unless discarded by
+    /// `discard_errors`. Each writer has its own `record_index`,
+    /// header and trailer, as with `ShardedJson`.
+    pub fn with_writer_selector<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Record, &OwnedKVList) -> io::Result<usize>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.selector = Some(Arc::new(f));
+        self
+    }
+
+    /// Only write the records `f` returns `true` for
+    ///
+    /// `f` is called with each record and the key-values of its logger,
+    /// before anything is serialized, and ahead of the rate limiting of
+    /// `rate_limit_per_level`, so that dropped records do not count
+    /// against it. Eg. to drop health checks:
+    ///
+    /// ```
+    /// use slog::{info, o, Drain, Key, Record, KV};
+    /// use std::fmt;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// /// Whether a record has a `path` key-value of `/health`
+    /// struct IsHealthCheck(bool);
+    ///
+    /// impl slog::Serializer for IsHealthCheck {
+    ///     fn emit_arguments(
+    ///         &mut self,
+    ///         key: Key,
+    ///         val: &fmt::Arguments,
+    ///     ) -> slog::Result {
+    ///         self.0 |= key == "path" && val.to_string() == "/health";
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let drain = slog_json::Json::new(vec![])
+    ///     .with_record_filter(|record: &Record, _| {
+    ///         let mut check = IsHealthCheck(false);
+    ///         let _ = record.kv().serialize(record, &mut check);
+    ///         !check.0
+    ///     })
+    ///     .build();
+    /// let drain = Arc::new(Mutex::new(drain));
+    /// let log = slog::Logger::root(drain.clone().fuse(), o!());
+    /// info!(log, "request"; "path" => "/health");
+    /// info!(log, "request"; "path" => "/users");
+    ///
+    /// drop(log);
+    /// let drain = Arc::try_unwrap(drain).ok().unwrap();
+    /// let out = drain.into_inner().unwrap().finish().unwrap();
+    /// assert_eq!(out, b"{\"path\":\"/users\"}\n");
+    /// ```
+    ///
+    /// Reading key-values in `f` takes a pass over them of its own, on top
+    /// of the one serializing the records that are kept, and formats
+    /// values through `fmt::Arguments` as in the example: a filter looking
+    /// at key-values adds to the cost of every record, written or not.
+    /// Filters on the level or tag of records are best done with
+    /// `slog::Filter` or `slog::LevelFilter` instead, which spare the
+    /// drain the records altogether.
+    pub fn with_record_filter<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Record, &OwnedKVList) -> bool + Send + Sync + 'static,
+    {
+        self.config.admission.record_filter = Some(Arc::new(f));
+        self
+    }
+
+    /// Add `trace.id` and `span.id` keys with the OpenTelemetry context of
+    /// the record, as read by `f`
+    ///
+    /// `f` is called on every record, on the thread logging it unless the
+    /// drain is behind `slog_async`, and returns the ids of the current
+    /// trace and span, if any. Eg. with the context kept in a thread-local:
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    /// use std::cell::RefCell;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// thread_local! {
+    ///     static CONTEXT: RefCell<Option<(String, String)>> = RefCell::new(None);
+    /// }
+    ///
+    /// let drain = slog_json::Json::new(vec![])
+    ///     .with_otel_context_fn(|| CONTEXT.with(|c| c.borrow().clone()))
+    ///     .build();
+    /// let drain = Arc::new(Mutex::new(drain));
+    /// let log = slog::Logger::root(drain.clone().fuse(), o!());
+    /// info!(log, "outside");
+    /// CONTEXT.with(|c| {
+    ///     *c.borrow_mut() = Some(("4bf92f35".into(), "00f067aa".into()));
+    /// });
+    /// info!(log, "inside");
+    ///
+    /// drop(log);
+    /// let drain = Arc::try_unwrap(drain).ok().unwrap();
+    /// let out = drain.into_inner().unwrap().finish().unwrap();
+    /// assert_eq!(
+    ///     String::from_utf8(out).unwrap(),
+    ///     "{}\n{\"trace.id\":\"4bf92f35\",\"span.id\":\"00f067aa\"}\n",
+    /// );
+    /// ```
+    ///
+    /// Both keys are emitted after `kind`, or neither when `f` returns
+    /// `None`.
+    pub fn with_otel_context_fn<F>(mut self, f: F) -> Self
+    where
+        F: Fn() -> Option<(String, String)> + Send + Sync + 'static,
+    {
+        self.config.fields.otel_context = Some(Arc::new(f));
+        self
+    }
+
+    /// Add a `labels` object with the given labels
+    ///
+    /// Labels from `with_dynamic_labels` are merged into it, overriding
+    /// these.
+    pub fn with_labels(
+        mut self,
+        labels: serde_json::Map<String, serde_json::Value>,
+    ) -> Self {
+        self.config.fields.labels = labels;
+        self
+    }
+
+    /// Merge labels derived from each record by `f` into the `labels`
+    /// object
+    ///
+    /// On conflict, labels returned by `f` override the ones of
+    /// `with_labels`.
+    ///
+    /// ```
+    /// use serde_json::json;
+    /// use slog::{info, o, Drain};
+    ///
+    /// let labels = json!({"region": "eu-west-1", "tier": "web"});
+    /// let buf = slog_json::SharedBuffer::new();
+    /// let drain = slog_json::Json::new(buf.clone())
+    ///     .with_labels(labels.as_object().unwrap().clone())
+    ///     .with_dynamic_labels(|record| {
+    ///         let mut labels = serde_json::Map::new();
+    ///         if !record.tag().is_empty() {
+    ///             labels.insert("tier".into(), record.tag().into());
+    ///         }
+    ///         labels
+    ///     })
+    ///     .build();
+    /// let log = slog::Logger::root(std::sync::Mutex::new(drain).fuse(), o!());
+    /// info!(log, #"batch", "hello");
+    ///
+    /// let record: serde_json::Value =
+    ///     serde_json::from_str(&buf.to_string_lossy()).unwrap();
+    /// assert_eq!(
+    ///     record["labels"],
+    ///     json!({"region": "eu-west-1", "tier": "batch"})
+    /// );
+    /// ```
+    pub fn with_dynamic_labels<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Record) -> serde_json::Map<String, serde_json::Value>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.config.fields.labels_fn = Some(Arc::new(f));
+        self
+    }
+
+    /// Add a `record_index` key counting the records logged by the drain
+    ///
+    /// The first record has index 0. The count is kept by the drain, so
+    /// all configurations of a `TieredJson` share it, and can be restarted
+    /// with `Json::reset_index`.
+    pub fn add_record_index(mut self) -> Self {
+        self.config.fields.record_index = true;
+        self
+    }
+
+    /// Add `file` and `line` keys with the source location of the record
+    pub fn add_source_location(mut self) -> Self {
+        self.config.fields.source_location = true;
+        self
+    }
+
+    /// Emit the `line` of `add_source_location` as a string, like `"42"`
+    ///
+    /// For consumers mapping the field as a string rather than a number.
+    pub fn line_as_string(mut self, enabled: bool) -> Self {
+        self.config.fields.line_as_string = enabled;
+        self
+    }
+
+    /// Add a `cpu_ms` key with the CPU time used by the logging thread
+    ///
+    /// The CPU time of the thread so far, in milliseconds, from
+    /// `clock_gettime(CLOCK_THREAD_CPUTIME_ID)`. This is only supported on
+    /// Linux, Android and macOS; elsewhere it is `null`. Note that it is the
+    /// thread calling the drain that is measured: behind `slog-async` it is
+    /// the logging thread, not the one that created the record.
+    ///
+    /// Requires the `cpu-time` feature.
+    #[cfg(feature = "cpu-time")]
+    pub fn add_cpu_time(mut self) -> Self {
+        self.config.fields.cpu_time = true;
+        self
+    }
+
+    /// Emit the values of loggers as a `spans` array, one entry per scope
+    ///
+    /// A scope is a group of key-values given to `Logger::new` whose first
+    /// key is `SPAN_KEY`:
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    /// use std::sync::Mutex;
+    ///
+    /// let buf = slog_json::SharedBuffer::new();
+    /// let drain = slog_json::Json::new(buf.clone()).set_spans(true).build();
+    /// let log = slog::Logger::root(Mutex::new(drain).fuse(), o!());
+    /// let req = log.new(o!("span" => "request", "path" => "/"));
+    /// let db = req.new(o!("span" => "query", "table" => "users"));
+    /// info!(db, "hello");
+    ///
+    /// let record: serde_json::Value =
+    ///     serde_json::from_str(&buf.to_string_lossy()).unwrap();
+    /// assert_eq!(
+    ///     record["spans"],
+    ///     serde_json::json!([
+    ///         {"span": "request", "path": "/"},
+    ///         {"span": "query", "table": "users"},
+    ///     ])
+    /// );
+    /// ```
+    ///
+    /// Spans are listed outermost scope first. Values of loggers without
+    /// `SPAN_KEY` belong to the enclosing span, or stay top-level keys
+    /// outside of any. `SPAN_KEY` has to come first: keys given ahead of it,
+    /// as in `o!("table" => "users", "span" => "query")`, belong to the
+    /// enclosing span too. Keys within an entry are not kept in order.
+    pub fn set_spans(mut self, enabled: bool) -> Self {
+        self.config.fields.spans = enabled;
+        self
+    }
+
+    /// Emit the `SUBSYSTEM_KEY` logger value as a `subsystem` key, right
+    /// after the default keys
+    ///
+    /// For routing records by the part of the code base they come from,
+    /// which slog does not track: loggers handed to a subsystem are given
+    /// its name, and records logged through them or their children carry
+    /// it in a fixed place, with the `_subsystem` key itself left out.
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let drain = slog_json::Json::new(vec![]).lift_subsystem(true).build();
+    /// let drain = Arc::new(Mutex::new(drain));
+    /// let log = slog::Logger::root(drain.clone().fuse(), o!());
+    /// let db = log.new(o!("_subsystem" => "storage", "pool" => 4));
+    /// info!(db, "connected");
+    ///
+    /// drop((log, db));
+    /// let drain = Arc::try_unwrap(drain).ok().unwrap();
+    /// let out = drain.into_inner().unwrap().finish().unwrap();
+    /// assert_eq!(out, b"{\"subsystem\":\"storage\",\"pool\":4}\n");
+    /// ```
+    ///
+    /// The innermost logger with a `_subsystem` key wins. Only the values
+    /// of loggers are looked at: a `_subsystem` key logged with a record is
+    /// emitted as is.
+    pub fn lift_subsystem(mut self, enabled: bool) -> Self {
+        self.config.fields.lift_subsystem = enabled;
+        self
+    }
+
+    /// Include the entries of the thread-local `mdc` map in every record
+    ///
+    /// They are emitted before the values of the logger and the record,
+    /// so a key set in both is emitted twice, the value of the record last:
+    /// `{"user":"a","user":"b"}`. With `DuplicateKeyPolicy::Suffix`, the
+    /// second one is renamed instead: `{"user":"a","user_2":"b"}`.
+    pub fn include_mdc(mut self, enabled: bool) -> Self {
+        self.config.fields.mdc = enabled;
+        self
+    }
+
+    /// Add a `record_bytes` key with the size of the serialized record
+    ///
+    /// The size is that of the record without `record_bytes` itself, which
+    /// is emitted last, and without the trailing newline. Adding the key
+    /// makes the line longer by its own length. Only emitted in JSON, see
+    /// `JsonBuilder`.
+    pub fn add_record_size(mut self, enabled: bool) -> Self {
+        self.config.tracking.record_size = enabled;
+        self
+    }
+
+    /// Add a `serialize_ns` key with the time taken to serialize the record
+    ///
+    /// Measured in nanoseconds from when the drain starts writing the
+    /// record, after rate limiting, to right before `serialize_ns` itself
+    /// is serialized, near the end of the record: only `record_bytes` and
+    /// the closing brace come after it. Writing the record to the `io`
+    /// happens along the way, so is included for unbuffered writers.
+    /// Only emitted in JSON, see `JsonBuilder`.
+    pub fn add_serialize_timing(mut self, enabled: bool) -> Self {
+        self.config.tracking.serialize_timing = enabled;
+        self
+    }
+
+    /// Write records as canonical JSON, for them to be signed
+    ///
+    /// Records are written as RFC 8785 canonical JSON: keys sorted by their
+    /// UTF-16 code units, in nested objects too, no whitespace, and numbers
+    /// formatted as ECMAScript does, like `1e+21` or `0.000001`. So records
+    /// with the same key-values are written as the same bytes, whatever
+    /// order they were logged in. A key logged more than once is written
+    /// once, with the value logged last.
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let drain = slog_json::Json::new(vec![]).canonical(true).build();
+    /// let drain = Arc::new(Mutex::new(drain));
+    /// let log = slog::Logger::root(drain.clone().fuse(), o!());
+    /// info!(log, "signed"; "b" => 1.0, "a" => 2);
+    /// info!(log, "signed"; "a" => 2, "b" => 1.0);
+    ///
+    /// drop(log);
+    /// let drain = Arc::try_unwrap(drain).ok().unwrap();
+    /// let out = drain.into_inner().unwrap().finish().unwrap();
+    /// assert_eq!(out, b"{\"a\":2,\"b\":1}\n{\"a\":2,\"b\":1}\n");
+    /// ```
+    ///
+    /// Numbers are doubles in canonical JSON: integers beyond 2^53 are
+    /// written as the nearest one, and are best logged as strings. Overrides
+    /// `set_pretty`, `escape_forward_slashes` and `json5_unquoted_keys`, and
+    /// records have no `event_id`, `record_bytes` or `serialize_ns` key,
+    /// which describe the output of the drain. Records are buffered to be
+    /// sorted.
+    pub fn canonical(mut self, enabled: bool) -> Self {
+        let format = &mut self.config.output.format;
+        if enabled {
+            *format = Format::Canonical;
+        } else if let Format::Canonical = format {
+            *format = Format::Json;
+        }
+        self
+    }
+
+    /// Emit key-values in an order that does not depend on how they were
+    /// logged
+    ///
+    /// `ts`, `level` and `msg` come first, in that order, followed by all
+    /// other keys sorted alphabetically; `event_id` and `record_bytes` are
+    /// still emitted last. Records are buffered to be sorted, and a key
+    /// logged more than once is emitted once, with the value logged last.
+    ///
+    /// Meant for output compared against golden files.
+    pub fn stable_output(mut self, enabled: bool) -> Self {
+        self.config.restructure.stable_output = enabled;
+        self
+    }
+
+    /// Nest the values of dotted keys into objects
+    ///
+    /// The inverse of `add_flattened_object`: `"http.status" => 200,
+    /// "http.method" => "GET"` is emitted as
+    /// `"http":{"method":"GET","status":200}`, in place of the first of
+    /// them. Records are buffered to be restructured. A key that is also the
+    /// prefix of other keys, like `http` along with `http.status`, keeps its
+    /// value, and the other keys are emitted as they are.
+    pub fn unflatten_dotted_keys(mut self, enabled: bool) -> Self {
+        self.config.restructure.unflatten = enabled;
+        self
+    }
+
+    /// Emit a key-value only once when it is logged several times with the
+    /// same value
+    ///
+    /// Like a key set with the same value by both the logger and the
+    /// record. Only exact repeats are dropped: a key logged with different
+    /// values is still emitted once per value. Records are buffered to be
+    /// compared.
+    pub fn collapse_identical_duplicates(mut self, enabled: bool) -> Self {
+        self.config.restructure.collapse_duplicates = enabled;
+        self
+    }
+
+    /// Set what to do with keys emitted more than once in a record
+    ///
+    /// Like a key set by both the logger and the record. By default, all
+    /// are emitted; backends rejecting duplicate keys can have them renamed
+    /// with `DuplicateKeyPolicy::Suffix` instead. Repeats are found after
+    /// `collapse_identical_duplicates`, among all keys including the
+    /// default ones. Records are buffered to be compared.
+    pub fn duplicate_key_policy(mut self, policy: DuplicateKeyPolicy) -> Self {
+        self.config.restructure.duplicate_keys = policy;
+        self
+    }
+
+    /// Add a `fields_count` key with the number of key-values logged
+    ///
+    /// Counts the key-values of the record and of its loggers, as logged:
+    /// default keys and the ones added by this builder are left out.
+    /// Records are buffered to be counted.
+    pub fn add_fields_count(mut self, enabled: bool) -> Self {
+        self.config.restructure.fields_count = enabled;
+        self
+    }
+
+    /// Add a `distinct_keys` key with the number of distinct keys logged
+    ///
+    /// Like `add_fields_count`, but a key logged more than once, eg. by
+    /// both the logger and the record, is counted once: the count is the
+    /// one of the keys left after deduplication.
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let drain = slog_json::Json::new(vec![])
+    ///     .add_distinct_key_count(true)
+    ///     .add_fields_count(true)
+    ///     .build();
+    /// let drain = Arc::new(Mutex::new(drain));
+    /// let log = slog::Logger::root(drain.clone().fuse(), o!("user" => "a"));
+    /// info!(log, "login"; "user" => "b", "attempt" => 2);
+    /// drop(log);
+    ///
+    /// let drain = Arc::try_unwrap(drain).ok().unwrap();
+    /// let out = drain.into_inner().unwrap().finish().unwrap();
+    /// let out = String::from_utf8(out).unwrap();
+    /// assert!(out.contains(r#""fields_count":3"#));
+    /// assert!(out.contains(r#""distinct_keys":2"#));
+    /// ```
+    pub fn add_distinct_key_count(mut self, enabled: bool) -> Self {
+        self.config.restructure.distinct_key_count = enabled;
+        self
+    }
+
+    /// Make sure every record has a `key`, adding a random UUID if missing
+    ///
+    /// So that every record can be correlated, while keeping the values
+    /// logged under `key`. Records are buffered to be checked. The UUIDs
+    /// are version 4, generated by the `uuid` crate.
+    #[cfg(feature = "uuid")]
+    pub fn ensure_correlation_id(mut self, key: &str) -> Self {
+        self.config.fields.correlation_key = Some(key.to_owned());
+        self
+    }
+
+    /// Check every record against the JSON Schema `schema`, handling the
+    /// ones not matching it as `policy` says
+    ///
+    /// Records are buffered to be checked as they are written, with the
+    /// value logged last of a key logged more than once. Records dropped
+    /// are not counted by `Json::dropped_count`. Fails if `schema` is not
+    /// a valid JSON Schema.
+    ///
+    /// ```
+    /// use serde_json::json;
+    /// use slog::{info, o, Drain};
+    /// use slog_json::ValidationPolicy;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let schema = json!({"required": ["user"]});
+    /// let drain = slog_json::Json::new(vec![])
+    ///     .with_schema_validator(schema, ValidationPolicy::Drop)
+    ///     .unwrap()
+    ///     .build();
+    /// let drain = Arc::new(Mutex::new(drain));
+    /// let log = slog::Logger::root(drain.clone().fuse(), o!());
+    /// info!(log, "login"; "user" => "a");
+    /// info!(log, "logout");
+    /// drop(log);
+    ///
+    /// let drain = Arc::try_unwrap(drain).ok().unwrap();
+    /// let out = drain.into_inner().unwrap().finish().unwrap();
+    /// assert_eq!(out, b"{\"user\":\"a\"}\n");
+    /// ```
+    #[cfg(feature = "jsonschema")]
+    pub fn with_schema_validator(
+        mut self,
+        schema: serde_json::Value,
+        policy: ValidationPolicy,
+    ) -> io::Result<Self> {
+        self.config.admission.schema =
+            Some(SchemaValidator::new(&schema, policy)?);
+        Ok(self)
+    }
+
+    /// Add an `event_id` key with a hash of the content of the record
+    ///
+    /// For idempotent ingestion: records with the same content get the
+    /// same id. The hash covers the record as serialized before `event_id`,
+    /// which is emitted last (but for `record_bytes`), leaving out the keys
+    /// that change on every record: `ts`, `record_index` and `cpu_ms`.
+    /// Only emitted in JSON, see `JsonBuilder`.
+    pub fn content_hash_id(mut self, algo: HashAlgo) -> Self {
+        self.config.tracking.content_hash = Some(algo);
+        self
+    }
+
+    /// Keep a running hash of all records, written in a trailer line by
+    /// `Json::finish`
+    ///
+    /// The trailer, `{"_trailer":{"count":...,"hash":"..."}}`, seals the
+    /// output: `count` is the number of records and `hash` the hash of
+    /// their lines as written, newlines included, in order. Lines written
+    /// ahead of the records, like the header of `delta_timestamps`, are
+    /// not covered.
+    pub fn with_sealed_trailer(mut self, algo: HashAlgo) -> Self {
+        self.config.output.seal = Some(algo);
+        self
+    }
+
+    /// Keep the offset of every `every`th record, written in a footer
+    /// line by `Json::finish`
+    ///
+    /// For seeking in large files without reading them through. The footer
+    /// is the last line of the output:
+    /// `{"_seek_index":{"every":100,"offsets":[0,18342,36417]}}`, where
+    /// `offsets` are the byte offsets of the first, `every + 1`th,
+    /// `2 * every + 1`th... records, from the start of the output of the
+    /// drain. An offset points at the first byte of the record: its type
+    /// tag, if any, past the comma of `with_leading_comma`.
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let drain = slog_json::Json::new(vec![]).with_seek_index(2).build();
+    /// let drain = Arc::new(Mutex::new(drain));
+    /// let log = slog::Logger::root(drain.clone().fuse(), o!());
+    /// for n in 0..5 {
+    ///     info!(log, "tick"; "n" => n);
+    /// }
+    ///
+    /// drop(log);
+    /// let drain = Arc::try_unwrap(drain).ok().unwrap();
+    /// let out = drain.into_inner().unwrap().finish().unwrap();
+    /// let out = String::from_utf8(out).unwrap();
+    /// let footer = out.lines().last().unwrap();
+    /// assert_eq!(footer, r#"{"_seek_index":{"every":2,"offsets":[0,16,32]}}"#);
+    /// assert!(out[32..].starts_with(r#"{"n":4}"#));
+    /// ```
+    ///
+    /// Only records logged count, not the lines of `with_level_summary`,
+    /// though all bytes ahead of a record count towards its offset. Offsets
+    /// are into the output as serialized, before any `with_compression`.
+    /// The footer comes after the trailer of `with_sealed_trailer`. An
+    /// `every` of 0 disables the index.
+    pub fn with_seek_index(mut self, every: usize) -> Self {
+        self.config.output.seek_every =
+            if every == 0 { None } else { Some(every) };
+        self
+    }
+
+    /// Replace line breaks in the `msg` default key with a space
+    ///
+    /// This is not about JSON escaping, which always happens: a message
+    /// spanning several lines is otherwise emitted with `\n` escapes and
+    /// shows up as a multi-line message to consumers. With this enabled it
+    /// is collapsed into a single line before serialization.
+    pub fn collapse_msg_newlines(mut self, enabled: bool) -> Self {
+        self.config.message.collapse_msg_newlines = enabled;
+        self
+    }
+
+    /// Strip leading and trailing whitespace from the `msg` default key
+    ///
+    /// `"  hello \n"` is then emitted as `"hello"`. Whitespace inside the
+    /// message is kept.
+    pub fn trim_msg(mut self, enabled: bool) -> Self {
+        self.config.message.trim_msg = enabled;
+        self
+    }
+
+    /// Emit `placeholder` as the `msg` default key of blank messages
+    ///
+    /// Messages that are empty or only whitespace, whether or not
+    /// `trim_msg` is enabled, are replaced with `placeholder`.
+    pub fn blank_msg_placeholder(mut self, placeholder: &str) -> Self {
+        self.config.message.blank_msg = Some(placeholder.to_owned());
+        self
+    }
+
+    /// Rename keys logged under the name of a default key, prefixing them
+    /// with `user_`
+    ///
+    /// A record or logger key-value named `ts`, say, is then emitted as
+    /// `user_ts` instead of standing next to the timestamp of the drain,
+    /// which consumers may take the last of. Applies to the key-values of
+    /// records, loggers and `add_key_value`, and to the `mdc`, under the
+    /// names set by `layout` or presets like `datadog`, when default keys
+    /// are added.
+    pub fn protect_reserved_keys(mut self, enabled: bool) -> Self {
+        self.config.message.protect_reserved = enabled;
+        self
+    }
+
+    /// Add a `level_usize` key with the level as a number
+    ///
+    /// The raw `slog::Level::as_usize`, from 1 for `Critical` to 6 for
+    /// `Trace`, emitted right after the `level` default key, and only
+    /// along with it: for checking how levels are mapped by
+    /// `tracing_level_names`, `layout` or presets.
+    pub fn add_level_debug_info(mut self, enabled: bool) -> Self {
+        self.config.message.level_debug_info = enabled;
+        self
+    }
+
+    /// Add a `summary` key with the first `max_len` characters of the
+    /// message
+    ///
+    /// For interfaces listing records by a short line. It is emitted right
+    /// after the `msg` default key, which is still emitted whole, and only
+    /// along with it.
+    pub fn add_summary(mut self, max_len: usize) -> Self {
+        self.config.message.summary_len = Some(max_len);
+        self
+    }
+}
+
+#[cfg(feature = "tty")]
+impl<W> JsonBuilder<W>
+where
+    W: io::Write + Tty,
+{
+    /// Use pretty formatting if the `io` is a terminal
+    ///
+    /// For people reading the output as it is written, while programs
+    /// reading it from a file or a pipe still get compact records. Checked
+    /// once, when called: only standard output and error can be detected,
+    /// and only on Unix, see `Tty`. Does nothing if `enabled` is false.
+    ///
+    /// ```
+    /// use slog::{info, o, Drain};
+    /// use slog_json::Tty;
+    /// use std::io;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// struct Terminal(Vec<u8>);
+    ///
+    /// impl io::Write for Terminal {
+    ///     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    ///         self.0.write(buf)
+    ///     }
+    ///     fn flush(&mut self) -> io::Result<()> {
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// impl Tty for Terminal {
+    ///     fn is_tty(&self) -> bool {
+    ///         true
+    ///     }
+    /// }
+    ///
+    /// let drain = slog_json::Json::new(Terminal(vec![]))
+    ///     .pretty_if_tty(true)
+    ///     .build();
+    /// let drain = Arc::new(Mutex::new(drain));
+    /// let log = slog::Logger::root(drain.clone().fuse(), o!());
+    /// info!(log, "hello"; "n" => 1);
+    /// drop(log);
+    ///
+    /// let drain = Arc::try_unwrap(drain).ok().unwrap();
+    /// let Terminal(out) = drain.into_inner().unwrap().finish().unwrap();
+    /// assert_eq!(out, b"{\n  \"n\": 1\n}\n");
+    /// ```
+    pub fn pretty_if_tty(mut self, enabled: bool) -> Self {
+        if enabled && self.io.is_tty() {
+            self.config.output.pretty = true;
+        }
+        self
+    }
+}
+// }}}
+
+// {{{ Presets
+/// Builder of a `Json` `Drain` for the Datadog log pipeline
+///
+/// The default keys are named as Datadog expects them: `message`,
+/// `status`, with lowercase level words (`critical`, `error`, `warning`,
+/// `info`, `debug`, `trace`), and `timestamp`. `service` and `ddsource`
+/// keys are added with the given values.
+///
+/// ```
+/// use slog::{o, warn, Drain};
+///
+/// let buf = slog_json::SharedBuffer::new();
+/// let drain = slog_json::datadog(buf.clone(), "api", "rust")
+///     .with_clock(|| std::time::UNIX_EPOCH)
+///     .build();
+/// let log = slog::Logger::root(std::sync::Mutex::new(drain).fuse(), o!());
+/// warn!(log, "slow"; "ms" => 900);
+/// assert_eq!(
+///     buf.to_string_lossy(),
+///     "{\"message\":\"slow\",\"status\":\"warning\",\
+///      \"timestamp\":\"1970-01-01T00:00:00Z\",\
+///      \"service\":\"api\",\"ddsource\":\"rust\",\"ms\":900}\n"
+/// );
+/// ```
+pub fn datadog<W>(io: W, service: &str, source: &str) -> JsonBuilder<W>
+where
+    W: io::Write,
+{
+    let mut builder = Json::new(io).add_default_keys();
+    builder.config.message.default_key_names = DefaultKeys {
+        msg: "message".into(),
+        level: "status".into(),
+        ts: "timestamp".into(),
+        level_format: LevelFormat::Word,
+        epoch_ts: false,
+        epoch_precision: None,
+    };
+    let constants = &mut builder.config.fields.constants;
+    constants.push(("service".into(), service.into()));
+    constants.push(("ddsource".into(), source.into()));
+    builder
+}
+
+/// Layout of the records, picked with `JsonBuilder::layout`
+///
+/// Every layout names the default keys for a given consumer, adding the
+/// keys it requires. Other key-values are emitted as logged, unless noted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Layout {
+    /// `msg`, `level` and `ts` next to the other key-values
+    Flat,
+    /// `msg`, `level` and `ts`, with all other key-values nested in a
+    /// `fields` object
+    Envelope,
+    /// Elastic Common Schema: `message`, `log.level` and `@timestamp`,
+    /// along with `ecs.version`
+    Ecs,
+    /// GELF 1.1, as read by Graylog: `short_message`, a syslog severity
+    /// `level` and a `timestamp` in seconds, along with `version` and
+    /// `host`. Other keys are prefixed with `_`, but for `id` and `_id`,
+    /// which GELF reserves, emitted as `__id`
+    Gelf,
+    /// Bunyan: `msg`, a numeric `level` and `time`, along with `v`, `name`
+    /// (of the executable), `hostname` and `pid`
+    Bunyan,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::Flat
+    }
+}
+
+/// Keys of GELF messages that are not additional fields
+const GELF_KEYS: &[&str] = &[
+    "version",
+    "host",
+    "short_message",
+    "full_message",
+    "timestamp",
+    "level",
+    "facility",
+    "line",
+    "file",
+];
+
+impl Layout {
+    fn default_keys(self) -> DefaultKeys {
+        let flat = DefaultKeys::default();
+        match self {
+            Layout::Flat | Layout::Envelope => flat,
+            Layout::Ecs => DefaultKeys {
+                msg: "message".into(),
+                level: "log.level".into(),
+                ts: "@timestamp".into(),
+                level_format: LevelFormat::Word,
+                ..flat
+            },
+            Layout::Gelf => DefaultKeys {
+                msg: "short_message".into(),
+                level: "level".into(),
+                ts: "timestamp".into(),
+                level_format: LevelFormat::Syslog,
+                epoch_ts: true,
+                epoch_precision: None,
+            },
+            Layout::Bunyan => DefaultKeys {
+                ts: "time".into(),
+                level_format: LevelFormat::Bunyan,
+                ..flat
+            },
+        }
+    }
+
+    /// Key-values required by the layout, resolved once
+    fn constants(self) -> Vec<(String, serde_json::Value)> {
+        let host = || hostname().unwrap_or_else(|| "localhost".into());
+        match self {
+            Layout::Flat | Layout::Envelope => vec![],
+            Layout::Ecs => vec![("ecs.version".into(), "1.6.0".into())],
+            Layout::Gelf => vec![
+                ("version".into(), "1.1".into()),
+                ("host".into(), host().into()),
+            ],
+            Layout::Bunyan => {
+                let name = std::env::current_exe()
                     .ok()
-            }),
-            "level" => FnValue(move |rinfo : &Record| {
-                rinfo.level().as_short_str()
-            }),
-            "msg" => PushFnValue(move |record : &Record, ser| {
-                ser.emit(record.msg())
-            }),
-        ))
+                    .and_then(|exe| {
+                        Some(exe.file_stem()?.to_string_lossy().into_owned())
+                    })
+                    .unwrap_or_default();
+                vec![
+                    ("v".into(), 0.into()),
+                    ("name".into(), name.into()),
+                    ("hostname".into(), host().into()),
+                    ("pid".into(), std::process::id().into()),
+                ]
+            }
+        }
+    }
+
+    /// Whether records are buffered to be restructured
+    fn is_buffered(self) -> bool {
+        matches!(self, Layout::Envelope | Layout::Gelf)
+    }
+
+    /// Restructure the key-values of a buffered record
+    fn apply(
+        self,
+        names: &DefaultKeys,
+        kvs: Vec<(String, serde_json::Value)>,
+    ) -> Vec<(String, serde_json::Value)> {
+        let is_default = |k: &str| {
+            [&names.msg, &names.level, &names.ts]
+                .iter()
+                .any(|n| *n == k)
+        };
+        match self {
+            Layout::Envelope => {
+                let (mut out, fields): (Vec<_>, Vec<_>) =
+                    kvs.into_iter().partition(|(k, _)| is_default(k));
+                let fields = fields.into_iter().collect();
+                out.push(("fields".into(), serde_json::Value::Object(fields)));
+                out
+            }
+            Layout::Gelf => kvs
+                .into_iter()
+                .map(|(k, v)| {
+                    if k == "id" || k == "_id" {
+                        ("__id".into(), v)
+                    } else if GELF_KEYS.contains(&&*k) || k.starts_with('_') {
+                        (k, v)
+                    } else {
+                        (format!("_{}", k), v)
+                    }
+                })
+                .collect(),
+            _ => kvs,
+        }
     }
 }
 // }}}
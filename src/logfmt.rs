@@ -0,0 +1,74 @@
+// {{{ Logfmt
+//! logfmt-style output with JSON values, see `JsonBuilder::build_logfmt`
+use crate::Json;
+use serde_json::Value;
+use slog::{OwnedKVList, Record};
+use std::fmt::Write;
+use std::io;
+
+/// logfmt-style `Drain`, with JSON values
+///
+/// Each record is written as a line of tab-separated `key=value` pairs,
+/// where the value is JSON: `msg="hello"\tlevel="INFO"\tn=1\ttags=["a"]`.
+/// Create with `JsonBuilder::build_logfmt`: records have the same
+/// key-values as with `Json`, but for the ones describing JSON output, see
+/// `JsonBuilder`.
+///
+/// Keys are written as is, but for those with whitespace, `=` or `"` in
+/// them, which are written as JSON strings.
+pub struct Logfmt<W: io::Write>(pub(crate) Json<W>);
+
+impl<W> Logfmt<W>
+where
+    W: io::Write,
+{
+    /// Number of records that failed to be serialized or written
+    pub fn dropped_count(&self) -> u64 {
+        self.0.dropped_count()
+    }
+}
+
+impl<W> slog::Drain for Logfmt<W>
+where
+    W: io::Write,
+{
+    type Ok = ();
+    type Err = io::Error;
+    fn log(
+        &self,
+        rinfo: &Record,
+        logger_values: &OwnedKVList,
+    ) -> io::Result<()> {
+        self.0.log(rinfo, logger_values)
+    }
+}
+
+/// Write `kvs` as a line of `key=value` pairs, without the newline
+pub(crate) fn write_record(out: &mut Vec<u8>, kvs: &[(String, Value)]) {
+    let mut line = String::with_capacity(256);
+    write_line(&mut line, kvs);
+    out.extend_from_slice(line.as_bytes());
+}
+
+fn write_line(out: &mut String, kvs: &[(String, Value)]) {
+    for (i, (k, v)) in kvs.iter().enumerate() {
+        if i > 0 {
+            out.push('\t');
+        }
+        if needs_quotes(k) {
+            let _ = write!(out, "{}", Value::from(k.as_str()));
+        } else {
+            out.push_str(k);
+        }
+        let _ = write!(out, "={}", v);
+    }
+}
+
+fn needs_quotes(key: &str) -> bool {
+    key.is_empty()
+        || key
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || "=\"".contains(c))
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
@@ -0,0 +1,54 @@
+// {{{ MDC
+//! Thread-local context map, included in records logged from its thread
+//!
+//! Modelled on SLF4J's Mapped Diagnostic Context: entries `put` on a thread
+//! are emitted with every record that thread logs, by drains built with
+//! `JsonBuilder::include_mdc`.
+//!
+//! ```
+//! slog_json::mdc::put("request_id", "d7f1");
+//! // ... records logged here carry `"request_id": "d7f1"`
+//! slog_json::mdc::remove("request_id");
+//! ```
+//!
+//! The map is read on the thread calling the drain, so this only works for
+//! drains called synchronously, eg. behind a `Mutex`: with `slog-async`,
+//! records are serialized on the logging thread, which has its own map.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+thread_local! {
+    static MDC: RefCell<BTreeMap<String, serde_json::Value>> =
+        RefCell::new(BTreeMap::new())
+}
+
+/// Set `key` to `value` in the map of the current thread
+pub fn put<K, V>(key: K, value: V)
+where
+    K: Into<String>,
+    V: Into<serde_json::Value>,
+{
+    MDC.with(|mdc| {
+        mdc.borrow_mut().insert(key.into(), value.into());
+    })
+}
+
+/// Remove `key` from the map of the current thread, returning its value
+pub fn remove(key: &str) -> Option<serde_json::Value> {
+    MDC.with(|mdc| mdc.borrow_mut().remove(key))
+}
+
+/// Remove all entries from the map of the current thread
+pub fn clear() {
+    MDC.with(|mdc| mdc.borrow_mut().clear())
+}
+
+/// Call `f` with the map of the current thread
+pub(crate) fn with<F, R>(f: F) -> R
+where
+    F: FnOnce(&BTreeMap<String, serde_json::Value>) -> R,
+{
+    MDC.with(|mdc| f(&mdc.borrow()))
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
@@ -0,0 +1,277 @@
+// {{{ Memory-mapped ring
+//! Records kept in a memory-mapped file, see `MmapRingJson`
+use crate::{DrainState, JsonConfig};
+use libc::{
+    c_void, mmap, munmap, MAP_FAILED, MAP_SHARED, PROT_READ, PROT_WRITE,
+};
+use slog::{OwnedKVList, Record};
+use std::cell::RefCell;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+/// First bytes of ring files, with the version of their layout
+const MAGIC: &[u8; 8] = b"SLOGRNG1";
+
+/// Size of the header, ahead of the ring itself
+///
+/// The header is made of `MAGIC` and three little-endian `u64`s: the size
+/// of the ring, the offset of the end of the last record written, and the
+/// offset of the end of the record being written, if any. Offsets count
+/// the bytes written since the file was created, so the ring holds the
+/// bytes from `offset - size` on.
+const HEADER_LEN: usize = 32;
+const SIZE_AT: usize = 8;
+const HEAD_AT: usize = 16;
+const RESERVED_AT: usize = 24;
+
+/// Size of the length of records, written both before and after them so
+/// that the ring can be read backwards from its end
+const LEN_LEN: u64 = 4;
+
+/// Json `Drain` keeping the last records in a memory-mapped file
+///
+/// For post-mortem analysis: the file is a ring of fixed size, each new
+/// record overwriting the oldest ones, and is kept up to date by the
+/// operating system, so that it holds the last records even after the
+/// process crashed. These are read back with `MmapRingJson::recover`, eg.
+/// by a supervisor or at the next start, before the file is created anew.
+///
+/// ```
+/// use slog::{info, o, Drain};
+/// use slog_json::{JsonBuilder, MmapRingJson};
+///
+/// let path = std::env::temp_dir().join("slog-json-ring-doctest");
+/// let config = JsonBuilder::config().set_newlines(false).build_config();
+/// let drain = MmapRingJson::create(&path, 64, config).unwrap();
+/// let log = slog::Logger::root(std::sync::Mutex::new(drain).fuse(), o!());
+/// for n in 0..10 {
+///     info!(log, "tick"; "n" => n);
+/// }
+/// drop(log);
+///
+/// // Only the last records fit in 64 bytes
+/// let records = MmapRingJson::recover(&path).unwrap();
+/// assert_eq!(records.last().unwrap(), r#"{"n":9}"#);
+/// assert!(records.len() < 10);
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+///
+/// The file survives crashes of the process, not of the operating system:
+/// pages are written back to disk as it sees fit. A crash in the middle of
+/// a record loses that record only. Only supported on Linux, Android and
+/// macOS.
+pub struct MmapRingJson {
+    config: JsonConfig,
+    state: DrainState,
+    ring: RefCell<Ring>,
+}
+
+impl MmapRingJson {
+    /// New `MmapRingJson` `Drain`, writing to a ring of `size` bytes in a
+    /// new file at `path`
+    ///
+    /// Any existing file at `path` is overwritten. Records take 8 bytes of
+    /// the ring on top of their JSON; ones that do not fit in it at all are
+    /// dropped.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        size: usize,
+        config: JsonConfig,
+    ) -> io::Result<Self> {
+        Ok(MmapRingJson {
+            config,
+            state: DrainState::new(),
+            ring: RefCell::new(Ring::create(path.as_ref(), size)?),
+        })
+    }
+
+    /// Records of the ring file at `path`, oldest first
+    ///
+    /// The file is read as is, whether the drain writing it is gone or
+    /// not: records being written at the time are left out, as is the
+    /// oldest one when it was partly overwritten.
+    pub fn recover<P: AsRef<Path>>(path: P) -> io::Result<Vec<String>> {
+        let file = fs::read(path)?;
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        if file.len() < HEADER_LEN || &file[..MAGIC.len()] != MAGIC {
+            return Err(invalid("not a ring file"));
+        }
+        let (header, data) = file.split_at(HEADER_LEN);
+        let size = read_u64(header, SIZE_AT);
+        let head = read_u64(header, HEAD_AT);
+        let reserved = read_u64(header, RESERVED_AT);
+        if size == 0 || data.len() as u64 != size || reserved < head {
+            return Err(invalid("corrupt ring file header"));
+        }
+
+        // Bytes before `oldest` were overwritten, or are being
+        let oldest = reserved.saturating_sub(size);
+        let mut records = vec![];
+        let mut end = head;
+        while end >= oldest + 2 * LEN_LEN {
+            let mut len = [0; LEN_LEN as usize];
+            read_ring(data, end - LEN_LEN, &mut len);
+            let len = u64::from(u32::from_le_bytes(len));
+            let start = match (end - oldest).checked_sub(len + 2 * LEN_LEN) {
+                Some(from_oldest) => oldest + from_oldest,
+                None => break,
+            };
+            let mut start_len = [0; LEN_LEN as usize];
+            read_ring(data, start, &mut start_len);
+            if u64::from(u32::from_le_bytes(start_len)) != len {
+                break;
+            }
+            let mut record = vec![0; len as usize];
+            read_ring(data, start + LEN_LEN, &mut record);
+            records.push(String::from_utf8_lossy(&record).into_owned());
+            end = start;
+        }
+        records.reverse();
+        Ok(records)
+    }
+
+    /// Number of records that failed to be serialized or did not fit in
+    /// the ring, and were discarded
+    ///
+    /// See `Json::dropped_count`.
+    pub fn dropped_count(&self) -> u64 {
+        self.state.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl slog::Drain for MmapRingJson {
+    type Ok = ();
+    type Err = io::Error;
+    fn log(
+        &self,
+        rinfo: &Record,
+        logger_values: &OwnedKVList,
+    ) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(256);
+        self.state
+            .log(&self.config, &mut buf, rinfo, logger_values)?;
+        let res = self.ring.borrow_mut().push(&buf);
+        self.state.settle(&self.config, res)
+    }
+}
+
+/// Mapping of a ring file
+struct Ring {
+    map: *mut u8,
+    len: usize,
+}
+
+// Safety: the mapping is owned by the `Ring`, like a `Vec` owns its buffer
+unsafe impl Send for Ring {}
+
+impl Ring {
+    fn create(path: &Path, size: usize) -> io::Result<Self> {
+        if size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "ring size must not be zero",
+            ));
+        }
+        let len = HEADER_LEN + size;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(len as u64)?;
+        // Safety: `file` is open for reading and writing, and at least
+        // `len` bytes long
+        let map = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if map == MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let mut ring = Ring {
+            map: map as *mut u8,
+            len,
+        };
+        let bytes = ring.bytes();
+        bytes[..MAGIC.len()].copy_from_slice(MAGIC);
+        write_u64(bytes, SIZE_AT, size as u64);
+        Ok(ring)
+    }
+
+    fn bytes(&mut self) -> &mut [u8] {
+        // Safety: the mapping is `len` bytes long, and only accessed
+        // through the `Ring`
+        unsafe { std::slice::from_raw_parts_mut(self.map, self.len) }
+    }
+
+    /// Append `record` to the ring
+    fn push(&mut self, record: &[u8]) -> io::Result<()> {
+        let (header, data) = self.bytes().split_at_mut(HEADER_LEN);
+        let len = record.len() as u64;
+        if len + 2 * LEN_LEN > data.len() as u64 || len > u64::from(u32::MAX) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "record larger than the ring",
+            ));
+        }
+        let head = read_u64(header, HEAD_AT);
+        let end = head + len + 2 * LEN_LEN;
+        // Readers skip the bytes about to be overwritten from now on
+        write_u64(header, RESERVED_AT, end);
+        let len = (len as u32).to_le_bytes();
+        write_ring(data, head, &len);
+        write_ring(data, head + LEN_LEN, record);
+        write_ring(data, end - LEN_LEN, &len);
+        write_u64(header, HEAD_AT, end);
+        Ok(())
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        // Safety: the mapping is not used past this point
+        unsafe {
+            munmap(self.map as *mut c_void, self.len);
+        }
+    }
+}
+
+fn read_u64(header: &[u8], at: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&header[at..at + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn write_u64(header: &mut [u8], at: usize, value: u64) {
+    header[at..at + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Read `out.len()` bytes of `ring` from `offset`, wrapping around its end
+fn read_ring(ring: &[u8], offset: u64, out: &mut [u8]) {
+    let at = (offset % ring.len() as u64) as usize;
+    let first = std::cmp::min(out.len(), ring.len() - at);
+    let (out_first, out_rest) = out.split_at_mut(first);
+    out_first.copy_from_slice(&ring[at..at + first]);
+    out_rest.copy_from_slice(&ring[..out_rest.len()]);
+}
+
+/// Write `bytes` to `ring` from `offset`, wrapping around its end
+fn write_ring(ring: &mut [u8], offset: u64, bytes: &[u8]) {
+    let at = (offset % ring.len() as u64) as usize;
+    let first = std::cmp::min(bytes.len(), ring.len() - at);
+    let (bytes_first, bytes_rest) = bytes.split_at(first);
+    ring[at..at + first].copy_from_slice(bytes_first);
+    ring[..bytes_rest.len()].copy_from_slice(bytes_rest);
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
@@ -0,0 +1,138 @@
+// {{{ Rate limiting
+//! Per-level token buckets, see `JsonBuilder::rate_limit_per_level`
+use slog::Level;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Minimum time between two summaries of throttled records
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time from `earlier` to `now`, zero if the clock went back
+fn since(now: SystemTime, earlier: SystemTime) -> Duration {
+    now.duration_since(earlier).unwrap_or_default()
+}
+
+/// Bucket refilled with `rate` tokens per second, holding at most `rate`
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    /// Unset until the first record
+    refilled_at: Option<SystemTime>,
+}
+
+impl Bucket {
+    fn take(&mut self, now: SystemTime) -> bool {
+        let elapsed = since(now, *self.refilled_at.get_or_insert(now));
+        self.refilled_at = Some(now);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.rate);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+struct State {
+    buckets: Vec<Bucket>,
+    /// Records throttled since the last summary, by level
+    throttled: [u64; 6],
+    /// Unset until the first record
+    summarized_at: Option<SystemTime>,
+}
+
+impl State {
+    /// Call `summary` with the levels and counts of the records throttled
+    /// since the last summary, and reset them
+    fn summarize<F>(&mut self, mut summary: F)
+    where
+        F: FnMut(Level, u64),
+    {
+        for (i, n) in self.throttled.iter_mut().enumerate() {
+            if *n > 0 {
+                if let Some(level) = Level::from_usize(i + 1) {
+                    summary(level, *n);
+                }
+                *n = 0;
+            }
+        }
+    }
+}
+
+/// Rate limits of records, by level
+pub(crate) struct RateLimiter {
+    state: Mutex<State>,
+}
+
+impl RateLimiter {
+    /// Limiter letting `limits[i]` records per second through, for levels
+    /// from `Critical` to `Trace`
+    pub(crate) fn new(limits: [u32; 6]) -> Self {
+        let buckets = limits
+            .iter()
+            .map(|&limit| Bucket {
+                rate: f64::from(limit),
+                tokens: f64::from(limit),
+                refilled_at: None,
+            })
+            .collect();
+        RateLimiter {
+            state: Mutex::new(State {
+                buckets,
+                throttled: [0; 6],
+                summarized_at: None,
+            }),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Whether a record at `level`, logged at `now`, may be emitted
+    ///
+    /// Either way, `summary` is called first with the levels and counts of
+    /// the records throttled since the last summary, when there are some
+    /// and the last summary is old enough.
+    pub(crate) fn admit<F>(
+        &self,
+        level: Level,
+        now: SystemTime,
+        summary: F,
+    ) -> bool
+    where
+        F: FnMut(Level, u64),
+    {
+        let mut state = self.lock();
+        let i = level.as_usize() - 1;
+        let admitted = state.buckets[i].take(now);
+        if !admitted {
+            state.throttled[i] += 1;
+        }
+
+        let summarized_at = *state.summarized_at.get_or_insert(now);
+        if since(now, summarized_at) >= SUMMARY_INTERVAL
+            && state.throttled.iter().any(|&n| n > 0)
+        {
+            state.summarized_at = Some(now);
+            state.summarize(summary);
+        }
+        admitted
+    }
+
+    /// Call `summary` with the records throttled since the last summary,
+    /// however recent
+    pub(crate) fn flush<F>(&self, summary: F)
+    where
+        F: FnMut(Level, u64),
+    {
+        self.lock().summarize(summary)
+    }
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
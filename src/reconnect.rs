@@ -0,0 +1,190 @@
+// {{{ ReconnectingWriter
+//! Writer re-establishing its connection on failure, see `ReconnectingWriter`
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Buffered bytes above which a write is sent without waiting for `flush`
+const MAX_BUFFERED: usize = 8 * 1024;
+
+/// `io::Write` over a connection that is re-established on failure
+///
+/// Intended as the writer of a `Json` drain shipping records straight to
+/// a local collector socket. Writes are buffered until `flush`, so use it
+/// with `JsonBuilder::set_flush(true)`: every record is then sent as a
+/// whole, and a record that fails to send is retried once on a fresh
+/// connection instead of being split across two of them.
+///
+/// When connecting fails, further attempts are held off with exponential
+/// backoff; records flushed in the meantime are dropped and reported as
+/// `io::ErrorKind::NotConnected`.
+///
+/// `write` only fails when sending the bytes buffered so far fails, in
+/// which case those bytes are dropped and none of the new ones are taken.
+///
+/// Note that a peer closing the connection is usually only noticed by the
+/// write after the one that got lost in the kernel buffers.
+pub struct ReconnectingWriter<S: io::Write> {
+    connect: Box<dyn FnMut() -> io::Result<S> + Send>,
+    stream: Option<S>,
+    buf: Vec<u8>,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    backoff: Duration,
+    retry_at: Option<Instant>,
+}
+
+impl<S> ReconnectingWriter<S>
+where
+    S: io::Write,
+{
+    /// New writer using `connect` to (re)establish the connection
+    ///
+    /// The first connection is made lazily, on first `flush`.
+    pub fn new<F>(connect: F) -> Self
+    where
+        F: FnMut() -> io::Result<S> + Send + 'static,
+    {
+        let initial_backoff = Duration::from_millis(100);
+        ReconnectingWriter {
+            connect: Box::new(connect),
+            stream: None,
+            buf: Vec::with_capacity(256),
+            initial_backoff,
+            max_backoff: Duration::from_secs(30),
+            backoff: initial_backoff,
+            retry_at: None,
+        }
+    }
+
+    /// Set the backoff between failed connection attempts
+    ///
+    /// The delay starts at `initial` and doubles after every failed
+    /// attempt, up to `max`. Defaults to 100ms and 30s.
+    pub fn set_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self.backoff = initial;
+        self
+    }
+
+    /// Whether there is currently an established connection
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    fn reconnect(&mut self) -> io::Result<&mut S> {
+        if let Some(retry_at) = self.retry_at {
+            if Instant::now() < retry_at {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "waiting to reconnect",
+                ));
+            }
+        }
+        match (self.connect)() {
+            Ok(stream) => {
+                self.retry_at = None;
+                self.backoff = self.initial_backoff;
+                Ok(self.stream.get_or_insert(stream))
+            }
+            Err(e) => {
+                self.retry_at = Some(Instant::now() + self.backoff);
+                self.backoff =
+                    std::cmp::min(self.backoff * 2, self.max_backoff);
+                Err(e)
+            }
+        }
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        if let Some(stream) = self.stream.as_mut() {
+            if stream
+                .write_all(&self.buf)
+                .and_then(|_| stream.flush())
+                .is_ok()
+            {
+                return Ok(());
+            }
+            self.stream = None;
+        }
+
+        let buf = std::mem::take(&mut self.buf);
+        let stream = self.reconnect();
+        let res =
+            stream.and_then(|s| s.write_all(&buf).and_then(|_| s.flush()));
+        if res.is_err() {
+            self.stream = None;
+        }
+        self.buf = buf;
+        res
+    }
+}
+
+impl ReconnectingWriter<TcpStream> {
+    /// New writer connecting over TCP to `addr`
+    ///
+    /// `addr` is resolved again on every reconnection.
+    pub fn tcp<A>(addr: A) -> Self
+    where
+        A: ToSocketAddrs + Send + 'static,
+    {
+        ReconnectingWriter::new(move || TcpStream::connect(&addr))
+    }
+}
+
+#[cfg(unix)]
+impl ReconnectingWriter<UnixStream> {
+    /// New writer connecting to the Unix domain socket at `path`
+    pub fn unix<P>(path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        let path = path.into();
+        ReconnectingWriter::new(move || UnixStream::connect(&path))
+    }
+}
+
+impl<S> io::Write for ReconnectingWriter<S>
+where
+    S: io::Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Send what is buffered before taking `buf`, so that an error never
+        // follows bytes that were accepted.
+        if !self.buf.is_empty() && self.buf.len() + buf.len() > MAX_BUFFERED {
+            self.flush()?;
+        }
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return match self.stream.as_mut() {
+                Some(stream) => stream.flush(),
+                None => Ok(()),
+            };
+        }
+        let res = self.send();
+        // A record that could not be sent is dropped rather than piling up
+        // while the peer is away.
+        self.buf.clear();
+        res
+    }
+}
+
+impl<S> Drop for ReconnectingWriter<S>
+where
+    S: io::Write,
+{
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
@@ -0,0 +1,67 @@
+// {{{ Schema validation
+//! Records checked against a JSON Schema, see
+//! `JsonBuilder::with_schema_validator`
+use jsonschema::JSONSchema;
+use serde_json::Value;
+use std::io;
+
+/// What to do with records not matching the schema of
+/// `JsonBuilder::with_schema_validator`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ValidationPolicy {
+    /// Discard the record, as if it was not logged
+    Drop,
+    /// Return an `io::ErrorKind::InvalidData` error, writing nothing
+    Error,
+    /// Write the record with a `_schema_errors` array of the violations
+    Annotate,
+}
+
+/// Compiled schema, along with its policy
+pub(crate) struct SchemaValidator {
+    schema: JSONSchema,
+    policy: ValidationPolicy,
+}
+
+impl SchemaValidator {
+    pub(crate) fn new(
+        schema: &Value,
+        policy: ValidationPolicy,
+    ) -> io::Result<Self> {
+        let schema = JSONSchema::compile(schema).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid JSON Schema: {}", e),
+            )
+        })?;
+        Ok(SchemaValidator { schema, policy })
+    }
+
+    /// Key-values of a record as they are to be written, or `None` if it
+    /// is to be dropped
+    pub(crate) fn check(
+        &self,
+        mut kvs: Vec<(String, Value)>,
+    ) -> io::Result<Option<Vec<(String, Value)>>> {
+        // Validated as read back, with the value logged last of a key
+        let record = Value::Object(kvs.iter().cloned().collect());
+        let errors: Vec<String> = match self.schema.validate(&record) {
+            Ok(()) => return Ok(Some(kvs)),
+            Err(errors) => errors.map(|e| e.to_string()).collect(),
+        };
+        match self.policy {
+            ValidationPolicy::Drop => Ok(None),
+            ValidationPolicy::Error => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("record does not match schema: {}", errors.join("; ")),
+            )),
+            ValidationPolicy::Annotate => {
+                kvs.push(("_schema_errors".into(), errors.into()));
+                Ok(Some(kvs))
+            }
+        }
+    }
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
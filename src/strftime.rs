@@ -0,0 +1,262 @@
+// {{{ strftime
+//! Timestamps formatted by a `strftime` format string, see
+//! `JsonBuilder::set_ts_format`
+use std::fmt::Write;
+use std::io;
+use time::OffsetDateTime;
+
+/// Specifier of a format string, without its `%`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Spec {
+    Year,
+    YearShort,
+    Month,
+    Day,
+    DayOfYear,
+    Hour,
+    Minute,
+    Second,
+    /// `%Y-%m-%d`
+    Date,
+    /// `%H:%M:%S`
+    Time,
+    /// Fraction of the second in so many digits, or in the fewest of 3, 6
+    /// or 9 that are exact and none for a whole second, after a `.` if set
+    Fraction(Option<u32>, bool),
+    EpochSeconds,
+    /// Offset from UTC, with a `:` between hours and minutes if set
+    Offset(bool),
+    Zone,
+    Percent,
+}
+
+/// Specifiers by their name, longest first where one is a prefix of another
+const SPECS: &[(&str, Spec)] = &[
+    (".3f", Spec::Fraction(Some(3), true)),
+    (".6f", Spec::Fraction(Some(6), true)),
+    (".9f", Spec::Fraction(Some(9), true)),
+    (".f", Spec::Fraction(None, true)),
+    ("3f", Spec::Fraction(Some(3), false)),
+    ("6f", Spec::Fraction(Some(6), false)),
+    ("9f", Spec::Fraction(Some(9), false)),
+    ("f", Spec::Fraction(Some(9), false)),
+    (":z", Spec::Offset(true)),
+    ("z", Spec::Offset(false)),
+    ("Z", Spec::Zone),
+    ("Y", Spec::Year),
+    ("y", Spec::YearShort),
+    ("m", Spec::Month),
+    ("d", Spec::Day),
+    ("j", Spec::DayOfYear),
+    ("H", Spec::Hour),
+    ("M", Spec::Minute),
+    ("S", Spec::Second),
+    ("F", Spec::Date),
+    ("T", Spec::Time),
+    ("s", Spec::EpochSeconds),
+    ("%", Spec::Percent),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Item {
+    Literal(String),
+    Spec(Spec),
+}
+
+/// Timestamp format, in `strftime` syntax, see `JsonBuilder::set_ts_format`
+///
+/// The specifiers are the ones of `chrono`, less those of names: `%Y`,
+/// `%y`, `%m`, `%d`, `%j`, `%H`, `%M`, `%S`, `%F`, `%T`, `%s`, `%z`, `%:z`,
+/// `%Z` and `%%`, and for fractions of seconds `%f`, `%.f`, `%.3f`,
+/// `%.6f`, `%.9f`, `%3f`, `%6f` and `%9f`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TsFormat(Vec<Item>);
+
+impl TsFormat {
+    /// Parse `format`
+    ///
+    /// Fails with an `io::ErrorKind::InvalidInput` error naming the first
+    /// specifier that is not supported, if any.
+    pub fn parse(format: &str) -> io::Result<Self> {
+        let mut items = vec![];
+        let mut literal = String::new();
+        let mut rest = format;
+        while let Some(at) = rest.find('%') {
+            literal.push_str(&rest[..at]);
+            rest = &rest[at + 1..];
+            let (name, spec) = SPECS
+                .iter()
+                .find(|(name, _)| rest.starts_with(name))
+                .ok_or_else(|| {
+                    let end = rest.chars().next().map_or(0, char::len_utf8);
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "unsupported timestamp specifier %{}",
+                            &rest[..end]
+                        ),
+                    )
+                })?;
+            rest = &rest[name.len()..];
+            if !literal.is_empty() {
+                items.push(Item::Literal(std::mem::take(&mut literal)));
+            }
+            items.push(Item::Spec(*spec));
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            items.push(Item::Literal(literal));
+        }
+        Ok(TsFormat(items))
+    }
+
+    pub(crate) fn format(&self, ts: OffsetDateTime) -> String {
+        let mut out = String::with_capacity(32);
+        for item in &self.0 {
+            match *item {
+                Item::Literal(ref literal) => out.push_str(literal),
+                Item::Spec(spec) => write_spec(&mut out, spec, ts),
+            }
+        }
+        out
+    }
+}
+
+fn write_spec(out: &mut String, spec: Spec, ts: OffsetDateTime) {
+    let _ = match spec {
+        Spec::Year => write!(out, "{:04}", ts.year()),
+        Spec::YearShort => write!(out, "{:02}", ts.year().rem_euclid(100)),
+        Spec::Month => write!(out, "{:02}", u8::from(ts.month())),
+        Spec::Day => write!(out, "{:02}", ts.day()),
+        Spec::DayOfYear => write!(out, "{:03}", ts.ordinal()),
+        Spec::Hour => write!(out, "{:02}", ts.hour()),
+        Spec::Minute => write!(out, "{:02}", ts.minute()),
+        Spec::Second => write!(out, "{:02}", ts.second()),
+        Spec::Date => write!(
+            out,
+            "{:04}-{:02}-{:02}",
+            ts.year(),
+            u8::from(ts.month()),
+            ts.day()
+        ),
+        Spec::Time => write!(
+            out,
+            "{:02}:{:02}:{:02}",
+            ts.hour(),
+            ts.minute(),
+            ts.second()
+        ),
+        Spec::Fraction(digits, dot) => {
+            let nanos = ts.nanosecond();
+            let digits = match digits {
+                Some(digits) => digits,
+                None if nanos == 0 => return,
+                None if nanos % 1_000_000 == 0 => 3,
+                None if nanos % 1_000 == 0 => 6,
+                None => 9,
+            };
+            let value = nanos / 10u32.pow(9 - digits);
+            let dot = if dot { "." } else { "" };
+            write!(out, "{}{:0width$}", dot, value, width = digits as usize)
+        }
+        Spec::EpochSeconds => write!(out, "{}", ts.unix_timestamp()),
+        Spec::Offset(colon) => {
+            let offset = ts.offset().whole_minutes();
+            let sign = if offset < 0 { '-' } else { '+' };
+            let colon = if colon { ":" } else { "" };
+            let (hours, minutes) = (offset.abs() / 60, offset.abs() % 60);
+            write!(out, "{}{:02}{}{:02}", sign, hours, colon, minutes)
+        }
+        Spec::Zone if ts.offset().is_utc() => write!(out, "UTC"),
+        Spec::Zone => write!(out, "{}", ts.offset()),
+        Spec::Percent => write!(out, "%"),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::UtcOffset;
+
+    /// 2023-11-14T22:13:20.123456789Z, the 318th day of the year
+    fn ts() -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp_nanos(1_700_000_000_123_456_789)
+            .unwrap()
+    }
+
+    fn format(format: &str, ts: OffsetDateTime) -> String {
+        TsFormat::parse(format).unwrap().format(ts)
+    }
+
+    #[test]
+    fn date_and_time() {
+        let ts = ts();
+        assert_eq!(format("%Y-%m-%d %H:%M:%S", ts), "2023-11-14 22:13:20");
+        assert_eq!(format("%y/%j", ts), "23/318");
+        assert_eq!(format("%FT%T", ts), "2023-11-14T22:13:20");
+        assert_eq!(format("%s", ts), "1700000000");
+        assert_eq!(format("100%% at %H", ts), "100% at 22");
+        assert_eq!(format("no specifier", ts), "no specifier");
+        assert_eq!(format("", ts), "");
+    }
+
+    #[test]
+    fn padding() {
+        let ts = OffsetDateTime::from_unix_timestamp(3_723).unwrap();
+        assert_eq!(
+            format("%Y-%m-%d %H:%M:%S %j", ts),
+            "1970-01-01 01:02:03 001"
+        );
+        let ts = OffsetDateTime::from_unix_timestamp(-62_135_596_800).unwrap();
+        assert_eq!(format("%Y %y", ts), "0001 01");
+    }
+
+    #[test]
+    fn fractions() {
+        let ts = ts();
+        assert_eq!(format("%f", ts), "123456789");
+        assert_eq!(format("%3f|%6f|%9f", ts), "123|123456|123456789");
+        assert_eq!(format("%.3f|%.6f|%.9f", ts), ".123|.123456|.123456789");
+        assert_eq!(format("%S%.f", ts), "20.123456789");
+
+        let at = |nanos| ts.replace_nanosecond(nanos).unwrap();
+        assert_eq!(format("%S%.f", at(0)), "20");
+        assert_eq!(format("%S%.f", at(120_000_000)), "20.120");
+        assert_eq!(format("%S%.f", at(123_400_000)), "20.123400");
+        assert_eq!(format("%S%.f", at(5)), "20.000000005");
+        assert_eq!(format("%.3f", at(5)), ".000");
+    }
+
+    #[test]
+    fn offsets() {
+        let utc = ts();
+        assert_eq!(format("%z %:z %Z", utc), "+0000 +00:00 UTC");
+        let ist = utc.to_offset(UtcOffset::from_hms(5, 30, 0).unwrap());
+        assert_eq!(format("%H:%M%z %:z", ist), "03:43+0530 +05:30");
+        assert_eq!(format("%Z", ist), "+05:30:00");
+        let est = utc.to_offset(UtcOffset::from_hms(-5, 0, 0).unwrap());
+        assert_eq!(format("%d %H%z", est), "14 17-0500");
+    }
+
+    #[test]
+    fn unsupported_specifiers() {
+        for (format, spec) in [
+            ("%A", "%A"),
+            ("%Y-%b", "%b"),
+            ("%.2f", "%."),
+            ("%é", "%é"),
+            ("trailing %", "%"),
+        ]
+        .iter()
+        {
+            let err = TsFormat::parse(format).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(
+                err.to_string(),
+                format!("unsupported timestamp specifier {}", spec)
+            );
+        }
+    }
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
@@ -0,0 +1,380 @@
+// {{{ Truncating serializer
+//! Conversion of nested values to JSON with arrays cut short, see
+//! `JsonBuilder::max_array_len`
+use serde::ser::{self, Serialize};
+use serde_json::{Error, Map, Value};
+
+/// `serde::Serializer` into a `serde_json::Value`, keeping at most `max`
+/// elements of every array
+///
+/// Elements past `max` are not serialized at all, but counted, and stand
+/// for a last element like `"<90 more>"`.
+pub(crate) struct Truncating {
+    pub(crate) max: usize,
+}
+
+/// Keys of maps, which have to be strings in JSON
+fn map_key(key: Value) -> Result<String, Error> {
+    match key {
+        Value::String(key) => Ok(key),
+        Value::Number(key) => Ok(key.to_string()),
+        Value::Bool(key) => Ok(key.to_string()),
+        _ => Err(ser::Error::custom("key must be a string")),
+    }
+}
+
+impl ser::Serializer for Truncating {
+    type Ok = Value;
+    type Error = Error;
+    type SerializeSeq = SerializeArray;
+    type SerializeTuple = SerializeArray;
+    type SerializeTupleStruct = SerializeArray;
+    type SerializeTupleVariant = SerializeVariant<SerializeArray>;
+    type SerializeMap = SerializeObject;
+    type SerializeStruct = SerializeObject;
+    type SerializeStructVariant = SerializeVariant<SerializeObject>;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, Error> {
+        Ok(v.into())
+    }
+    fn serialize_i8(self, v: i8) -> Result<Value, Error> {
+        Ok(v.into())
+    }
+    fn serialize_i16(self, v: i16) -> Result<Value, Error> {
+        Ok(v.into())
+    }
+    fn serialize_i32(self, v: i32) -> Result<Value, Error> {
+        Ok(v.into())
+    }
+    fn serialize_i64(self, v: i64) -> Result<Value, Error> {
+        Ok(v.into())
+    }
+    fn serialize_i128(self, v: i128) -> Result<Value, Error> {
+        serde_json::value::Serializer.serialize_i128(v)
+    }
+    fn serialize_u8(self, v: u8) -> Result<Value, Error> {
+        Ok(v.into())
+    }
+    fn serialize_u16(self, v: u16) -> Result<Value, Error> {
+        Ok(v.into())
+    }
+    fn serialize_u32(self, v: u32) -> Result<Value, Error> {
+        Ok(v.into())
+    }
+    fn serialize_u64(self, v: u64) -> Result<Value, Error> {
+        Ok(v.into())
+    }
+    fn serialize_u128(self, v: u128) -> Result<Value, Error> {
+        serde_json::value::Serializer.serialize_u128(v)
+    }
+    fn serialize_f32(self, v: f32) -> Result<Value, Error> {
+        Ok(v.into())
+    }
+    fn serialize_f64(self, v: f64) -> Result<Value, Error> {
+        Ok(v.into())
+    }
+    fn serialize_char(self, v: char) -> Result<Value, Error> {
+        Ok(v.to_string().into())
+    }
+    fn serialize_str(self, v: &str) -> Result<Value, Error> {
+        Ok(v.into())
+    }
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, Error> {
+        use ser::SerializeSeq;
+        let mut seq = self.serialize_seq(Some(v.len()))?;
+        for b in v {
+            seq.serialize_element(b)?;
+        }
+        seq.end()
+    }
+    fn serialize_none(self) -> Result<Value, Error> {
+        Ok(Value::Null)
+    }
+    fn serialize_some<T>(self, value: &T) -> Result<Value, Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<Value, Error> {
+        Ok(Value::Null)
+    }
+    fn serialize_unit_struct(
+        self,
+        _name: &'static str,
+    ) -> Result<Value, Error> {
+        Ok(Value::Null)
+    }
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Value, Error> {
+        Ok(variant.into())
+    }
+    fn serialize_newtype_struct<T>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Value, Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value, Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let mut map = Map::new();
+        map.insert(variant.into(), value.serialize(self)?);
+        Ok(Value::Object(map))
+    }
+    fn serialize_seq(
+        self,
+        len: Option<usize>,
+    ) -> Result<SerializeArray, Error> {
+        let cap = len.map_or(0, |len| std::cmp::min(len, self.max) + 1);
+        Ok(SerializeArray {
+            max: self.max,
+            elements: Vec::with_capacity(cap),
+            skipped: 0,
+        })
+    }
+    fn serialize_tuple(self, len: usize) -> Result<SerializeArray, Error> {
+        self.serialize_seq(Some(len))
+    }
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeArray, Error> {
+        self.serialize_seq(Some(len))
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeArray>, Error> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+    fn serialize_map(
+        self,
+        _len: Option<usize>,
+    ) -> Result<SerializeObject, Error> {
+        Ok(SerializeObject {
+            max: self.max,
+            map: Map::new(),
+            key: None,
+        })
+    }
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeObject, Error> {
+        self.serialize_map(Some(len))
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeObject>, Error> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_map(Some(len))?,
+        })
+    }
+}
+
+pub(crate) struct SerializeArray {
+    max: usize,
+    elements: Vec<Value>,
+    skipped: usize,
+}
+
+impl SerializeArray {
+    fn push<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        if self.elements.len() < self.max {
+            let value = value.serialize(Truncating { max: self.max })?;
+            self.elements.push(value);
+        } else {
+            self.skipped += 1;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Value {
+        if self.skipped > 0 {
+            self.elements
+                .push(format!("<{} more>", self.skipped).into());
+        }
+        Value::Array(self.elements)
+    }
+}
+
+impl ser::SerializeSeq for SerializeArray {
+    type Ok = Value;
+    type Error = Error;
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTuple for SerializeArray {
+    type Ok = Value;
+    type Error = Error;
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeArray {
+    type Ok = Value;
+    type Error = Error;
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+pub(crate) struct SerializeObject {
+    max: usize,
+    map: Map<String, Value>,
+    /// Key of the value to be serialized next
+    key: Option<String>,
+}
+
+impl SerializeObject {
+    fn insert<T>(&mut self, key: String, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let value = value.serialize(Truncating { max: self.max })?;
+        self.map.insert(key, value);
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for SerializeObject {
+    type Ok = Value;
+    type Error = Error;
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let key = key.serialize(serde_json::value::Serializer)?;
+        self.key = Some(map_key(key)?);
+        Ok(())
+    }
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let key = self.key.take().expect("value serialized before its key");
+        self.insert(key, value)
+    }
+    fn end(self) -> Result<Value, Error> {
+        Ok(Value::Object(self.map))
+    }
+}
+
+impl ser::SerializeStruct for SerializeObject {
+    type Ok = Value;
+    type Error = Error;
+    fn serialize_field<T>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.insert(key.into(), value)
+    }
+    fn end(self) -> Result<Value, Error> {
+        Ok(Value::Object(self.map))
+    }
+}
+
+/// Enum variant with fields, serialized as `{"<variant>": <fields>}`
+pub(crate) struct SerializeVariant<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl<S> SerializeVariant<S> {
+    fn wrap(variant: &'static str, value: Value) -> Value {
+        let mut map = Map::new();
+        map.insert(variant.into(), value);
+        Value::Object(map)
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeArray> {
+    type Ok = Value;
+    type Error = Error;
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.inner.push(value)
+    }
+    fn end(self) -> Result<Value, Error> {
+        Ok(Self::wrap(self.variant, self.inner.finish()))
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeObject> {
+    type Ok = Value;
+    type Error = Error;
+    fn serialize_field<T>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.inner.insert(key.into(), value)
+    }
+    fn end(self) -> Result<Value, Error> {
+        Ok(Self::wrap(self.variant, Value::Object(self.inner.map)))
+    }
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
@@ -0,0 +1,54 @@
+// {{{ Terminal detection
+//! Whether standard streams are terminals, see `JsonBuilder::pretty_if_tty`
+use std::io;
+
+#[cfg(unix)]
+use libc::isatty;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+
+/// Writer that may be a terminal, see `JsonBuilder::pretty_if_tty`
+///
+/// Implemented for the standard output and error streams. Only detected on
+/// Unix: they are never taken to be terminals elsewhere.
+pub trait Tty {
+    /// Whether the writer is a terminal
+    fn is_tty(&self) -> bool;
+}
+
+#[cfg(unix)]
+fn fd_is_tty<T: AsRawFd>(stream: &T) -> bool {
+    // Safety: `isatty` only looks at the file descriptor
+    unsafe { isatty(stream.as_raw_fd()) == 1 }
+}
+
+#[cfg(not(unix))]
+fn fd_is_tty<T>(_stream: &T) -> bool {
+    false
+}
+
+impl Tty for io::Stdout {
+    fn is_tty(&self) -> bool {
+        fd_is_tty(self)
+    }
+}
+
+impl Tty for io::Stderr {
+    fn is_tty(&self) -> bool {
+        fd_is_tty(self)
+    }
+}
+
+impl Tty for io::StdoutLock<'_> {
+    fn is_tty(&self) -> bool {
+        fd_is_tty(self)
+    }
+}
+
+impl Tty for io::StderrLock<'_> {
+    fn is_tty(&self) -> bool {
+        fd_is_tty(self)
+    }
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
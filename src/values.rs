@@ -0,0 +1,96 @@
+// {{{ Serde values
+//! `slog::SerdeValue` wrappers emitting structured JSON
+use serde::ser::SerializeMap;
+use slog::{Key, Record};
+use std::fmt;
+
+/// Outcome of an operation, emitted as `{"ok": ...}` or `{"err": ...}`
+///
+/// ```
+/// use slog::{info, o, Drain};
+/// use slog_json::{Json, ResultValue, SharedBuffer};
+///
+/// let buf = SharedBuffer::new();
+/// let drain = Json::new(buf.clone()).build();
+/// let log = slog::Logger::root(std::sync::Mutex::new(drain).fuse(), o!());
+/// let saved: Result<u32, String> = Ok(3);
+/// info!(log, "saved"; "result" => ResultValue(saved));
+/// let failed: Result<u32, String> = Err("disk full".into());
+/// info!(log, "saved"; "result" => ResultValue(failed));
+///
+/// assert_eq!(
+///     buf.to_string_lossy(),
+///     "{\"result\":{\"ok\":3}}\n{\"result\":{\"err\":\"disk full\"}}\n"
+/// );
+/// ```
+///
+/// Results whose values are only `Display` can be converted with
+/// `ResultValue::display`.
+#[derive(Clone)]
+pub struct ResultValue<T, E>(pub Result<T, E>)
+where
+    T: serde::Serialize + Clone + Send + 'static,
+    E: serde::Serialize + Clone + Send + 'static;
+
+impl ResultValue<String, String> {
+    /// Wrap a `Result`, emitting both variants by their `Display`
+    pub fn display<T, E>(res: &Result<T, E>) -> Self
+    where
+        T: fmt::Display,
+        E: fmt::Display,
+    {
+        ResultValue(match res {
+            Ok(v) => Ok(v.to_string()),
+            Err(e) => Err(e.to_string()),
+        })
+    }
+}
+
+impl<T, E> serde::Serialize for ResultValue<T, E>
+where
+    T: serde::Serialize + Clone + Send + 'static,
+    E: serde::Serialize + Clone + Send + 'static,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(1))?;
+        match &self.0 {
+            Ok(v) => map.serialize_entry("ok", v)?,
+            Err(e) => map.serialize_entry("err", e)?,
+        }
+        map.end()
+    }
+}
+
+impl<T, E> slog::Value for ResultValue<T, E>
+where
+    T: serde::Serialize + Clone + Send + 'static,
+    E: serde::Serialize + Clone + Send + 'static,
+{
+    fn serialize(
+        &self,
+        _record: &Record,
+        key: Key,
+        serializer: &mut dyn slog::Serializer,
+    ) -> slog::Result {
+        serializer.emit_serde(key, self)
+    }
+}
+
+impl<T, E> slog::SerdeValue for ResultValue<T, E>
+where
+    T: serde::Serialize + Clone + Send + 'static,
+    E: serde::Serialize + Clone + Send + 'static,
+{
+    fn as_serde(&self) -> &dyn erased_serde::Serialize {
+        self
+    }
+
+    fn to_sendable(&self) -> Box<dyn slog::SerdeValue + Send + 'static> {
+        Box::new(self.clone())
+    }
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
@@ -0,0 +1,52 @@
+// {{{ YAML
+//! YAML output, see `JsonBuilder::build_yaml`
+use crate::{Json, KvMap};
+use serde_json::Value;
+use slog::{OwnedKVList, Record};
+use std::io;
+
+/// YAML `Drain`
+///
+/// Each record is written as a YAML document, starting with `---`. Create
+/// with `JsonBuilder::build_yaml`: records have the same key-values as with
+/// `Json`, but for the ones describing JSON output, see `JsonBuilder`.
+///
+/// Documents are written by `serde_yaml`, which quotes strings that would
+/// otherwise be read as another type, like `"true"` or `"12"`.
+pub struct Yaml<W: io::Write>(pub(crate) Json<W>);
+
+impl<W> Yaml<W>
+where
+    W: io::Write,
+{
+    /// Number of records that failed to be serialized or written
+    pub fn dropped_count(&self) -> u64 {
+        self.0.dropped_count()
+    }
+}
+
+impl<W> slog::Drain for Yaml<W>
+where
+    W: io::Write,
+{
+    type Ok = ();
+    type Err = io::Error;
+    fn log(
+        &self,
+        rinfo: &Record,
+        logger_values: &OwnedKVList,
+    ) -> io::Result<()> {
+        self.0.log(rinfo, logger_values)
+    }
+}
+
+/// Write `kvs` as a YAML document, with its trailing newline
+pub(crate) fn write_document(
+    out: &mut Vec<u8>,
+    kvs: &[(String, Value)],
+) -> io::Result<()> {
+    serde_yaml::to_writer(out, &KvMap(kvs))
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
+// }}}
+// vim: foldmethod=marker foldmarker={{{,}}}
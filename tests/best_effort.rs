@@ -0,0 +1,86 @@
+//! `JsonBuilder::best_effort_fields`
+#![cfg(feature = "nested-values")]
+
+use slog::{info, o, Drain, Logger};
+use slog_json::{Json, JsonBuilder, ResultValue, SharedBuffer};
+use std::sync::Mutex;
+
+/// Value failing to serialize
+#[derive(Clone)]
+struct Failing;
+
+impl serde::Serialize for Failing {
+    fn serialize<S>(&self, _serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        Err(serde::ser::Error::custom("failing"))
+    }
+}
+
+fn output(
+    build: impl FnOnce(JsonBuilder<SharedBuffer>) -> JsonBuilder<SharedBuffer>,
+) -> String {
+    let buf = SharedBuffer::new();
+    let drain = build(Json::new(buf.clone()).best_effort_fields(true)).build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!("app" => "x"));
+    let bad: ResultValue<Failing, ()> = ResultValue(Ok(Failing));
+    info!(log, "hi"; "a" => 1, "bad" => bad, "b" => 2);
+    buf.to_string_lossy()
+}
+
+#[test]
+fn failing_field_is_replaced() {
+    assert_eq!(
+        output(|b| b),
+        "{\"app\":\"x\",\"b\":2,\"bad\":\"<field error>\",\"a\":1}\n"
+    );
+}
+
+#[test]
+fn failing_field_is_replaced_when_buffered() {
+    assert_eq!(
+        output(|b| b.stable_output(true)),
+        "{\"a\":1,\"app\":\"x\",\"b\":2,\"bad\":\"<field error>\"}\n"
+    );
+}
+
+#[test]
+fn failing_logger_value_is_replaced_when_collected() {
+    // Logger values are collected to be grouped into spans
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .best_effort_fields(true)
+        .set_spans(true)
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    let bad: ResultValue<Failing, ()> = ResultValue(Ok(Failing));
+    let req = log.new(o!("span" => "request", "bad" => bad, "n" => 1));
+    info!(req, "hi");
+    let record: serde_json::Value =
+        serde_json::from_str(&buf.to_string_lossy()).unwrap();
+    assert_eq!(
+        record,
+        serde_json::json!({
+            "spans": [{"span": "request", "bad": "<field error>", "n": 1}],
+        })
+    );
+}
+
+#[test]
+fn failing_field_fails_record_otherwise() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone()).build();
+    let bad: ResultValue<Failing, ()> = ResultValue(Ok(Failing));
+    let res = drain.log(
+        &slog::record!(
+            slog::Level::Info,
+            "",
+            &format_args!("hi"),
+            slog::b!("a" => 1, "bad" => bad)
+        ),
+        &slog::OwnedKVList::from(o!()),
+    );
+    assert!(res.is_err());
+    assert_eq!(drain.dropped_count(), 0);
+}
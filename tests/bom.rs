@@ -0,0 +1,26 @@
+//! `JsonBuilder::with_bom`
+use slog::{info, o, Drain, Logger};
+use slog_json::{Json, SharedBuffer};
+use std::sync::Mutex;
+
+#[test]
+fn bom_precedes_the_first_record_only() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone()).with_bom(true).build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "a"; "n" => 1);
+    info!(log, "b"; "n" => 2);
+
+    let bytes = buf.bytes().clone();
+    assert_eq!(bytes[..3], [0xEF, 0xBB, 0xBF]);
+    assert_eq!(&bytes[3..], b"{\"n\":1}\n{\"n\":2}\n");
+}
+
+#[test]
+fn no_bom_by_default() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone()).build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "a"; "n" => 1);
+    assert_eq!(&buf.bytes()[..], b"{\"n\":1}\n");
+}
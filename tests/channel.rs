@@ -0,0 +1,52 @@
+//! `ChannelJson`
+use slog::{info, o, warn, Drain, Logger};
+use slog_json::{ChannelJson, JsonBuilder};
+use std::sync::{mpsc, Mutex};
+
+#[test]
+fn received_strings_are_the_logged_records() {
+    let (tx, rx) = mpsc::channel();
+    let config = JsonBuilder::config().add_default_keys().build_config();
+    let drain = ChannelJson::new(tx, config);
+    let log = Logger::root(Mutex::new(drain).fuse(), o!("app" => "api"));
+    info!(log, "started"; "port" => 8080);
+    warn!(log, "slow"; "ms" => 900, "path" => "/users");
+    drop(log);
+
+    let received: Vec<String> = rx.iter().collect();
+    assert_eq!(received.len(), 2);
+    let records: Vec<serde_json::Value> = received
+        .iter()
+        .map(|record| {
+            assert!(record.ends_with('\n'), "{:?}", record);
+            assert_eq!(record.matches('\n').count(), 1, "{:?}", record);
+            serde_json::from_str(record).unwrap()
+        })
+        .collect();
+
+    assert_eq!(records[0]["msg"], "started");
+    assert_eq!(records[0]["level"], "INFO");
+    assert_eq!(records[0]["app"], "api");
+    assert_eq!(records[0]["port"], 8080);
+    assert!(records[0]["ts"].is_string());
+
+    assert_eq!(records[1]["msg"], "slow");
+    assert_eq!(records[1]["level"], "WARN");
+    assert_eq!(records[1]["app"], "api");
+    assert_eq!(records[1]["ms"], 900);
+    assert_eq!(records[1]["path"], "/users");
+}
+
+#[test]
+fn received_strings_without_newlines() {
+    let (tx, rx) = mpsc::channel();
+    let config = JsonBuilder::config().set_newlines(false).build_config();
+    let drain = ChannelJson::new(tx, config);
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "a"; "n" => 1);
+    info!(log, "b"; "n" => 2);
+    drop(log);
+
+    let received: Vec<String> = rx.iter().collect();
+    assert_eq!(received, ["{\"n\":1}", "{\"n\":2}"]);
+}
@@ -0,0 +1,79 @@
+//! Round trips of `JsonBuilder::with_compression`
+use slog::{info, o, Drain, Logger};
+use slog_json::{CompressedWriter, Compression, Json};
+use std::sync::{Arc, Mutex};
+
+/// Output of a drain compressing with `codec`, and the lines it compressed
+fn compress(codec: Compression, records: usize) -> (Vec<u8>, Vec<String>) {
+    let drain = Json::new(vec![])
+        .with_compression(codec)
+        .add_key_value(o!("app" => "test"))
+        .set_flush(true)
+        .build();
+    let drain = Arc::new(Mutex::new(drain));
+    let log = Logger::root(drain.clone().fuse(), o!());
+    for i in 0..records {
+        info!(log, "record"; "i" => i, "even" => i % 2 == 0);
+    }
+    drop(log);
+
+    let drain = Arc::try_unwrap(drain).ok().unwrap().into_inner().unwrap();
+    let writer: CompressedWriter<Vec<u8>> = drain.finish().unwrap();
+    let out = writer.finish().unwrap();
+    let lines = (0..records)
+        .map(|i| format!(r#"{{"app":"test","even":{},"i":{}}}"#, i % 2 == 0, i))
+        .collect();
+    (out, lines)
+}
+
+fn assert_lines(decompressed: &[u8], lines: &[String]) {
+    let decompressed = std::str::from_utf8(decompressed).unwrap();
+    assert_eq!(decompressed.lines().collect::<Vec<_>>(), lines);
+}
+
+#[test]
+fn none_writes_as_is() {
+    let (out, lines) = compress(Compression::None, 3);
+    assert_lines(&out, &lines);
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn gzip_round_trip() {
+    // Enough for several blocks
+    let (out, lines) = compress(Compression::Gzip, 5000);
+    use std::io::Read;
+
+    let mut decompressed = vec![];
+    flate2::read::GzDecoder::new(&out[..])
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert_lines(&decompressed, &lines);
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn gzip_empty_round_trip() {
+    let (out, lines) = compress(Compression::Gzip, 0);
+    use std::io::Read;
+
+    let mut decompressed = vec![];
+    flate2::read::GzDecoder::new(&out[..])
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert_lines(&decompressed, &lines);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_round_trip() {
+    let (out, lines) = compress(Compression::Zstd, 5000);
+    use std::io::Read;
+
+    let mut decompressed = vec![];
+    zstd::stream::read::Decoder::new(&out[..])
+        .unwrap()
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert_lines(&decompressed, &lines);
+}
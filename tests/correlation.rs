@@ -0,0 +1,35 @@
+//! `JsonBuilder::ensure_correlation_id`
+#![cfg(feature = "uuid")]
+
+use slog::{info, o, Drain, Logger};
+use slog_json::{Json, SharedBuffer};
+use std::sync::Mutex;
+
+fn output(log: impl FnOnce(&Logger)) -> serde_json::Value {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .ensure_correlation_id("request_id")
+        .build();
+    log(&Logger::root(Mutex::new(drain).fuse(), o!()));
+    serde_json::from_str(&buf.to_string_lossy()).unwrap()
+}
+
+#[test]
+fn absent_id_is_generated() {
+    let line = output(|log| info!(log, "hi"; "user" => "ann"));
+    let id = line["request_id"].as_str().unwrap();
+    let id = uuid::Uuid::parse_str(id).unwrap();
+    assert_eq!(id.get_version_num(), 4);
+    assert_eq!(line["user"], "ann");
+}
+
+#[test]
+fn present_id_is_kept() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .ensure_correlation_id("request_id")
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "hi"; "request_id" => "abc");
+    assert_eq!(buf.to_string_lossy(), "{\"request_id\":\"abc\"}\n");
+}
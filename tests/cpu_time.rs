@@ -0,0 +1,32 @@
+//! `JsonBuilder::add_cpu_time`
+#![cfg(all(
+    feature = "cpu-time",
+    any(target_os = "linux", target_os = "android", target_os = "macos")
+))]
+
+use slog::{info, o, Drain, Logger};
+use slog_json::{Json, SharedBuffer};
+use std::sync::Mutex;
+
+#[test]
+fn cpu_ms_does_not_decrease() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone()).add_cpu_time().build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    for i in 0..20 {
+        // Burn some CPU between records
+        let sum: u64 = (0..200_000u64).map(|n| n ^ i).sum();
+        info!(log, ""; "sum" => sum);
+    }
+
+    let cpu_ms: Vec<u64> = buf
+        .to_string_lossy()
+        .lines()
+        .map(|l| {
+            let v: serde_json::Value = serde_json::from_str(l).unwrap();
+            v["cpu_ms"].as_u64().unwrap()
+        })
+        .collect();
+    assert_eq!(cpu_ms.len(), 20);
+    assert!(cpu_ms.windows(2).all(|w| w[0] <= w[1]), "{:?}", cpu_ms);
+}
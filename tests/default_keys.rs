@@ -0,0 +1,202 @@
+//! `JsonBuilder::add_default_keys` and the order of key-values
+use slog::{crit, error, info, o, warn, Drain, Logger};
+use slog_json::{Json, JsonBuilder, SharedBuffer};
+use std::sync::Mutex;
+
+fn output(
+    build: impl FnOnce(JsonBuilder<SharedBuffer>) -> JsonBuilder<SharedBuffer>,
+) -> String {
+    let buf = SharedBuffer::new();
+    let drain = build(Json::new(buf.clone())).build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!("logger" => 2));
+    info!(log, "hi"; "record" => 3);
+    buf.to_string_lossy()
+}
+
+#[test]
+fn default_keys_come_first() {
+    let out = output(|b| b.add_default_keys().add_key_value(o!("value" => 1)));
+    assert!(
+        out.starts_with("{\"msg\":\"hi\",\"level\":\"INFO\",\"ts\":\""),
+        "{}",
+        out
+    );
+    assert!(
+        out.ends_with("\",\"value\":1,\"logger\":2,\"record\":3}\n"),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn default_keys_follow_values_added_before() {
+    let out = output(|b| {
+        b.add_key_value(o!("early" => 0))
+            .add_default_keys()
+            .add_key_value(o!("value" => 1))
+    });
+    assert!(
+        out.starts_with(
+            "{\"early\":0,\"msg\":\"hi\",\"level\":\"INFO\",\"ts\":\""
+        ),
+        "{}",
+        out
+    );
+    assert!(
+        out.ends_with("\",\"value\":1,\"logger\":2,\"record\":3}\n"),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn tracing_level_names() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .tracing_level_names(true)
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    crit!(log, "a");
+    error!(log, "b");
+    warn!(log, "c");
+    info!(log, "d");
+
+    let levels: Vec<serde_json::Value> = buf
+        .to_string_lossy()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .map(|line| line["level"].clone())
+        .collect();
+    assert_eq!(levels, ["error", "error", "warn", "info"]);
+}
+
+#[test]
+fn trimmed_msg() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .trim_msg(true)
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "  hello \n");
+    info!(log, " two  words\t");
+
+    let msgs: Vec<serde_json::Value> = buf
+        .to_string_lossy()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .map(|line| line["msg"].clone())
+        .collect();
+    assert_eq!(msgs, ["hello", "two  words"]);
+}
+
+#[test]
+fn blank_msg_placeholder() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .trim_msg(true)
+        .blank_msg_placeholder("(blank)")
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, " \n\t ");
+    info!(log, "");
+    info!(log, " hi ");
+
+    let msgs: Vec<serde_json::Value> = buf
+        .to_string_lossy()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .map(|line| line["msg"].clone())
+        .collect();
+    assert_eq!(msgs, ["(blank)", "(blank)", "hi"]);
+}
+
+#[test]
+fn summary_is_truncated_msg() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .add_summary(5)
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "héllo wörld");
+    info!(log, "hi");
+
+    let records: Vec<serde_json::Value> = buf
+        .to_string_lossy()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records[0]["summary"], "héllo");
+    assert_eq!(records[0]["msg"], "héllo wörld");
+    assert_eq!(records[1]["summary"], "hi");
+    assert_eq!(records[1]["msg"], "hi");
+}
+
+#[test]
+fn level_debug_info() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .tracing_level_names(true)
+        .add_level_debug_info(true)
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    crit!(log, "a");
+    warn!(log, "b");
+
+    let records: Vec<serde_json::Value> = buf
+        .to_string_lossy()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records[0]["level"], "error");
+    assert_eq!(records[0]["level_usize"], 1);
+    assert_eq!(records[1]["level"], "warn");
+    assert_eq!(records[1]["level_usize"], 3);
+}
+
+#[test]
+fn reserved_keys_are_protected() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .protect_reserved_keys(true)
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!("level" => "custom"));
+    info!(log, "hi"; "ts" => "yesterday");
+
+    let out = buf.to_string_lossy();
+    let record: serde_json::Value = serde_json::from_str(&out).unwrap();
+    assert_eq!(record["user_ts"], "yesterday");
+    assert_eq!(record["user_level"], "custom");
+    assert_eq!(record["level"], "INFO");
+    assert_ne!(record["ts"], "yesterday");
+    assert_eq!(out.matches("\"ts\"").count(), 1, "{}", out);
+}
+
+#[test]
+fn collapse_msg_newlines() {
+    let output = |enabled| {
+        let buf = SharedBuffer::new();
+        let drain = Json::new(buf.clone())
+            .add_default_keys()
+            .collapse_msg_newlines(enabled)
+            .build();
+        let log = Logger::root(Mutex::new(drain).fuse(), o!());
+        info!(log, "one\ntwo\r\nthree\rfour");
+        buf.to_string_lossy()
+    };
+
+    let out = output(true);
+    assert_eq!(out.lines().count(), 1);
+    let record: serde_json::Value = serde_json::from_str(&out).unwrap();
+    assert_eq!(record["msg"], "one two three four");
+
+    let out = output(false);
+    assert_eq!(out.lines().count(), 1);
+    let record: serde_json::Value = serde_json::from_str(&out).unwrap();
+    assert_eq!(record["msg"], "one\ntwo\r\nthree\rfour");
+}
@@ -0,0 +1,107 @@
+//! Failing records, and `dropped_count`
+use slog::{b, o, record, Drain, Level, OwnedKVList};
+use slog_json::{ChannelJson, DisconnectPolicy, Json, JsonBuilder};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+
+/// Writer failing while `failing` is set
+struct Flaky {
+    failing: Arc<AtomicBool>,
+    out: Vec<u8>,
+}
+
+impl Flaky {
+    fn new(failing: bool) -> (Self, Arc<AtomicBool>) {
+        let failing = Arc::new(AtomicBool::new(failing));
+        let flaky = Flaky {
+            failing: failing.clone(),
+            out: vec![],
+        };
+        (flaky, failing)
+    }
+}
+
+impl io::Write for Flaky {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.failing.load(Ordering::Relaxed) {
+            return Err(io::Error::new(io::ErrorKind::Other, "flaky"));
+        }
+        self.out.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn log<D: Drain>(drain: &D, msg: &str) -> Result<D::Ok, D::Err> {
+    drain.log(
+        &record!(Level::Info, "", &format_args!("{}", msg), b!("n" => 1)),
+        &OwnedKVList::from(o!()),
+    )
+}
+
+#[test]
+fn errors_are_returned_and_not_counted() {
+    let drain = Json::new(Flaky::new(true).0).build();
+    for _ in 0..3 {
+        let err = log(&drain, "lost").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+    }
+    assert_eq!(drain.dropped_count(), 0);
+}
+
+#[test]
+fn discarded_errors_are_counted() {
+    let drain = Json::new(Flaky::new(true).0).discard_errors(true).build();
+    for _ in 0..3 {
+        log(&drain, "lost").unwrap();
+    }
+    assert_eq!(drain.dropped_count(), 3);
+}
+
+#[test]
+fn only_failed_records_are_counted() {
+    let (flaky, failing) = Flaky::new(false);
+    let drain = Json::new(flaky).discard_errors(true).build();
+    for fail in [false, true, true, false].iter() {
+        failing.store(*fail, Ordering::Relaxed);
+        log(&drain, "maybe").unwrap();
+    }
+    assert_eq!(drain.dropped_count(), 2);
+    let out = drain.finish().unwrap().out;
+    assert_eq!(out, b"{\"n\":1}\n{\"n\":1}\n");
+}
+
+#[test]
+fn missing_writer_is_discarded() {
+    let drain = Json::new(vec![])
+        .with_writer_selector(|_, _| Ok(1))
+        .discard_errors(true)
+        .build();
+    log(&drain, "nowhere").unwrap();
+    assert_eq!(drain.dropped_count(), 1);
+}
+
+#[test]
+fn channel_disconnect_policies() {
+    let config = || JsonBuilder::config().build_config();
+
+    let (tx, rx) = mpsc::channel();
+    drop(rx);
+    let drain = ChannelJson::new(tx, config());
+    let err = log(&drain, "gone").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    assert_eq!(drain.dropped_count(), 0);
+
+    let (tx, rx) = mpsc::channel();
+    let drain = ChannelJson::new(tx, config())
+        .set_disconnect_policy(DisconnectPolicy::Discard);
+    log(&drain, "sent").unwrap();
+    drop(rx);
+    log(&drain, "gone").unwrap();
+    log(&drain, "gone").unwrap();
+    assert_eq!(drain.dropped_count(), 2);
+}
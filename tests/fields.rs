@@ -0,0 +1,294 @@
+//! Key-values added by the drain
+use slog::{info, o, Drain, Logger, KV};
+use slog_json::{FlattenStyle, HashAlgo, Json, SharedBuffer};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+fn lines(buf: &SharedBuffer) -> Vec<serde_json::Value> {
+    buf.to_string_lossy()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn record_index_increments_and_resets() {
+    let buf = SharedBuffer::new();
+    let drain = Arc::new(Mutex::new(
+        Json::new(buf.clone()).add_record_index().build(),
+    ));
+    let log = Logger::root(drain.clone().fuse(), o!());
+    info!(log, "a");
+    info!(log, "b");
+    info!(log, "c");
+    drain.lock().unwrap().reset_index();
+    info!(log, "d");
+
+    let indexes: Vec<_> = lines(&buf)
+        .iter()
+        .map(|l| l["record_index"].clone())
+        .collect();
+    assert_eq!(indexes, [0, 1, 2, 0]);
+}
+
+fn flattened(style: FlattenStyle) -> String {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .set_flatten_style(style)
+        .add_flattened_object(serde_json::json!({
+            "service": {"name": "api", "a/b": 1},
+            "tags": ["x"],
+        }))
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "hi");
+    buf.to_string_lossy()
+}
+
+#[test]
+fn flattened_object_styles() {
+    assert_eq!(
+        flattened(FlattenStyle::JsonPointer),
+        "{\"/service/a~1b\":1,\"/service/name\":\"api\",\"/tags/0\":\"x\"}\n"
+    );
+    assert_eq!(
+        flattened(FlattenStyle::Dotted),
+        "{\"service.a/b\":1,\"service.name\":\"api\",\"tags.0\":\"x\"}\n"
+    );
+    assert_eq!(
+        flattened(FlattenStyle::Bracketed),
+        "{\"service[a/b]\":1,\"service[name]\":\"api\",\"tags[0]\":\"x\"}\n"
+    );
+}
+
+#[test]
+fn host_ip_is_present() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone()).add_host_ip().build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "hi");
+
+    let line = &lines(&buf)[0];
+    // Depends on the network of the host: an address, or none at all
+    match &line["host.ip"] {
+        serde_json::Value::String(ip) => {
+            let ip: std::net::IpAddr = ip.parse().unwrap();
+            assert!(!ip.is_loopback() && !ip.is_unspecified());
+        }
+        ip => assert!(ip.is_null() && line.get("host.ip").is_some()),
+    }
+}
+
+#[test]
+fn host_info_is_present() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone()).add_host_info().build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "hi");
+
+    let line = &lines(&buf)[0];
+    assert_eq!(line["host.os"], std::env::consts::OS);
+    let name = &line["host.name"];
+    assert!(name
+        .as_str()
+        .map_or(name.is_null(), |name| !name.is_empty()));
+}
+
+#[test]
+fn process_start_time_is_constant() {
+    let before = time::OffsetDateTime::now_utc();
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone()).add_process_start_time().build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "a");
+    std::thread::sleep(Duration::from_millis(2));
+    info!(log, "b");
+
+    let records = lines(&buf);
+    let start = records[0]["process.start_time"].as_str().unwrap();
+    let start = time::OffsetDateTime::parse(
+        start,
+        &time::format_description::well_known::Rfc3339,
+    )
+    .unwrap();
+    assert!(before <= start && start <= time::OffsetDateTime::now_utc());
+    assert_eq!(
+        records[0]["process.start_time"],
+        records[1]["process.start_time"]
+    );
+}
+
+#[test]
+fn record_bytes_is_line_length_without_itself() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .add_record_size(true)
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!("app" => "x"));
+    info!(log, "hello"; "n" => 1, "tags" => "a,b");
+    info!(log, "");
+
+    for line in buf.to_string_lossy().lines() {
+        let record: serde_json::Value = serde_json::from_str(line).unwrap();
+        let size = record["record_bytes"].as_u64().unwrap() as usize;
+        let own = format!(",\"record_bytes\":{}", size).len();
+        assert_eq!(size + own, line.len(), "{}", line);
+    }
+}
+
+#[test]
+fn record_bytes_of_pretty_records() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .set_pretty(true)
+        .add_record_size(true)
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "hello"; "n" => 1);
+
+    let out = buf.to_string_lossy();
+    let record: serde_json::Value = serde_json::from_str(&out).unwrap();
+    let size = record["record_bytes"].as_u64().unwrap() as usize;
+    let own = format!(",\n  \"record_bytes\": {}", size).len();
+    assert_eq!(size + own, out.trim_end_matches('\n').len(), "{}", out);
+}
+
+#[test]
+fn content_hash_id_ignores_timestamps() {
+    let now = Arc::new(Mutex::new(UNIX_EPOCH + Duration::from_secs(1000)));
+    let clock = now.clone();
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .add_record_index()
+        .content_hash_id(HashAlgo::Fnv1a64)
+        .with_clock(move || *clock.lock().unwrap())
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!("app" => "x"));
+    info!(log, "hello"; "n" => 1);
+    *now.lock().unwrap() += Duration::from_millis(1500);
+    info!(log, "hello"; "n" => 1);
+    info!(log, "hello"; "n" => 2);
+
+    let records = lines(&buf);
+    assert_ne!(records[0]["ts"], records[1]["ts"]);
+    let id = records[0]["event_id"].as_str().unwrap();
+    assert_eq!(id.len(), 16);
+    assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+    assert_eq!(records[1]["event_id"], id);
+    assert_ne!(records[2]["event_id"], id);
+}
+
+#[test]
+fn line_as_string() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_source_location()
+        .line_as_string(true)
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    let line = line!() + 1;
+    info!(log, "hi");
+
+    let record = &lines(&buf)[0];
+    assert_eq!(record["line"], line.to_string());
+    assert_eq!(record["file"], file!());
+}
+
+#[test]
+fn fields_count_leaves_out_default_keys() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .add_key_value(o!("env" => "prod"))
+        .add_fields_count(true)
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!("app" => "x"));
+    info!(log, "hi"; "user" => "ann", "n" => 1);
+    info!(log, "hi");
+
+    let records = lines(&buf);
+    assert_eq!(records[0]["fields_count"], 3);
+    assert_eq!(records[1]["fields_count"], 1);
+}
+
+#[test]
+fn serialize_ns_is_positive() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .add_serialize_timing(true)
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "hi"; "n" => 1);
+
+    let ns = lines(&buf)[0]["serialize_ns"].as_u64().unwrap();
+    assert!(ns > 0);
+}
+
+#[test]
+fn null_placeholder_is_present() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_null_placeholder("_ingest_ts")
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "hi"; "n" => 1);
+    assert_eq!(buf.to_string_lossy(), "{\"_ingest_ts\":null,\"n\":1}\n");
+}
+
+#[test]
+fn static_kind() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .with_kind("audit")
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "a");
+    info!(log, "b"; "user" => "ann");
+
+    let records = lines(&buf);
+    assert_eq!(records[0]["kind"], "audit");
+    assert_eq!(records[1]["kind"], "audit");
+}
+
+/// Reads the value of an `event` key-value of a record
+#[derive(Default)]
+struct EventKind(Option<String>);
+
+impl slog::Serializer for EventKind {
+    fn emit_arguments(
+        &mut self,
+        key: slog::Key,
+        val: &std::fmt::Arguments,
+    ) -> slog::Result {
+        if key == "event" {
+            self.0 = Some(val.to_string());
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn kind_from_a_record_key_value() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .with_kind("log")
+        .with_dynamic_kind(|record| {
+            let mut kind = EventKind::default();
+            record.kv().serialize(record, &mut kind).ok()?;
+            kind.0
+        })
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "a"; "event" => "login", "user" => "ann");
+    info!(log, "b"; "user" => "ann");
+
+    let out = buf.to_string_lossy();
+    let records = lines(&buf);
+    assert_eq!(records[0]["kind"], "login");
+    assert_eq!(records[0]["event"], "login");
+    assert!(records[1].get("kind").is_none(), "{}", out);
+}
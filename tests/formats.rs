@@ -0,0 +1,70 @@
+//! Options applying to some output formats only
+use slog::{info, o, Drain, Logger};
+use slog_json::{HashAlgo, Json, JsonBuilder, SharedBuffer};
+use std::sync::Mutex;
+
+/// Builder adding the keys describing the JSON output
+fn tracking(buf: &SharedBuffer) -> JsonBuilder<SharedBuffer> {
+    Json::new(buf.clone())
+        .add_record_size(true)
+        .add_serialize_timing(true)
+        .content_hash_id(HashAlgo::Fnv1a64)
+}
+
+fn log_to<D>(drain: D)
+where
+    D: Drain<Ok = (), Err = std::io::Error> + Send + 'static,
+{
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "hi"; "n" => 1);
+}
+
+#[test]
+fn json_has_tracking_keys() {
+    let buf = SharedBuffer::new();
+    log_to(tracking(&buf).build());
+    let line: serde_json::Value =
+        serde_json::from_str(&buf.to_string_lossy()).unwrap();
+    assert!(line["record_bytes"].is_u64());
+    assert!(line["serialize_ns"].is_u64());
+    assert!(line["event_id"].is_string());
+}
+
+#[test]
+fn logfmt_has_no_tracking_keys() {
+    let buf = SharedBuffer::new();
+    log_to(tracking(&buf).build_logfmt());
+    assert_eq!(buf.to_string_lossy(), "n=1\n");
+}
+
+#[test]
+fn canonical_has_no_tracking_keys() {
+    let buf = SharedBuffer::new();
+    log_to(tracking(&buf).canonical(true).build());
+    assert_eq!(buf.to_string_lossy(), "{\"n\":1}\n");
+}
+
+#[test]
+fn json_only_options_do_not_apply_to_logfmt() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .set_pretty(true)
+        .escape_forward_slashes(true)
+        .json5_unquoted_keys(true)
+        .build_logfmt();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "hi"; "path" => "a/b");
+    assert_eq!(buf.to_string_lossy(), "path=\"a/b\"\n");
+}
+
+#[test]
+fn canonical_can_be_turned_off() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .canonical(true)
+        .canonical(false)
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!("b" => 1));
+    info!(log, "hi"; "a" => 2);
+    assert_eq!(buf.to_string_lossy(), "{\"b\":1,\"a\":2}\n");
+}
@@ -0,0 +1,181 @@
+//! Transformations of keys
+use slog::{info, o, Drain, Logger};
+use slog_json::{
+    DuplicateKeyPolicy, Json, JsonBuilder, LongKeyPolicy, SharedBuffer,
+};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+fn output(
+    build: impl FnOnce(JsonBuilder<SharedBuffer>) -> JsonBuilder<SharedBuffer>,
+    log: impl FnOnce(&Logger),
+) -> String {
+    let buf = SharedBuffer::new();
+    let drain = build(Json::new(buf.clone())).build();
+    log(&Logger::root(Mutex::new(drain).fuse(), o!()));
+    buf.to_string_lossy()
+}
+
+#[test]
+fn lowercase_keys() {
+    let out = output(
+        |b| b.lowercase_keys(true),
+        |log| info!(log, "hi"; "UserID" => 1),
+    );
+    assert_eq!(out, "{\"userid\":1}\n");
+}
+
+#[test]
+fn lowercased_collisions_are_suffixed() {
+    let out = output(
+        |b| {
+            b.lowercase_keys(true)
+                .duplicate_key_policy(DuplicateKeyPolicy::Suffix)
+        },
+        |log| info!(log, "hi"; "userid" => 2, "UserID" => 1),
+    );
+    assert_eq!(out, "{\"userid\":1,\"userid_2\":2}\n");
+}
+
+#[test]
+fn stable_output_pins_defaults_and_sorts_the_rest() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .stable_output(true)
+        .with_clock(|| UNIX_EPOCH)
+        .build();
+    let log = Logger::root(
+        Mutex::new(drain).fuse(),
+        o!("service" => "api", "host" => "h1"),
+    );
+    info!(log, "hi"; "zone" => "eu", "attempt" => 2, "Upper" => true);
+    assert_eq!(
+        buf.to_string_lossy(),
+        "{\"ts\":\"1970-01-01T00:00:00Z\",\"level\":\"INFO\",\"msg\":\"hi\",\
+         \"Upper\":true,\"attempt\":2,\"host\":\"h1\",\"service\":\"api\",\
+         \"zone\":\"eu\"}\n"
+    );
+}
+
+#[test]
+fn dotted_keys_are_unflattened() {
+    let out = output(
+        |b| b.unflatten_dotted_keys(true),
+        |log| info!(log, "hi"; "http.status" => 200, "http.method" => "GET", "user" => "ann"),
+    );
+    assert_eq!(
+        out,
+        "{\"user\":\"ann\",\"http\":{\"method\":\"GET\",\"status\":200}}\n"
+    );
+}
+
+#[test]
+fn unflattened_prefix_conflicts_are_kept() {
+    let out = output(
+        |b| b.unflatten_dotted_keys(true),
+        |log| info!(log, "hi"; "http.status" => 200, "http" => "1.1"),
+    );
+    assert_eq!(out, "{\"http\":\"1.1\",\"http.status\":200}\n");
+}
+
+#[test]
+fn identical_duplicates_are_collapsed() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .collapse_identical_duplicates(true)
+        .duplicate_key_policy(DuplicateKeyPolicy::Suffix)
+        .build();
+    let log = Logger::root(
+        Mutex::new(drain).fuse(),
+        o!("user" => "ann", "region" => "eu"),
+    );
+    info!(log, "hi"; "user" => "ann", "region" => "us");
+    assert_eq!(
+        buf.to_string_lossy(),
+        "{\"region\":\"eu\",\"user\":\"ann\",\"region_2\":\"us\"}\n"
+    );
+}
+
+#[test]
+fn json5_identifier_keys_are_unquoted() {
+    let out = output(
+        |b| b.add_default_keys().json5_unquoted_keys(true),
+        |log| info!(log, "hi"; "http.status" => 200, "user_id" => 7, "$ref" => "x"),
+    );
+    assert!(
+        out.starts_with("{msg:\"hi\",level:\"INFO\",ts:\""),
+        "{}",
+        out
+    );
+    assert!(
+        out.ends_with(",$ref:\"x\",user_id:7,\"http.status\":200}\n"),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn long_keys_are_truncated_apart() {
+    let out = output(
+        |b| b.max_key_length(22),
+        |log| {
+            info!(log, "hi";
+                "request_header_user_agent" => 1,
+                "request_header_user_id" => 2,
+                "request_header_referrer" => 3,
+                "short" => 4)
+        },
+    );
+    let line: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(&out).unwrap();
+    assert_eq!(line.len(), 4);
+    assert_eq!(line["short"], 4);
+    assert_eq!(line["request_header_user_id"], 2);
+    let truncated: Vec<&String> =
+        line.keys().filter(|k| k.contains('~')).collect();
+    assert_eq!(truncated.len(), 2);
+    for key in truncated {
+        assert_eq!(key.len(), 22);
+        assert!(key.starts_with("request_heade~"), "{}", key);
+    }
+}
+
+#[test]
+fn tripled_key_is_suffixed() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .duplicate_key_policy(DuplicateKeyPolicy::Suffix)
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!("key" => 1));
+    info!(log, "hi"; "key" => 3, "key" => 2);
+    assert_eq!(
+        buf.to_string_lossy(),
+        "{\"key\":1,\"key_2\":2,\"key_3\":3}\n"
+    );
+}
+
+#[test]
+fn long_keys_are_replaced_by_a_stable_hash() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .max_key_length(16)
+        .long_key_policy(LongKeyPolicy::HashReplace)
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "a"; "request_header_user_agent" => 1, "short" => 2);
+    info!(log, "b"; "request_header_user_agent" => 3);
+
+    let records: Vec<serde_json::Map<String, serde_json::Value>> = buf
+        .to_string_lossy()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let key = records[0].keys().find(|k| *k != "short").unwrap();
+    assert_eq!(key.len(), 10);
+    assert!(key.starts_with("k_"), "{}", key);
+    assert!(key[2..].chars().all(|c| c.is_ascii_hexdigit()), "{}", key);
+    assert_eq!(records[0][key], 1);
+    assert_eq!(records[1][key], 3);
+    assert_eq!(records[0]["short"], 2);
+}
@@ -0,0 +1,127 @@
+//! `JsonBuilder::layout`
+use slog::{info, o, Drain, Logger};
+use slog_json::{Json, Layout, SharedBuffer};
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// The record logged with `layout`, at 2023-11-14T22:13:20.5Z
+fn record(layout: Layout) -> serde_json::Map<String, serde_json::Value> {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .layout(layout)
+        .with_clock(|| UNIX_EPOCH + Duration::from_millis(1_700_000_000_500))
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!("app" => "api"));
+    info!(log, "hello"; "user" => "ann");
+    let out = buf.to_string_lossy();
+    assert_eq!(out.lines().count(), 1, "{}", out);
+    serde_json::from_str(&out).unwrap()
+}
+
+fn keys(record: &serde_json::Map<String, serde_json::Value>) -> Vec<&str> {
+    let mut keys: Vec<&str> = record.keys().map(|k| &**k).collect();
+    keys.sort_unstable();
+    keys
+}
+
+#[test]
+fn flat() {
+    let record = record(Layout::Flat);
+    assert_eq!(keys(&record), ["app", "level", "msg", "ts", "user"]);
+    assert_eq!(record["msg"], "hello");
+    assert_eq!(record["level"], "INFO");
+    assert_eq!(record["ts"], "2023-11-14T22:13:20.5Z");
+}
+
+#[test]
+fn envelope() {
+    let record = record(Layout::Envelope);
+    assert_eq!(keys(&record), ["fields", "level", "msg", "ts"]);
+    assert_eq!(record["msg"], "hello");
+    assert_eq!(record["level"], "INFO");
+    assert_eq!(
+        record["fields"],
+        serde_json::json!({"app": "api", "user": "ann"})
+    );
+}
+
+#[test]
+fn ecs() {
+    let record = record(Layout::Ecs);
+    assert_eq!(
+        keys(&record),
+        [
+            "@timestamp",
+            "app",
+            "ecs.version",
+            "log.level",
+            "message",
+            "user"
+        ]
+    );
+    assert_eq!(record["message"], "hello");
+    assert_eq!(record["log.level"], "info");
+    assert_eq!(record["@timestamp"], "2023-11-14T22:13:20.5Z");
+    assert!(record["ecs.version"].is_string());
+}
+
+#[test]
+fn gelf() {
+    let record = record(Layout::Gelf);
+    assert_eq!(
+        keys(&record),
+        [
+            "_app",
+            "_user",
+            "host",
+            "level",
+            "short_message",
+            "timestamp",
+            "version"
+        ]
+    );
+    assert_eq!(record["version"], "1.1");
+    assert_eq!(record["short_message"], "hello");
+    assert_eq!(record["level"], 6);
+    assert_eq!(record["timestamp"], 1_700_000_000.5);
+    assert_eq!(record["_user"], "ann");
+}
+
+#[test]
+fn gelf_reserved_id_is_renamed() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone()).layout(Layout::Gelf).build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "a"; "id" => 1);
+    info!(log, "b"; "_id" => 2, "_kept" => 3);
+
+    let records: Vec<serde_json::Value> = buf
+        .to_string_lossy()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    for record in &records {
+        assert!(record.get("_id").is_none(), "{}", record);
+    }
+    assert_eq!(records[0]["__id"], 1);
+    assert_eq!(records[1]["__id"], 2);
+    assert_eq!(records[1]["_kept"], 3);
+}
+
+#[test]
+fn bunyan() {
+    let record = record(Layout::Bunyan);
+    assert_eq!(
+        keys(&record),
+        [
+            "app", "hostname", "level", "msg", "name", "pid", "time", "user",
+            "v"
+        ]
+    );
+    assert_eq!(record["v"], 0);
+    assert_eq!(record["level"], 30);
+    assert_eq!(record["msg"], "hello");
+    assert_eq!(record["time"], "2023-11-14T22:13:20.5Z");
+    assert_eq!(record["pid"], std::process::id());
+    assert!(record["hostname"].as_str().map_or(false, |h| !h.is_empty()));
+}
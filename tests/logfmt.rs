@@ -0,0 +1,49 @@
+//! `JsonBuilder::build_logfmt`
+use slog::{info, o, Drain, Logger};
+use slog_json::{Json, SharedBuffer};
+use std::sync::Mutex;
+
+fn output(log: impl FnOnce(&Logger)) -> String {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone()).build_logfmt();
+    log(&Logger::root(Mutex::new(drain).fuse(), o!("app" => "x")));
+    buf.to_string_lossy()
+}
+
+#[test]
+fn values_are_json() {
+    let out = output(
+        |log| info!(log, "hi"; "n" => 1, "s" => "a b", "ok" => true, "none" => None::<u8>),
+    );
+    assert_eq!(out, "app=\"x\"\tnone=null\tok=true\ts=\"a b\"\tn=1\n");
+}
+
+#[test]
+fn plain_keys_are_bare() {
+    let out = output(|log| info!(log, "hi"; "user.id" => 7, "k-2" => 8));
+    assert_eq!(out, "app=\"x\"\tk-2=8\tuser.id=7\n");
+}
+
+#[test]
+fn keys_with_spaces_are_quoted() {
+    let out = output(|log| info!(log, "hi"; "user name" => "ann"));
+    assert_eq!(out, "app=\"x\"\t\"user name\"=\"ann\"\n");
+}
+
+#[test]
+fn keys_with_equals_are_quoted() {
+    let out = output(|log| info!(log, "hi"; "a=b" => 1));
+    assert_eq!(out, "app=\"x\"\t\"a=b\"=1\n");
+}
+
+#[test]
+fn keys_with_quotes_are_escaped() {
+    let out = output(|log| info!(log, "hi"; "say \"hi\"" => 1));
+    assert_eq!(out, "app=\"x\"\t\"say \\\"hi\\\"\"=1\n");
+}
+
+#[test]
+fn keys_with_tabs_are_escaped() {
+    let out = output(|log| info!(log, "hi"; "a\tb" => 1));
+    assert_eq!(out, "app=\"x\"\t\"a\\tb\"=1\n");
+}
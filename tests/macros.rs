@@ -0,0 +1,33 @@
+//! Key-values set at compile time
+use slog::{info, o, Drain, Logger, OwnedKV, SendSyncRefUnwindSafeKV};
+use slog_json::{Json, SharedBuffer};
+use std::sync::Mutex;
+
+fn output<T>(kv: OwnedKV<T>) -> String
+where
+    T: SendSyncRefUnwindSafeKV + 'static,
+{
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone()).add_key_value(kv).build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "hi"; "n" => 1);
+    buf.to_string_lossy()
+}
+
+#[test]
+fn git_sha_of_a_set_variable() {
+    let out = output(slog_json::git_sha_kv!("CARGO_PKG_NAME"));
+    assert_eq!(
+        out,
+        format!(
+            "{{\"build.git_sha\":\"{}\",\"n\":1}}\n",
+            env!("CARGO_PKG_NAME")
+        )
+    );
+}
+
+#[test]
+fn git_sha_of_an_unset_variable_is_omitted() {
+    let out = output(slog_json::git_sha_kv!("SLOG_JSON_UNSET_GIT_SHA"));
+    assert_eq!(out, "{\"n\":1}\n");
+}
@@ -0,0 +1,53 @@
+//! `JsonBuilder::include_mdc`
+use slog::{info, o, Drain, Logger};
+use slog_json::{mdc, DuplicateKeyPolicy, Json, JsonBuilder, SharedBuffer};
+use std::sync::Mutex;
+
+fn logger(
+    build: impl FnOnce(JsonBuilder<SharedBuffer>) -> JsonBuilder<SharedBuffer>,
+) -> (Logger, SharedBuffer) {
+    let buf = SharedBuffer::new();
+    let drain = build(Json::new(buf.clone()).include_mdc(true)).build();
+    (Logger::root(Mutex::new(drain).fuse(), o!()), buf)
+}
+
+#[test]
+fn entries_are_included_until_removed() {
+    let (log, buf) = logger(|b| b);
+    mdc::put("request_id", "d7f1");
+    info!(log, "in"; "n" => 1);
+    assert_eq!(mdc::remove("request_id"), Some("d7f1".into()));
+    info!(log, "out"; "n" => 2);
+    assert_eq!(
+        buf.to_string_lossy(),
+        "{\"request_id\":\"d7f1\",\"n\":1}\n{\"n\":2}\n"
+    );
+}
+
+#[test]
+fn colliding_keys_are_emitted_twice() {
+    let (log, buf) = logger(|b| b);
+    mdc::put("user", "a");
+    info!(log, ""; "user" => "b");
+    mdc::clear();
+    assert_eq!(buf.to_string_lossy(), "{\"user\":\"a\",\"user\":\"b\"}\n");
+}
+
+#[test]
+fn colliding_keys_are_suffixed() {
+    let (log, buf) =
+        logger(|b| b.duplicate_key_policy(DuplicateKeyPolicy::Suffix));
+    mdc::put("user", "a");
+    info!(log, ""; "user" => "b");
+    mdc::clear();
+    assert_eq!(buf.to_string_lossy(), "{\"user\":\"a\",\"user_2\":\"b\"}\n");
+}
+
+#[test]
+fn entries_are_per_thread() {
+    let (log, buf) = logger(|b| b);
+    mdc::put("thread", "main");
+    std::thread::spawn(move || info!(log, "")).join().unwrap();
+    mdc::clear();
+    assert_eq!(buf.to_string_lossy(), "{}\n");
+}
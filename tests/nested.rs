@@ -0,0 +1,27 @@
+//! Nested values, logged with `slog::Serde`
+#![cfg(feature = "nested-values")]
+
+use slog::{info, o, Drain, Logger};
+use slog_json::{Json, SharedBuffer};
+use std::sync::Mutex;
+
+#[test]
+fn long_arrays_are_cut() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone()).max_array_len(10).build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    let ids: Vec<u32> = (0..100).collect();
+    let short = vec![1, 2, 3];
+    info!(log, "hi"; "ids" => slog::Serde(ids), "short" => slog::Serde(short));
+
+    let line: serde_json::Value =
+        serde_json::from_str(&buf.to_string_lossy()).unwrap();
+    let ids = line["ids"].as_array().unwrap();
+    assert_eq!(ids.len(), 11);
+    assert_eq!(
+        ids[..10],
+        (0..10).map(serde_json::Value::from).collect::<Vec<_>>()[..]
+    );
+    assert_eq!(ids[10], "<90 more>");
+    assert_eq!(line["short"], serde_json::json!([1, 2, 3]));
+}
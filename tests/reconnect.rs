@@ -0,0 +1,102 @@
+//! `ReconnectingWriter` against a collector dropping the connection
+use slog::{info, o, Drain, Logger};
+use slog_json::{Json, ReconnectingWriter};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn records_resume_after_peer_drops_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let resumed = Arc::new(AtomicBool::new(false));
+    let server = {
+        let resumed = resumed.clone();
+        thread::spawn(move || {
+            let (first, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(first).read_line(&mut line).unwrap();
+            // `first` is dropped here, closing the connection
+
+            let (second, _) = listener.accept().unwrap();
+            let mut lines = vec![];
+            for l in BufReader::new(second).lines() {
+                lines.push(l.unwrap());
+                resumed.store(true, Ordering::SeqCst);
+            }
+            (line, lines)
+        })
+    };
+
+    let writer = ReconnectingWriter::tcp(addr)
+        .set_backoff(Duration::from_millis(0), Duration::from_millis(0));
+    let drain = Json::new(writer).set_flush(true).build();
+    let log = Logger::root(Mutex::new(drain).ignore_res(), o!());
+
+    info!(log, ""; "n" => "first");
+    let mut sent = 0;
+    while !resumed.load(Ordering::SeqCst) {
+        assert!(sent < 1000, "never reconnected");
+        info!(log, ""; "n" => "retry");
+        sent += 1;
+        thread::sleep(Duration::from_millis(10));
+    }
+    info!(log, ""; "n" => "last");
+    drop(log);
+
+    let (first, lines) = server.join().unwrap();
+    assert_eq!(first, "{\"n\":\"first\"}\n");
+    assert!(lines
+        .iter()
+        .all(|l| l == "{\"n\":\"retry\"}" || l == "{\"n\":\"last\"}"));
+    assert_eq!(lines.last().unwrap(), "{\"n\":\"last\"}");
+}
+
+/// Stream appending to `out`, failing while `failing` is set
+struct Shared {
+    failing: Arc<AtomicBool>,
+    out: Arc<Mutex<Vec<u8>>>,
+}
+
+impl io::Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.failing.load(Ordering::SeqCst) {
+            return Err(io::Error::new(io::ErrorKind::Other, "down"));
+        }
+        self.out.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn failed_write_takes_no_bytes() {
+    let failing = Arc::new(AtomicBool::new(true));
+    let out = Arc::new(Mutex::new(vec![]));
+    let mut writer = {
+        let (failing, out) = (failing.clone(), out.clone());
+        ReconnectingWriter::new(move || {
+            Ok(Shared {
+                failing: failing.clone(),
+                out: out.clone(),
+            })
+        })
+        .set_backoff(Duration::from_millis(0), Duration::from_millis(0))
+    };
+
+    let chunk = vec![b'a'; 5000];
+    assert_eq!(writer.write(&chunk).unwrap(), chunk.len());
+    // Sending the first chunk fails: it is dropped, the second not taken
+    assert!(writer.write(&[b'b'; 5000]).is_err());
+
+    failing.store(false, Ordering::SeqCst);
+    writer.write_all(&[b'c'; 5000]).unwrap();
+    writer.flush().unwrap();
+    assert_eq!(*out.lock().unwrap(), vec![b'c'; 5000]);
+}
@@ -0,0 +1,84 @@
+//! `JsonBuilder::with_schema_validator`
+#![cfg(feature = "jsonschema")]
+
+use serde_json::json;
+use slog::{b, o, record, Drain, Level, OwnedKVList};
+use slog_json::{Json, ValidationPolicy};
+use std::io;
+
+/// Output of a drain requiring a `user`, after logging a record with one
+/// and then one without, along with the result of logging the latter
+fn validate(policy: ValidationPolicy) -> (String, io::Result<()>, u64) {
+    let schema = json!({
+        "type": "object",
+        "required": ["user"],
+        "properties": {"user": {"type": "string"}},
+    });
+    let drain = Json::new(vec![])
+        .add_key_value(o!("app" => "test"))
+        .add_record_index()
+        .with_schema_validator(schema, policy)
+        .unwrap()
+        .build();
+    let values = OwnedKVList::from(o!());
+    drain
+        .log(
+            &record!(Level::Info, "", &format_args!("in"), b!("user" => "a")),
+            &values,
+        )
+        .unwrap();
+    let res = drain.log(
+        &record!(Level::Info, "", &format_args!("out"), b!("n" => 1)),
+        &values,
+    );
+
+    let dropped = drain.dropped_count();
+    let out = String::from_utf8(drain.finish().unwrap()).unwrap();
+    (out, res, dropped)
+}
+
+const VALID: &str = r#"{"record_index":0,"app":"test","user":"a"}"#;
+
+#[test]
+fn drop_skips_record() {
+    let (out, res, dropped) = validate(ValidationPolicy::Drop);
+    res.unwrap();
+    assert_eq!(out, format!("{}\n", VALID));
+    assert_eq!(dropped, 0);
+}
+
+#[test]
+fn error_fails_record() {
+    let (out, res, _) = validate(ValidationPolicy::Error);
+    let err = res.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("\"user\" is a required property"));
+    assert_eq!(out, format!("{}\n", VALID));
+}
+
+#[test]
+fn annotate_adds_errors() {
+    let (out, res, _) = validate(ValidationPolicy::Annotate);
+    res.unwrap();
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(lines[0], VALID);
+    let annotated: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+    assert_eq!(
+        annotated,
+        json!({
+            "app": "test",
+            "record_index": 1,
+            "n": 1,
+            "_schema_errors": ["\"user\" is a required property"],
+        })
+    );
+}
+
+#[test]
+fn invalid_schema_fails() {
+    let res = Json::new(vec![]).with_schema_validator(
+        json!({"type": "no such type"}),
+        ValidationPolicy::Drop,
+    );
+    assert_eq!(res.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+}
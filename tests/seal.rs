@@ -0,0 +1,52 @@
+//! `JsonBuilder::with_sealed_trailer`
+use slog::{info, o, warn, Drain, Logger};
+use slog_json::{HashAlgo, Json};
+use std::sync::{Arc, Mutex};
+
+/// FNV-1a, 64 bits
+fn fnv1a64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[test]
+fn trailer_hashes_records_as_written() {
+    let drain = Json::new(vec![])
+        .add_default_keys()
+        .with_sealed_trailer(HashAlgo::Fnv1a64)
+        .build();
+    let drain = Arc::new(Mutex::new(drain));
+    let log = Logger::root(drain.clone().fuse(), o!("app" => "x"));
+    info!(log, "one"; "n" => 1);
+    warn!(log, "two"; "path" => "/tmp/ü");
+    info!(log, "three");
+    drop(log);
+
+    let drain = Arc::try_unwrap(drain).ok().unwrap().into_inner().unwrap();
+    let out = String::from_utf8(drain.finish().unwrap()).unwrap();
+    let trailer_at = out.trim_end().rfind('\n').unwrap() + 1;
+    let (records, trailer) = out.split_at(trailer_at);
+    assert_eq!(records.lines().count(), 3);
+
+    let trailer: serde_json::Value = serde_json::from_str(trailer).unwrap();
+    let hash = format!("{:016x}", fnv1a64(records.as_bytes()));
+    assert_eq!(
+        trailer,
+        serde_json::json!({"_trailer": {"count": 3, "hash": hash}})
+    );
+}
+
+#[test]
+fn empty_output_is_sealed() {
+    let drain = Json::new(vec![])
+        .with_sealed_trailer(HashAlgo::Fnv1a64)
+        .build();
+    let out = drain.finish().unwrap();
+    let trailer: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    let hash = format!("{:016x}", fnv1a64(b""));
+    assert_eq!(
+        trailer,
+        serde_json::json!({"_trailer": {"count": 0, "hash": hash}})
+    );
+}
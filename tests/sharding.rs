@@ -0,0 +1,46 @@
+//! `ShardedJson`
+use slog::{info, o, Drain, Logger};
+use slog_json::{JsonBuilder, ShardedJson, SharedBuffer};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+#[test]
+fn records_are_sharded_by_key() {
+    let shards: Vec<SharedBuffer> =
+        (0..4).map(|_| SharedBuffer::new()).collect();
+    let config = JsonBuilder::config().build_config();
+    let drain = ShardedJson::new(shards.clone(), "tenant", config);
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    for round in 0..3 {
+        for tenant in 0..20 {
+            info!(log, "hi"; "tenant" => format!("t{}", tenant), "round" => round);
+        }
+        info!(log, "hi"; "round" => round);
+    }
+
+    let mut tenant_shards: HashMap<String, HashSet<usize>> = HashMap::new();
+    let mut keyless = vec![];
+    for (i, shard) in shards.iter().enumerate() {
+        for line in shard.to_string_lossy().lines() {
+            let record: serde_json::Value = serde_json::from_str(line).unwrap();
+            match record["tenant"].as_str() {
+                Some(tenant) => {
+                    tenant_shards
+                        .entry(tenant.to_owned())
+                        .or_default()
+                        .insert(i);
+                }
+                None => keyless.push(i),
+            }
+        }
+    }
+
+    assert_eq!(tenant_shards.len(), 20);
+    for (tenant, shards) in &tenant_shards {
+        assert_eq!(shards.len(), 1, "{} is in shards {:?}", tenant, shards);
+    }
+    let used: HashSet<usize> =
+        tenant_shards.values().flatten().copied().collect();
+    assert!(used.len() > 1, "all tenants in shards {:?}", used);
+    assert_eq!(keyless, [0, 0, 0]);
+}
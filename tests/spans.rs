@@ -0,0 +1,70 @@
+//! `JsonBuilder::set_spans`
+use slog::{info, o, Drain, Logger};
+use slog_json::{Json, SharedBuffer};
+use std::sync::Mutex;
+
+fn root() -> (Logger, SharedBuffer) {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone()).set_spans(true).build();
+    (
+        Logger::root(Mutex::new(drain).fuse(), o!("app" => "x")),
+        buf,
+    )
+}
+
+fn record(buf: &SharedBuffer) -> serde_json::Value {
+    serde_json::from_str(&buf.to_string_lossy()).unwrap()
+}
+
+#[test]
+fn nested_scopes_outermost_first() {
+    let (log, buf) = root();
+    let req = log.new(o!("span" => "request", "path" => "/"));
+    let db = req.new(o!("span" => "query", "table" => "users"));
+    info!(db, "hi"; "n" => 1);
+    assert_eq!(
+        record(&buf),
+        serde_json::json!({
+            "app": "x",
+            "spans": [
+                {"span": "request", "path": "/"},
+                {"span": "query", "table": "users"},
+            ],
+            "n": 1,
+        })
+    );
+}
+
+#[test]
+fn values_without_marker_join_enclosing_span() {
+    let (log, buf) = root();
+    let req = log.new(o!("span" => "request"));
+    let user = req.new(o!("user" => "ann"));
+    info!(user, "hi");
+    assert_eq!(
+        record(&buf),
+        serde_json::json!({
+            "app": "x",
+            "spans": [{"span": "request", "user": "ann"}],
+        })
+    );
+}
+
+#[test]
+fn keys_ahead_of_marker_join_enclosing_span() {
+    let (log, buf) = root();
+    let req = log.new(o!("span" => "request", "path" => "/"));
+    // Not first: `table` belongs to `request`, not to `query`
+    let db = req.new(o!("table" => "users", "span" => "query"));
+    info!(db, "hi");
+    assert_eq!(
+        record(&buf),
+        serde_json::json!({
+            "app": "x",
+            "spans": [
+                {"span": "request", "path": "/", "table": "users"},
+                {"span": "query"},
+            ],
+        })
+    );
+}
@@ -0,0 +1,169 @@
+//! Rate limiting and level summaries
+use slog::{error, info, o, warn, Drain, Logger};
+use slog_json::{Json, SharedBuffer};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Clock standing still until advanced
+#[derive(Clone)]
+struct TestClock(Arc<Mutex<SystemTime>>);
+
+impl TestClock {
+    fn new() -> Self {
+        TestClock(Arc::new(Mutex::new(
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        )))
+    }
+
+    fn now(&self) -> SystemTime {
+        *self.0.lock().unwrap()
+    }
+
+    fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+fn lines(buf: &SharedBuffer) -> Vec<serde_json::Value> {
+    buf.to_string_lossy()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn level_summary_counts_records_of_interval() {
+    let clock = TestClock::new();
+    let buf = SharedBuffer::new();
+    let now = clock.clone();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .with_clock(move || now.now())
+        .with_level_summary(Duration::from_secs(10))
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+
+    info!(log, "one");
+    info!(log, "two");
+    clock.advance(Duration::from_secs(9));
+    warn!(log, "three");
+    clock.advance(Duration::from_secs(1));
+    error!(log, "four");
+    clock.advance(Duration::from_secs(10));
+    info!(log, "five");
+
+    let lines = lines(&buf);
+    assert_eq!(lines.len(), 7);
+    assert_eq!(
+        lines[3],
+        serde_json::json!({"summary": {
+            "CRIT": 0, "ERRO": 0, "WARN": 1, "INFO": 2, "DEBG": 0, "TRCE": 0,
+        }}),
+    );
+    assert_eq!(lines[4]["msg"], "four");
+    assert_eq!(lines[5]["summary"]["ERRO"], 1);
+    assert_eq!(lines[5]["summary"]["INFO"], 0);
+    assert_eq!(lines[6]["msg"], "five");
+}
+
+#[test]
+fn level_summary_is_sealed() {
+    let clock = TestClock::new();
+    let now = clock.clone();
+    let drain = Json::new(vec![])
+        .add_default_keys()
+        .with_clock(move || now.now())
+        .with_level_summary(Duration::from_secs(1))
+        .with_sealed_trailer(slog_json::HashAlgo::Fnv1a64)
+        .build();
+    let drain = Arc::new(Mutex::new(drain));
+    let log = Logger::root(drain.clone().fuse(), o!());
+    info!(log, "one");
+    clock.advance(Duration::from_secs(1));
+    info!(log, "two");
+    drop(log);
+
+    let drain = Arc::try_unwrap(drain).ok().unwrap().into_inner().unwrap();
+    let out = drain.finish().unwrap();
+    let lines: Vec<serde_json::Value> =
+        serde_json::Deserializer::from_slice(&out)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[1]["summary"]["INFO"], 1);
+    assert_eq!(lines[3]["_trailer"]["count"], 3);
+}
+
+/// Limits letting through `info` records per second, and all others
+fn info_limit(info: u32) -> [u32; 6] {
+    [u32::MAX, u32::MAX, u32::MAX, info, u32::MAX, u32::MAX]
+}
+
+#[test]
+fn burst_is_throttled_then_summarized() {
+    let clock = TestClock::new();
+    let buf = SharedBuffer::new();
+    let now = clock.clone();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .with_clock(move || now.now())
+        .rate_limit_per_level(info_limit(2))
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+
+    for n in 0..5 {
+        info!(log, "burst"; "n" => n);
+    }
+    clock.advance(Duration::from_secs(1));
+    info!(log, "after");
+
+    let lines = lines(&buf);
+    let msgs: Vec<_> = lines.iter().map(|l| l["msg"].clone()).collect();
+    assert_eq!(
+        msgs,
+        [
+            "burst",
+            "burst",
+            "records dropped by rate limiting",
+            "after"
+        ]
+    );
+    assert_eq!(lines[1]["n"], 1);
+    assert_eq!(lines[2]["level"], "INFO");
+    assert_eq!(lines[2]["dropped"], 3);
+}
+
+#[test]
+fn summary_is_written_when_everything_is_throttled() {
+    let clock = TestClock::new();
+    let now = clock.clone();
+    let drain = Json::new(vec![])
+        .add_default_keys()
+        .with_clock(move || now.now())
+        .rate_limit_per_level(info_limit(0))
+        .build();
+    let drain = Arc::new(Mutex::new(drain));
+    let log = Logger::root(drain.clone().fuse(), o!());
+
+    for _ in 0..3 {
+        info!(log, "throttled");
+    }
+    clock.advance(Duration::from_secs(1));
+    info!(log, "throttled");
+    info!(log, "throttled");
+    info!(log, "throttled");
+    drop(log);
+
+    let drain = Arc::try_unwrap(drain).ok().unwrap().into_inner().unwrap();
+    let out = String::from_utf8(drain.finish().unwrap()).unwrap();
+    let lines: Vec<serde_json::Value> = out
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    // Once due, then when finishing
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["msg"], "records dropped by rate limiting");
+    assert_eq!(lines[0]["dropped"], 4);
+    assert_eq!(lines[1]["dropped"], 2);
+}
@@ -0,0 +1,342 @@
+//! Timestamps, read from the clock of `JsonBuilder::with_clock`
+use slog::{info, o, Drain, Logger};
+use slog_json::{
+    EpochPrecision, Json, JsonBuilder, SharedBuffer, TimestampFormat,
+    TimestampPreset,
+};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 2023-11-14T22:13:20.123456789Z
+fn fixed() -> SystemTime {
+    UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789)
+}
+
+fn records(
+    build: impl FnOnce(JsonBuilder<SharedBuffer>) -> JsonBuilder<SharedBuffer>,
+) -> Vec<serde_json::Value> {
+    let buf = SharedBuffer::new();
+    let drain = build(Json::new(buf.clone()).with_clock(fixed)).build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "hi");
+    buf.to_string_lossy()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn rfc3339_by_default() {
+    let lines = records(|b| b.add_default_keys());
+    assert_eq!(lines[0]["ts"], "2023-11-14T22:13:20.123456789Z");
+}
+
+#[test]
+fn iso8601_basic() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .set_timestamp_format(TimestampFormat::Iso8601Basic)
+        .with_clock(fixed)
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "hi");
+    assert_eq!(
+        buf.to_string_lossy(),
+        "{\"msg\":\"hi\",\"level\":\"INFO\",\"ts\":\"20231114T221320Z\"}\n"
+    );
+}
+
+#[test]
+fn cached_timestamps_are_truncated() {
+    let lines = records(|b| {
+        b.add_default_keys()
+            .cache_timestamps(Duration::from_secs(1))
+    });
+    assert_eq!(lines[0]["ts"], "2023-11-14T22:13:20Z");
+}
+
+#[test]
+fn epoch_timestamps() {
+    let lines =
+        records(|b| b.add_default_keys().set_ts_epoch(EpochPrecision::Millis));
+    assert_eq!(lines[0]["ts"], 1_700_000_000_123u64);
+
+    let origin = UNIX_EPOCH + Duration::from_secs(1_699_999_000);
+    let lines = records(|b| {
+        b.add_default_keys()
+            .set_ts_epoch(EpochPrecision::Seconds)
+            .with_epoch_origin(origin)
+    });
+    assert_eq!(lines[0]["ts"], 1000);
+}
+
+#[test]
+fn kibana_timestamps() {
+    let lines = records(|b| b.add_default_keys().kibana_timestamps());
+    assert_eq!(lines[0]["@timestamp"], "2023-11-14T22:13:20.123456789Z");
+    assert_eq!(lines[0]["timestamp_ms"], 1_700_000_000_123u64);
+    assert!(lines[0].get("ts").is_none());
+}
+
+#[test]
+fn ecs_timestamps() {
+    let lines = records(|b| b.add_default_keys().ecs_timestamps());
+    assert_eq!(lines[0]["@timestamp"], "2023-11-14T22:13:20.123456789Z");
+    assert_eq!(lines[0]["event.created"], "2023-11-14T22:13:20.123456789Z");
+}
+
+#[test]
+fn delta_timestamps() {
+    let now = Arc::new(Mutex::new(fixed()));
+    let clock = now.clone();
+    let buf = SharedBuffer::new();
+    // The base is read when building, from the clock set after
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .delta_timestamps(true)
+        .with_clock(move || *clock.lock().unwrap())
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    *now.lock().unwrap() += Duration::from_millis(1500);
+    info!(log, "hi");
+
+    let out = buf.to_string_lossy();
+    let lines: Vec<serde_json::Value> = out
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines[0]["base_ts"], "2023-11-14T22:13:20.123Z");
+    assert_eq!(lines[0]["base_ts_ms"], 1_700_000_000_123u64);
+    assert_eq!(lines[1]["dt_ms"], 1500);
+}
+
+#[test]
+fn rfc3339_seconds_has_no_fraction() {
+    let lines = records(|b| {
+        b.add_default_keys()
+            .set_timestamp_format(TimestampFormat::Rfc3339Seconds)
+    });
+    assert_eq!(lines[0]["ts"], "2023-11-14T22:13:20Z");
+
+    // From the system clock, which has a fractional part
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .set_timestamp_format(TimestampFormat::Rfc3339Seconds)
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "hi");
+    let line: serde_json::Value =
+        serde_json::from_str(&buf.to_string_lossy()).unwrap();
+    let ts = line["ts"].as_str().unwrap();
+    assert_eq!(ts.len(), "2023-11-14T22:13:20Z".len(), "{}", ts);
+    assert!(!ts.contains('.'), "{}", ts);
+    assert!(ts.ends_with('Z'), "{}", ts);
+}
+
+#[test]
+fn kibana_timestamps_are_from_one_clock_read() {
+    // Every read of the clock is a millisecond later than the previous one
+    let reads = Arc::new(Mutex::new(0u32));
+    let clock = reads.clone();
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .kibana_timestamps()
+        .with_clock(move || {
+            let mut reads = clock.lock().unwrap();
+            *reads += 1;
+            fixed() + Duration::from_millis(u64::from(*reads))
+        })
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    for _ in 0..3 {
+        info!(log, "hi");
+    }
+
+    assert!(*reads.lock().unwrap() >= 3);
+    for line in buf.to_string_lossy().lines() {
+        let line: serde_json::Value = serde_json::from_str(line).unwrap();
+        let ts = line["@timestamp"].as_str().unwrap();
+        let ms = line["timestamp_ms"].as_u64().unwrap();
+        assert_eq!(&ts[..20], "2023-11-14T22:13:20.");
+        assert_eq!(ts[20..23].parse::<u64>().unwrap(), ms % 1000);
+        assert_eq!(ms / 1000, 1_700_000_000);
+    }
+}
+
+#[test]
+fn deltas_reconstruct_absolute_times() {
+    let now = Arc::new(Mutex::new(fixed()));
+    let clock = now.clone();
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .delta_timestamps(true)
+        .with_clock(move || *clock.lock().unwrap())
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    let offsets_ms = [250i64, 1500, 1499, -300];
+    for &offset in &offsets_ms {
+        *now.lock().unwrap() = if offset < 0 {
+            fixed() - Duration::from_millis(offset.unsigned_abs())
+        } else {
+            fixed() + Duration::from_millis(offset as u64)
+        };
+        info!(log, "hi");
+    }
+
+    let out = buf.to_string_lossy();
+    let lines: Vec<serde_json::Value> = out
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let base = lines[0]["base_ts_ms"].as_i64().unwrap();
+    let absolute: Vec<i64> = lines[1..]
+        .iter()
+        .map(|line| base + line["dt_ms"].as_i64().unwrap())
+        .collect();
+    let expected: Vec<i64> = offsets_ms
+        .iter()
+        .map(|offset| 1_700_000_000_123 + offset)
+        .collect();
+    assert_eq!(absolute, expected);
+}
+
+#[test]
+fn monotonic_ts_is_strictly_increasing() {
+    // The wall clock stands still, the monotonic one does not
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .add_monotonic_ts("mono_ns")
+        .with_clock(fixed)
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    for _ in 0..1000 {
+        info!(log, "hi");
+    }
+
+    let values: Vec<u64> = buf
+        .to_string_lossy()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .map(|line| line["mono_ns"].as_u64().unwrap())
+        .collect();
+    assert_eq!(values.len(), 1000);
+    assert!(values.windows(2).all(|w| w[0] < w[1]), "{:?}", values);
+}
+
+#[test]
+fn ecs_event_created_is_read_after_the_values() {
+    let now = Arc::new(Mutex::new(fixed()));
+    let clock = now.clone();
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .ecs_timestamps()
+        .with_clock(move || *clock.lock().unwrap())
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    // A value taking 250ms to compute
+    let slow = slog::FnValue(move |_| {
+        *now.lock().unwrap() += Duration::from_millis(250);
+        1
+    });
+    info!(log, "hi"; "slow" => slow);
+
+    let line: serde_json::Value =
+        serde_json::from_str(&buf.to_string_lossy()).unwrap();
+    let parse = |key: &str| {
+        time::OffsetDateTime::parse(
+            line[key].as_str().unwrap(),
+            &time::format_description::well_known::Rfc3339,
+        )
+        .unwrap()
+    };
+    assert_eq!(
+        parse("event.created") - parse("@timestamp"),
+        time::Duration::milliseconds(250)
+    );
+}
+
+#[test]
+fn timestamp_presets() {
+    let parse = |ts: &serde_json::Value| {
+        let ts = time::OffsetDateTime::parse(
+            ts.as_str().unwrap(),
+            &time::format_description::well_known::Rfc3339,
+        )
+        .unwrap();
+        SystemTime::from(ts)
+    };
+    let preset =
+        |preset| records(|b| b.add_default_keys().timestamp_preset(preset));
+    let millis = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+
+    let lines = preset(TimestampPreset::Elasticsearch);
+    assert_eq!(lines[0]["@timestamp"], "2023-11-14T22:13:20.123Z");
+    assert_eq!(parse(&lines[0]["@timestamp"]), millis);
+
+    let lines = preset(TimestampPreset::Splunk);
+    assert_eq!(lines[0]["time"].as_f64(), Some(1_700_000_000.123));
+
+    let lines = preset(TimestampPreset::Datadog);
+    assert_eq!(lines[0]["timestamp"], "2023-11-14T22:13:20.123Z");
+    assert_eq!(parse(&lines[0]["timestamp"]), millis);
+
+    let lines = preset(TimestampPreset::Loki);
+    assert_eq!(lines[0]["ts"], "2023-11-14T22:13:20.123456789Z");
+    assert_eq!(parse(&lines[0]["ts"]), fixed());
+
+    for preset in &[
+        TimestampPreset::Elasticsearch,
+        TimestampPreset::Splunk,
+        TimestampPreset::Datadog,
+    ] {
+        let lines = records(|b| b.add_default_keys().timestamp_preset(*preset));
+        assert!(lines[0].get("ts").is_none(), "{:?}", preset);
+    }
+}
+
+#[test]
+fn cached_timestamps_within_a_second_are_shared() {
+    let now = Arc::new(Mutex::new(fixed()));
+    let clock = now.clone();
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_default_keys()
+        .set_timestamp_format(TimestampFormat::Rfc3339Millis)
+        .cache_timestamps(Duration::from_secs(1))
+        .with_clock(move || *clock.lock().unwrap())
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+
+    // 20.123s, from two threads, then 20.999s and 21.000s
+    info!(log, "a");
+    let other = log.clone();
+    std::thread::spawn(move || info!(other, "b"))
+        .join()
+        .unwrap();
+    *now.lock().unwrap() =
+        UNIX_EPOCH + Duration::from_millis(1_700_000_000_999);
+    info!(log, "c");
+    *now.lock().unwrap() = UNIX_EPOCH + Duration::from_secs(1_700_000_001);
+    info!(log, "d");
+
+    let out = buf.to_string_lossy();
+    let ts: Vec<serde_json::Value> = out
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .map(|line| line["ts"].clone())
+        .collect();
+    assert_eq!(
+        ts,
+        [
+            "2023-11-14T22:13:20.000Z",
+            "2023-11-14T22:13:20.000Z",
+            "2023-11-14T22:13:20.000Z",
+            "2023-11-14T22:13:21.000Z",
+        ]
+    );
+}
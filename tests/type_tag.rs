@@ -0,0 +1,27 @@
+//! `JsonBuilder::with_type_tag_byte`
+use slog::{info, o, Drain, Logger};
+use slog_json::{Json, SharedBuffer};
+use std::sync::Mutex;
+
+#[test]
+fn tag_precedes_every_record() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone()).with_type_tag_byte(b'L').build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "a"; "n" => 1);
+    info!(log, "b"; "n" => 2);
+    assert_eq!(&buf.bytes()[..], b"L{\"n\":1}\nL{\"n\":2}\n");
+}
+
+#[test]
+fn tag_without_newlines() {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .with_type_tag_byte(0x1e)
+        .set_newlines(false)
+        .build();
+    let log = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(log, "a"; "n" => 1);
+    info!(log, "b"; "n" => 2);
+    assert_eq!(&buf.bytes()[..], b"\x1e{\"n\":1}\x1e{\"n\":2}");
+}
@@ -0,0 +1,46 @@
+//! `JsonBuilder::normalize_unicode`
+#![cfg(feature = "unicode-normalization")]
+
+use slog::{info, o, Drain, Logger};
+use slog_json::{Json, SharedBuffer, UnicodeForm};
+use std::sync::Mutex;
+
+fn output(form: UnicodeForm, log: impl FnOnce(&Logger)) -> String {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone()).normalize_unicode(form).build();
+    log(&Logger::root(
+        Mutex::new(drain).fuse(),
+        o!("city" => "Mal\u{0065}\u{0301}"),
+    ));
+    buf.to_string_lossy()
+}
+
+#[test]
+fn nfc_composes_values() {
+    let out = output(UnicodeForm::Nfc, |log| {
+        info!(log, "cafe\u{301}"; "name" => "Jose\u{301}", "arg" => format_args!("{}\u{301}", "e"));
+    });
+    assert_eq!(
+        out,
+        "{\"city\":\"Mal\u{e9}\",\"arg\":\"\u{e9}\",\"name\":\"Jos\u{e9}\"}\n"
+    );
+}
+
+#[test]
+fn nfd_decomposes_values() {
+    let out = output(UnicodeForm::Nfd, |log| {
+        info!(log, "hi"; "name" => "Jos\u{e9}");
+    });
+    assert_eq!(
+        out,
+        "{\"city\":\"Mal\u{65}\u{301}\",\"name\":\"Jose\u{301}\"}\n"
+    );
+}
+
+#[test]
+fn nfkc_folds_compatibility_characters() {
+    let out = output(UnicodeForm::Nfkc, |log| {
+        info!(log, "hi"; "file" => "\u{fb01}le");
+    });
+    assert_eq!(out, "{\"city\":\"Mal\u{e9}\",\"file\":\"file\"}\n");
+}
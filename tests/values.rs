@@ -0,0 +1,128 @@
+//! How values are emitted
+use slog::{info, o, Drain, Logger};
+use slog_json::{Json, JsonBuilder, SharedBuffer};
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+fn output(
+    build: impl FnOnce(JsonBuilder<SharedBuffer>) -> JsonBuilder<SharedBuffer>,
+    log: impl FnOnce(&Logger),
+) -> String {
+    let buf = SharedBuffer::new();
+    let drain = build(Json::new(buf.clone())).build();
+    log(&Logger::root(Mutex::new(drain).fuse(), o!()));
+    buf.to_string_lossy()
+}
+
+fn keys(keys: &[&str]) -> HashSet<String> {
+    keys.iter().map(|&k| k.to_owned()).collect()
+}
+
+#[test]
+fn numbers_outside_allowlist_are_strings() {
+    let out = output(
+        |b| b.numeric_allowlist(keys(&["status"])),
+        |log| info!(log, "hi"; "status" => 200, "latency" => 1.5, "size" => 42u64),
+    );
+    assert_eq!(
+        out,
+        "{\"size\":\"42\",\"latency\":\"1.5\",\"status\":200}\n"
+    );
+}
+
+#[test]
+fn bools_as_ints_for_given_keys() {
+    let out = output(
+        |b| b.bool_as_int_for(keys(&["active", "admin"])),
+        |log| info!(log, "hi"; "active" => true, "admin" => false, "cached" => true),
+    );
+    assert_eq!(out, "{\"cached\":true,\"admin\":0,\"active\":1}\n");
+}
+
+#[test]
+fn traceparent_is_split() {
+    let out = output(
+        |b| b.parse_traceparent("traceparent"),
+        |log| {
+            info!(log, "hi";
+                "traceparent" => "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+        },
+    );
+    let line: serde_json::Value = serde_json::from_str(&out).unwrap();
+    assert_eq!(line["trace_id"], "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert_eq!(line["span_id"], "00f067aa0ba902b7");
+    assert_eq!(line["trace_flags"], "01");
+    assert!(line.get("traceparent").is_none());
+}
+
+#[test]
+fn malformed_traceparent_is_kept() {
+    let out = output(
+        |b| b.parse_traceparent("traceparent"),
+        |log| info!(log, "hi"; "traceparent" => "00-nothex-01"),
+    );
+    assert_eq!(out, "{\"traceparent\":\"00-nothex-01\"}\n");
+}
+
+#[test]
+fn forward_slashes_are_escaped() {
+    let out = output(
+        |b| b.escape_forward_slashes(true),
+        |log| info!(log, "hi"; "html" => "</script>"),
+    );
+    assert_eq!(out, "{\"html\":\"<\\/script>\"}\n");
+
+    let out = output(|b| b, |log| info!(log, "hi"; "html" => "</script>"));
+    assert_eq!(out, "{\"html\":\"</script>\"}\n");
+}
+
+#[test]
+fn empty_strings_as_null() {
+    let out = output(
+        |b| b.empty_string_as_null(true),
+        |log| info!(log, "hi"; "name" => "", "shown" => %"", "city" => "Oslo"),
+    );
+    assert_eq!(out, "{\"city\":\"Oslo\",\"shown\":null,\"name\":null}\n");
+}
+
+#[test]
+fn control_chars_are_stripped() {
+    let out = output(
+        |b| b.strip_control_chars(true),
+        |log| info!(log, "hi"; "name" => "a\0b\tc\nd", "shown" => %"e\u{7}f"),
+    );
+    assert_eq!(out, "{\"shown\":\"ef\",\"name\":\"abcd\"}\n");
+
+    let out = output(
+        |b| b.strip_control_chars(true).keep_tabs_and_newlines(true),
+        |log| info!(log, "hi"; "name" => "a\0b\tc\nd"),
+    );
+    assert_eq!(out, "{\"name\":\"ab\\tc\\nd\"}\n");
+}
+
+#[test]
+fn all_values_as_strings() {
+    let out = output(
+        |b| b.all_string_values(true),
+        |log| info!(log, "hi"; "n" => 42, "ok" => true, "ratio" => 0.5, "name" => "ann"),
+    );
+    assert_eq!(
+        out,
+        "{\"name\":\"ann\",\"ratio\":\"0.5\",\"ok\":\"true\",\"n\":\"42\"}\n"
+    );
+}
+
+#[test]
+fn number_strings_as_numbers() {
+    let out = output(
+        |b| b.auto_number_strings(keys(&["count", "id", "ratio"])),
+        |log| {
+            info!(log, "hi";
+                "count" => "42", "id" => "007", "ratio" => "0.5", "other" => "1")
+        },
+    );
+    assert_eq!(
+        out,
+        "{\"other\":\"1\",\"ratio\":0.5,\"id\":\"007\",\"count\":42}\n"
+    );
+}
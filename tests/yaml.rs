@@ -0,0 +1,51 @@
+//! Output of `JsonBuilder::build_yaml`
+#![cfg(feature = "yaml")]
+
+use serde::Deserialize;
+use slog::{info, o, Drain, Logger};
+use slog_json::{Json, SharedBuffer};
+use std::sync::Mutex;
+
+/// Records logged to a `Yaml` drain, read back as JSON values
+fn round_trip(log: impl FnOnce(&Logger)) -> (String, Vec<serde_json::Value>) {
+    let buf = SharedBuffer::new();
+    let drain = Json::new(buf.clone())
+        .add_key_value(o!("app" => "test"))
+        .build_yaml();
+    log(&Logger::root(Mutex::new(drain).fuse(), o!()));
+
+    let out = buf.to_string_lossy();
+    let docs = serde_yaml::Deserializer::from_str(&out)
+        .map(|doc| serde_json::Value::deserialize(doc).unwrap())
+        .collect();
+    (out, docs)
+}
+
+#[test]
+fn documents_read_back() {
+    let (out, docs) = round_trip(|log| {
+        info!(log, "one"; "n" => 1, "ok" => true);
+        info!(log, "two"; "text" => "multi\nline: \"quoted\"");
+    });
+    assert!(out.starts_with("---\n"));
+    assert_eq!(
+        docs,
+        vec![
+            serde_json::json!({"app": "test", "ok": true, "n": 1}),
+            serde_json::json!({"app": "test", "text": "multi\nline: \"quoted\""}),
+        ]
+    );
+}
+
+#[test]
+fn ambiguous_strings_stay_strings() {
+    let (_, docs) = round_trip(|log| {
+        info!(log, "one"; "yes" => "true", "num" => "12", "null" => "~", "empty" => "");
+    });
+    assert_eq!(
+        docs,
+        vec![serde_json::json!({
+            "app": "test", "empty": "", "null": "~", "num": "12", "yes": "true",
+        })]
+    );
+}